@@ -0,0 +1,45 @@
+// Async router example
+//
+// Run with `cargo run --example async_node --features async`. It will:
+// - start a Babel node driven by Tokio
+// - advertise 10.0.2.0/24
+// - log events as they arrive, without polling
+
+use std::io;
+use std::net::Ipv4Addr;
+
+use babel_rs::node::{AdvertisedPrefix, BabelConfig, BabelNode};
+use babel_rs::{AsyncBabelNode, Event};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> io::Result<()> {
+    let router_id: [u8; 8] = [0x02, 0, 0, 0, 0, 0, 0, 0x02];
+
+    let config = BabelConfig::new().with_advertised_prefix(AdvertisedPrefix {
+        ae: 1,    // IPv4
+        plen: 24, // 10.0.2.0/24
+        prefix: vec![10, 0, 2],
+        metric: 256,
+    });
+    let node = BabelNode::new_v4_multicast(Ipv4Addr::UNSPECIFIED, 0, router_id, config)?;
+    let (mut node, mut events) = AsyncBabelNode::new(node)?;
+
+    let log_events = async {
+        while let Some(ev) = events.recv().await {
+            match ev {
+                Event::NeighborUp(addr, _) => println!("[async] Neighbor up: {addr}"),
+                Event::NeighborDown(addr) => println!("[async] Neighbor down: {addr}"),
+                Event::BestRouteChanged(key, route) => {
+                    println!("[async] Best route for {key}: {}", route.summary())
+                }
+                Event::RouteRetracted(key) => println!("[async] Route retracted: {key}"),
+                other => println!("[async] {other:?}"),
+            }
+        }
+    };
+
+    tokio::select! {
+        result = node.run() => result,
+        _ = log_events => Ok(()),
+    }
+}
@@ -0,0 +1,15 @@
+// fuzz/fuzz_targets/parse.rs
+//! Feed arbitrary datagrams to the parsers; they must return an error on
+//! malformed input, never panic.
+
+#![no_main]
+
+use babel_rs::{Packet, Tlv};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Packet::from_bytes(data);
+    let _ = Packet::from_bytes_with_remainder(data);
+    let _ = Tlv::parse_all(data);
+    let _ = Tlv::parse_all_strict(data);
+});
@@ -0,0 +1,25 @@
+// fuzz/fuzz_targets/roundtrip.rs
+//! Whatever parses must serialize back to bytes that parse to the same
+//! TLVs.
+
+#![no_main]
+
+use babel_rs::{Packet, Tlv};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tlvs) = Tlv::parse_all(data) {
+        let mut bytes = Vec::new();
+        for tlv in &tlvs {
+            bytes.extend(tlv.to_bytes().expect("parsed TLV serializes"));
+        }
+        let reparsed = Tlv::parse_all(&bytes).expect("serialized TLVs parse");
+        assert_eq!(reparsed, tlvs);
+    }
+
+    if let Ok(packet) = Packet::from_bytes(data) {
+        let bytes = packet.to_bytes().expect("parsed packet serializes");
+        let reparsed = Packet::from_bytes(&bytes).expect("serialized packet parses");
+        assert_eq!(reparsed.tlvs(), packet.tlvs());
+    }
+});
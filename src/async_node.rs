@@ -0,0 +1,145 @@
+// src/async_node.rs
+//! Tokio driver for [`BabelNode`] (feature `async`).
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::event::Event;
+use crate::node::BabelNode;
+
+/// Timers are checked this many times per shortest Hello/IHU/Update
+/// interval, which bounds how late one of them can fire.
+const TIMER_CHECKS_PER_INTERVAL: u32 = 8;
+
+/// Lower bound on the timer check period.
+const MIN_TIMER_PERIOD: Duration = Duration::from_millis(10);
+
+/// A [`BabelNode`] driven by Tokio instead of `poll()` in a loop.
+///
+/// Packets are handled as soon as the socket is readable, and the node's
+/// timers are checked on a [`tokio::time::interval`]. The protocol logic
+/// is the wrapped node's, fed through [`BabelNode::ingest`] and
+/// [`BabelNode::tick`].
+///
+/// The node is not `Send`, so run it on the task that created it, e.g.
+/// with a current-thread runtime or a `LocalSet`.
+pub struct AsyncBabelNode {
+    node: BabelNode,
+    socket: UdpSocket,
+    timer_period: Duration,
+}
+
+impl AsyncBabelNode {
+    /// Take over `node` and its socket; events are delivered on the
+    /// returned channel as they happen.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(mut node: BabelNode) -> io::Result<(Self, UnboundedReceiver<Event>)> {
+        let socket = UdpSocket::from_std(node.socket().try_clone()?)?;
+        let timer_period =
+            (node.shortest_interval() / TIMER_CHECKS_PER_INTERVAL).max(MIN_TIMER_PERIOD);
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        node.on_event(Box::new(move |ev| {
+            // The application dropping the receiver just means it stopped
+            // listening.
+            let _ = tx.send(ev.clone());
+        }));
+
+        let driver = AsyncBabelNode {
+            node,
+            socket,
+            timer_period,
+        };
+        Ok((driver, rx))
+    }
+
+    /// The wrapped node, e.g. to inspect neighbors and routes.
+    pub fn node(&self) -> &BabelNode {
+        &self.node
+    }
+
+    /// Mutable access to the wrapped node, e.g. to advertise prefixes.
+    pub fn node_mut(&mut self) -> &mut BabelNode {
+        &mut self.node
+    }
+
+    /// Run the node until a receive error occurs.
+    ///
+    /// Errors sending a packet are logged and don't stop the node, as with
+    /// [`BabelNode::poll`].
+    pub async fn run(&mut self) -> io::Result<()> {
+        let mut timer = time::interval(self.timer_period);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut buf = [0u8; 1500];
+
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {}
+                received = self.socket.recv_from(&mut buf) => {
+                    let (n, src) = received?;
+                    if let Err(e) = self.node.ingest(&buf[..n], src) {
+                        eprintln!("[AsyncBabelNode] dropping packet from {src}: {e}");
+                    }
+                }
+            }
+
+            // Events already went out through the channel.
+            self.node.drain_events();
+
+            // Also flushes Acks and Updates queued by what we just received.
+            for pkt in self.node.tick(Instant::now()) {
+                if let Err(e) = self.socket.send_to(&pkt.bytes, pkt.dest).await {
+                    eprintln!("[AsyncBabelNode] error sending to {}: {e}", pkt.dest);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::BabelConfig;
+    use std::net::Ipv4Addr;
+
+    fn node(router: u8, peer: std::net::SocketAddr, socket: std::net::UdpSocket) -> BabelNode {
+        let config = BabelConfig::new()
+            .hello_interval_ms(100)
+            .multicast(false)
+            .add_unicast_peer(peer);
+        BabelNode::with_socket(socket, 1, [router; 8], config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn nodes_discover_each_other() {
+        let sock_a = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let sock_b = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr_a = sock_a.local_addr().unwrap();
+        let addr_b = sock_b.local_addr().unwrap();
+
+        let (mut a, _) = AsyncBabelNode::new(node(0xa, addr_b, sock_a)).unwrap();
+        let (mut b, mut events) = AsyncBabelNode::new(node(0xb, addr_a, sock_b)).unwrap();
+
+        let neighbor_up = async {
+            while let Some(ev) = events.recv().await {
+                if let Event::NeighborUp(addr, _) = ev {
+                    return addr;
+                }
+            }
+            panic!("event channel closed");
+        };
+        let up = tokio::select! {
+            addr = neighbor_up => addr,
+            r = a.run() => panic!("node a stopped: {r:?}"),
+            r = b.run() => panic!("node b stopped: {r:?}"),
+            _ = time::sleep(Duration::from_secs(2)) => panic!("no NeighborUp"),
+        };
+        assert_eq!(up, addr_a);
+        assert!(b.node().neighbors.get(&addr_a, 1).is_some());
+    }
+}
@@ -19,4 +19,25 @@ pub enum Event {
 
     /// The best route for a prefix changed.
     BestRouteChanged(RouteKey, Route),
+
+    /// The last route for a prefix was removed.
+    RouteRetracted(RouteKey),
+
+    /// A known neighbor became reachable (`true`) or unreachable (`false`)
+    /// according to its Hello history.
+    NeighborReachabilityChanged(SocketAddr, bool),
+}
+
+impl Event {
+    /// The prefix a route event is about; `None` for neighbor events.
+    pub fn route_key(&self) -> Option<&RouteKey> {
+        match self {
+            Event::RouteUpdated(key, _)
+            | Event::BestRouteChanged(key, _)
+            | Event::RouteRetracted(key) => Some(key),
+            Event::NeighborUp(..)
+            | Event::NeighborDown(_)
+            | Event::NeighborReachabilityChanged(..) => None,
+        }
+    }
 }
@@ -0,0 +1,405 @@
+// src/kernel.rs
+//! Installing selected routes into the Linux kernel FIB over rtnetlink
+//! (feature `linux-fib`).
+
+use std::io;
+use std::net::IpAddr;
+
+use netlink_packet_core::{
+    NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_REPLACE, NLM_F_REQUEST, NetlinkHeader,
+    NetlinkMessage, NetlinkPayload,
+};
+use netlink_packet_route::constants::{
+    AF_INET, AF_INET6, RT_SCOPE_UNIVERSE, RT_TABLE_COMPAT, RT_TABLE_MAIN, RTN_UNICAST, RTPROT_BABEL,
+};
+use netlink_packet_route::route::Nla;
+use netlink_packet_route::{RouteMessage, RtnlMessage};
+use netlink_sys::{Socket, SocketAddr, protocols::NETLINK_ROUTE};
+
+use crate::event::Event;
+use crate::node::BabelNode;
+use crate::routing::{Route, RouteKey};
+use crate::tlv::AddressFamily;
+
+/// errno the kernel answers a delete of a missing route with.
+const ESRCH: i32 = 3;
+
+/// Keeps the kernel's routing table in line with a node's best routes.
+///
+/// Routes are installed with protocol `babel` (42) in one table, the main
+/// table by default, one kernel route per prefix. Only learned routes are
+/// installed: our own prefixes are reachable locally already.
+pub struct KernelSync {
+    socket: Socket,
+    table: u32,
+    seq: u32,
+}
+
+impl KernelSync {
+    /// Open an rtnetlink socket managing routes in the main table.
+    pub fn new() -> io::Result<Self> {
+        Self::with_table(RT_TABLE_MAIN as u32)
+    }
+
+    /// Open an rtnetlink socket managing routes in routing table `table`.
+    pub fn with_table(table: u32) -> io::Result<Self> {
+        let mut socket = Socket::new(NETLINK_ROUTE)?;
+        socket.bind_auto()?;
+        socket.connect(&SocketAddr::new(0, 0))?;
+        Ok(KernelSync {
+            socket,
+            table,
+            seq: 0,
+        })
+    }
+
+    /// Bring the kernel in line with `node`'s current best routes, then
+    /// follow its route events for as long as the node lives.
+    ///
+    /// Errors after the initial reconciliation are logged, as the node's
+    /// event callbacks can't fail.
+    pub fn attach(mut self, node: &mut BabelNode) -> io::Result<()> {
+        let mut keys: Vec<&RouteKey> = Vec::new();
+        for r in node.routes() {
+            if !keys.contains(&&r.key) {
+                keys.push(&r.key);
+            }
+        }
+        let best: Vec<Route> = keys
+            .into_iter()
+            .filter_map(|k| node.best_route(k).cloned())
+            .collect();
+        self.reconcile(&best)?;
+
+        node.on_event(Box::new(move |ev| {
+            if let Err(e) = self.apply(ev) {
+                eprintln!("[KernelSync] error updating kernel route: {e}");
+            }
+        }));
+        Ok(())
+    }
+
+    /// Update the kernel for one node event.
+    ///
+    /// A new best route replaces the kernel route of its prefix (or removes
+    /// it, if the best route is now one of ours); a retracted prefix loses
+    /// its kernel route. Other events are ignored.
+    pub fn apply(&mut self, event: &Event) -> io::Result<()> {
+        match event {
+            Event::BestRouteChanged(_, route) if route.next_hop.is_some() => self.install(route),
+            Event::BestRouteChanged(key, _) | Event::RouteRetracted(key) => self.remove(key),
+            _ => Ok(()),
+        }
+    }
+
+    /// Install `route` as the kernel route for its prefix, replacing any
+    /// previous one.
+    pub fn install(&mut self, route: &Route) -> io::Result<()> {
+        let next_hop = route
+            .next_hop
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "route has no next hop"))?;
+        let mut msg = self.route_message(&route.key)?;
+
+        let same_family = next_hop.is_ipv4() == (msg.header.address_family == AF_INET as u8);
+        if same_family {
+            msg.nlas.push(Nla::Gateway(ip_bytes(next_hop)));
+        } else {
+            // E.g. an IPv4 prefix through an IPv6 next hop (RFC 9229).
+            let mut via = family_of(next_hop).to_ne_bytes().to_vec();
+            via.extend(ip_bytes(next_hop));
+            msg.nlas.push(Nla::Via(via));
+        }
+        msg.nlas.push(Nla::Oif(route.iface_index));
+
+        self.request(RtnlMessage::NewRoute(msg), NLM_F_CREATE | NLM_F_REPLACE)
+    }
+
+    /// Remove our kernel route for `key`, if there is one.
+    pub fn remove(&mut self, key: &RouteKey) -> io::Result<()> {
+        let msg = self.route_message(key)?;
+        match self.request(RtnlMessage::DelRoute(msg), 0) {
+            Err(e) if e.raw_os_error() == Some(ESRCH) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Make our kernel routes exactly `best`: install each of them, and
+    /// delete the routes we installed earlier (e.g. before a restart) for
+    /// prefixes not among them.
+    pub fn reconcile(&mut self, best: &[Route]) -> io::Result<()> {
+        let wanted: Vec<&Route> = best.iter().filter(|r| r.next_hop.is_some()).collect();
+
+        let wanted_ids: Vec<_> = wanted
+            .iter()
+            .filter_map(|r| self.route_message(&r.key).ok())
+            .map(|m| route_id(&m))
+            .collect();
+        for installed in self.dump_routes()? {
+            if !wanted_ids.contains(&route_id(&installed)) {
+                let mut msg = installed;
+                msg.nlas
+                    .retain(|nla| matches!(nla, Nla::Destination(_) | Nla::Table(_)));
+                match self.request(RtnlMessage::DelRoute(msg), 0) {
+                    Err(e) if e.raw_os_error() == Some(ESRCH) => {}
+                    result => result?,
+                }
+            }
+        }
+
+        for route in wanted {
+            self.install(route)?;
+        }
+        Ok(())
+    }
+
+    /// Our routes currently in the kernel: protocol `babel`, in our table.
+    pub fn dump_routes(&mut self) -> io::Result<Vec<RouteMessage>> {
+        let seq = self.send(RtnlMessage::GetRoute(RouteMessage::default()), NLM_F_DUMP)?;
+
+        let mut routes = Vec::new();
+        loop {
+            for msg in self.recv(seq)? {
+                match msg.payload {
+                    NetlinkPayload::Done(_) => return Ok(routes),
+                    NetlinkPayload::Error(e) => return Err(e.to_io()),
+                    NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(route))
+                        if route.header.protocol == RTPROT_BABEL
+                            && route_table(&route) == self.table =>
+                    {
+                        routes.push(route);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Skeleton of a message for our kernel route to `key`.
+    fn route_message(&self, key: &RouteKey) -> io::Result<RouteMessage> {
+        let (family, len) = match key.address_family() {
+            Some(AddressFamily::V4) => (AF_INET, 4),
+            Some(AddressFamily::V6) => (AF_INET6, 16),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no kernel route for {key}"),
+                ));
+            }
+        };
+        // A prefix from the wire may carry host bits, which the kernel
+        // rejects as an invalid destination.
+        let (addr, plen) = key
+            .to_ip_and_prefixlen()
+            .expect("key has an address family");
+        let masked = RouteKey::from_addr(addr, plen);
+        let mut dst = masked.prefix;
+        dst.resize(len, 0);
+
+        let mut msg = RouteMessage::default();
+        msg.header.address_family = family as u8;
+        msg.header.destination_prefix_length = masked.plen;
+        msg.header.table = u8::try_from(self.table).unwrap_or(RT_TABLE_COMPAT);
+        msg.header.protocol = RTPROT_BABEL;
+        msg.header.scope = RT_SCOPE_UNIVERSE;
+        msg.header.kind = RTN_UNICAST;
+        msg.nlas.push(Nla::Destination(dst));
+        msg.nlas.push(Nla::Table(self.table));
+        Ok(msg)
+    }
+
+    /// Send a request and wait for the kernel's acknowledgement.
+    fn request(&mut self, msg: RtnlMessage, flags: u16) -> io::Result<()> {
+        let seq = self.send(msg, flags | NLM_F_ACK)?;
+        loop {
+            for msg in self.recv(seq)? {
+                if let NetlinkPayload::Error(e) = msg.payload {
+                    return match e.code {
+                        None => Ok(()),
+                        Some(_) => Err(e.to_io()),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Send `msg` as a request with `flags`; returns its sequence number.
+    fn send(&mut self, msg: RtnlMessage, flags: u16) -> io::Result<u32> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut header = NetlinkHeader::default();
+        header.flags = NLM_F_REQUEST | flags;
+        header.sequence_number = self.seq;
+
+        let mut packet = NetlinkMessage::new(header, NetlinkPayload::from(msg));
+        packet.finalize();
+        let mut buf = vec![0; packet.buffer_len()];
+        packet.serialize(&mut buf);
+        self.socket.send(&buf, 0)?;
+        Ok(self.seq)
+    }
+
+    /// Receive one datagram and return the messages in it answering `seq`.
+    fn recv(&mut self, seq: u32) -> io::Result<Vec<NetlinkMessage<RtnlMessage>>> {
+        let (buf, _) = self.socket.recv_from_full()?;
+
+        let mut out = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let msg = NetlinkMessage::<RtnlMessage>::deserialize(&buf[offset..])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            let len = msg.header.length as usize;
+            if msg.header.sequence_number == seq {
+                out.push(msg);
+            }
+            if len == 0 {
+                break;
+            }
+            offset += len;
+        }
+        Ok(out)
+    }
+}
+
+/// Table of a kernel route, which only fits the header below 256.
+fn route_table(route: &RouteMessage) -> u32 {
+    route
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(route.header.table as u32)
+}
+
+/// What identifies a kernel route within a table: family, prefix length
+/// and destination.
+fn route_id(route: &RouteMessage) -> (u8, u8, Option<Vec<u8>>) {
+    let dst = route.nlas.iter().find_map(|nla| match nla {
+        Nla::Destination(d) => Some(d.clone()),
+        _ => None,
+    });
+    (
+        route.header.address_family,
+        route.header.destination_prefix_length,
+        dst,
+    )
+}
+
+fn family_of(addr: IpAddr) -> u16 {
+    match addr {
+        IpAddr::V4(_) => AF_INET,
+        IpAddr::V6(_) => AF_INET6,
+    }
+}
+
+fn ip_bytes(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netlink_packet_route::LinkMessage;
+    use netlink_packet_route::constants::IFF_UP;
+    use std::net::Ipv4Addr;
+    use std::time::Instant;
+
+    /// Move the calling thread into a new, empty network namespace.
+    /// Needs root; returns false if that's not possible.
+    fn enter_netns() -> bool {
+        // SAFETY: unshare only changes this thread's namespaces.
+        unsafe { libc::unshare(libc::CLONE_NEWNET) == 0 }
+    }
+
+    fn host_route(last_octet: u8) -> Route {
+        Route {
+            key: RouteKey {
+                ae: 1,
+                plen: 32,
+                prefix: vec![10, 1, 1, last_octet],
+            },
+            metric: 256,
+            advertised_metric: 0,
+            smoothed_metric: 256,
+            seqno: 1,
+            router_id: [1; 8],
+            next_hop: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            // Loopback, the only interface of a new namespace.
+            iface_index: 1,
+            last_updated: Instant::now(),
+            expires_at: Instant::now(),
+        }
+    }
+
+    fn installed(sync: &mut KernelSync) -> Vec<(Vec<u8>, Vec<u8>)> {
+        sync.dump_routes()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                let find = |want_gw: bool| {
+                    r.nlas
+                        .iter()
+                        .find_map(|nla| match nla {
+                            Nla::Destination(d) if !want_gw => Some(d.clone()),
+                            Nla::Gateway(g) if want_gw => Some(g.clone()),
+                            _ => None,
+                        })
+                        .unwrap()
+                };
+                (find(false), find(true))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn route_message_clears_host_bits() {
+        let sync = KernelSync::new().unwrap();
+        let key = RouteKey {
+            ae: 1,
+            plen: 20,
+            prefix: vec![10, 1, 0xff, 7],
+        };
+        let msg = sync.route_message(&key).unwrap();
+        assert_eq!(msg.header.destination_prefix_length, 20);
+        assert!(msg.nlas.contains(&Nla::Destination(vec![10, 1, 0xf0, 0])));
+    }
+
+    #[test]
+    fn routes_are_installed_in_the_kernel() {
+        if !enter_netns() {
+            eprintln!("skipping: can't create a network namespace (not root?)");
+            return;
+        }
+        let mut sync = KernelSync::new().unwrap();
+        let mut lo = LinkMessage::default();
+        lo.header.index = 1;
+        lo.header.flags = IFF_UP;
+        lo.header.change_mask = IFF_UP;
+        sync.request(RtnlMessage::SetLink(lo), 0).unwrap();
+
+        let route = host_route(1);
+        let want = vec![(vec![10, 1, 1, 1], vec![127, 0, 0, 1])];
+        sync.install(&route).unwrap();
+        assert_eq!(installed(&mut sync), want);
+        // Installing again replaces the route.
+        sync.install(&route).unwrap();
+        assert_eq!(installed(&mut sync), want);
+
+        // Left over from a previous run.
+        sync.install(&host_route(2)).unwrap();
+        sync.reconcile(std::slice::from_ref(&route)).unwrap();
+        assert_eq!(installed(&mut sync), want);
+
+        sync.apply(&Event::RouteRetracted(route.key.clone()))
+            .unwrap();
+        assert!(installed(&mut sync).is_empty());
+        sync.remove(&route.key).unwrap();
+
+        sync.apply(&Event::BestRouteChanged(route.key.clone(), route))
+            .unwrap();
+        assert_eq!(installed(&mut sync), want);
+    }
+}
@@ -4,23 +4,41 @@
 //! - [`BabelNode`]: synchronous Babel node that sends Hellos, parses TLVs,
 //!   tracks neighbors and a routing table.
 //! - [`BabelConfig`]: configuration for [`BabelNode`].
+//! - `AsyncBabelNode` (feature `async`): drives a [`BabelNode`] on Tokio.
+//! - `KernelSync` (feature `linux-fib`): installs a [`BabelNode`]'s best
+//!   routes into the Linux kernel.
 //!
 //! Lower-level modules are also exposed for advanced usage:
 //! - [`tlv`]: TLV parsing/encoding
 //! - [`packet`]: packet building, header + TLVs, multicast helpers
 //! - [`neighbor`]: neighbor tracking and reachability
 //! - [`routing`]: routing table and route selection
+//! - [`metrics`]: node health counters
+//! - [`trace`]: pcap capture of a node's traffic
 
+#[cfg(feature = "async")]
+pub mod async_node;
 pub mod event;
+#[cfg(all(feature = "linux-fib", target_os = "linux"))]
+pub mod kernel;
+pub mod metrics;
 pub mod neighbor;
 pub mod node;
 pub mod packet;
 pub mod routing;
 pub mod tlv;
+pub mod trace;
 
+#[cfg(feature = "async")]
+pub use crate::async_node::AsyncBabelNode;
 pub use crate::event::Event;
-pub use crate::neighbor::{Neighbor, NeighborTable};
-pub use crate::node::{BabelConfig, BabelNode};
+#[cfg(all(feature = "linux-fib", target_os = "linux"))]
+pub use crate::kernel::KernelSync;
+pub use crate::metrics::NodeMetrics;
+pub use crate::neighbor::{LinkCostStrategy, Neighbor, NeighborSnapshot, NeighborTable};
+pub use crate::node::{BabelConfig, BabelNode, OutgoingPacket};
 pub use crate::packet::{BABEL_PORT, MULTICAST_V4_ADDR, MULTICAST_V6_ADDR, Packet};
-pub use crate::routing::{Route, RouteKey, RoutingTable};
-pub use crate::tlv::{SubTlv, Tlv};
+pub use crate::routing::{
+    InstallOutcome, MetricComputer, Route, RouteChange, RouteKey, RoutingTable, SourceTable,
+};
+pub use crate::tlv::{AddressFamily, SubTlv, Tlv, TlvError, TlvRef};
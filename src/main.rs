@@ -5,19 +5,12 @@
 // - advertise 10.0.1.0/24
 // - log neighbor and route events
 
-mod event;
-mod neighbor;
-mod node;
-mod packet;
-mod routing;
-mod tlv;
-
 use std::io;
 use std::net::Ipv4Addr;
 use std::time::Duration;
 
-use event::Event;
-use node::{AdvertisedPrefix, BabelConfig, BabelNode};
+use babel_rs::Event;
+use babel_rs::node::{AdvertisedPrefix, BabelConfig, BabelNode};
 
 fn main() -> io::Result<()> {
     // Unique router-id for router 1
@@ -59,21 +52,30 @@ fn main() -> io::Result<()> {
                 Event::NeighborDown(addr) => {
                     println!("[router1] Neighbor down: {addr}");
                 }
+                Event::NeighborReachabilityChanged(addr, reachable) => {
+                    let state = if reachable {
+                        "reachable"
+                    } else {
+                        "unreachable"
+                    };
+                    println!("[router1] Neighbor {addr} is now {state}");
+                }
                 Event::RouteUpdated(key, route) => {
                     println!(
-                        "[router1] Route updated: ae={} plen={} prefix={:?} via {:?} metric={} seqno={}",
-                        key.ae, key.plen, key.prefix, route.next_hop, route.metric, route.seqno
+                        "[router1] Route updated: {} via {:?} metric={} seqno={}",
+                        key, route.next_hop, route.metric, route.seqno
                     );
                 }
                 Event::BestRouteChanged(key, route) => {
                     println!(
-                        "[router1] *** Best route changed for ae={} plen={} prefix={:?}: {}",
-                        key.ae,
-                        key.plen,
-                        key.prefix,
+                        "[router1] *** Best route changed for {}: {}",
+                        key,
                         route.summary()
                     );
                 }
+                Event::RouteRetracted(key) => {
+                    println!("[router1] Route retracted: {key}");
+                }
             }
         }
 
@@ -0,0 +1,146 @@
+// src/metrics.rs
+//! Health counters of a Babel node, for scraping.
+
+use std::fmt::Write;
+
+/// Snapshot of a node's state and traffic counters, see
+/// [`BabelNode::metrics`](crate::BabelNode::metrics).
+///
+/// Neighbor and route counts describe the node at the time of the
+/// snapshot; the others count since the node was created.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeMetrics {
+    /// Known neighbors.
+    pub neighbors_total: u64,
+    /// Neighbors we heard enough Hellos from recently to route through.
+    pub neighbors_reachable: u64,
+    /// Routes in the table, all paths counted, including our own.
+    pub routes_total: u64,
+    /// Prefixes with a selected (reachable) best route.
+    pub routes_best: u64,
+    /// Routes kept with an infinite metric after a retraction.
+    pub routes_retracted: u64,
+    /// Datagrams sent, or handed out by `tick` for the caller to send.
+    pub packets_sent: u64,
+    /// Packets received and accepted for processing.
+    pub packets_received: u64,
+    /// TLVs in received packets.
+    pub tlvs_parsed: u64,
+    /// Received datagrams dropped because they couldn't be parsed.
+    pub parse_errors: u64,
+    /// Received datagrams dropped because they failed authentication.
+    pub auth_failures: u64,
+    /// Queued datagrams dropped because sending them failed.
+    pub send_errors: u64,
+    /// Hellos sent (one per Hello seqno, whatever the number of
+    /// destinations).
+    pub hellos_sent: u64,
+}
+
+impl NodeMetrics {
+    /// Render in the Prometheus text exposition format, one `babel_*`
+    /// metric per field.
+    pub fn to_prometheus_text(&self) -> String {
+        let metrics = [
+            (
+                "neighbors",
+                "gauge",
+                "Known neighbors.",
+                self.neighbors_total,
+            ),
+            (
+                "neighbors_reachable",
+                "gauge",
+                "Reachable neighbors.",
+                self.neighbors_reachable,
+            ),
+            ("routes", "gauge", "Routes in the table.", self.routes_total),
+            (
+                "routes_best",
+                "gauge",
+                "Prefixes with a best route.",
+                self.routes_best,
+            ),
+            (
+                "routes_retracted",
+                "gauge",
+                "Unreachable routes in the table.",
+                self.routes_retracted,
+            ),
+            (
+                "packets_sent_total",
+                "counter",
+                "Packets sent.",
+                self.packets_sent,
+            ),
+            (
+                "packets_received_total",
+                "counter",
+                "Packets received.",
+                self.packets_received,
+            ),
+            (
+                "tlvs_parsed_total",
+                "counter",
+                "TLVs received.",
+                self.tlvs_parsed,
+            ),
+            (
+                "parse_errors_total",
+                "counter",
+                "Received packets that failed to parse.",
+                self.parse_errors,
+            ),
+            (
+                "auth_failures_total",
+                "counter",
+                "Received packets that failed authentication.",
+                self.auth_failures,
+            ),
+            (
+                "send_errors_total",
+                "counter",
+                "Queued packets dropped after a send error.",
+                self.send_errors,
+            ),
+            (
+                "hellos_sent_total",
+                "counter",
+                "Hellos sent.",
+                self.hellos_sent,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            // Writing to a String can't fail.
+            let _ = writeln!(out, "# HELP babel_{name} {help}");
+            let _ = writeln!(out, "# TYPE babel_{name} {kind}");
+            let _ = writeln!(out, "babel_{name} {value}");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_text_has_type_and_value() {
+        let metrics = NodeMetrics {
+            neighbors_total: 2,
+            hellos_sent: 5,
+            ..NodeMetrics::default()
+        };
+        let text = metrics.to_prometheus_text();
+
+        assert!(text.contains("# TYPE babel_neighbors gauge\nbabel_neighbors 2\n"));
+        assert!(
+            text.contains("# TYPE babel_hellos_sent_total counter\nbabel_hellos_sent_total 5\n")
+        );
+        assert!(text.contains("babel_parse_errors_total 0\n"));
+        assert_eq!(text.lines().filter(|l| l.starts_with("# TYPE")).count(), 12);
+    }
+}
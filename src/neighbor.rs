@@ -5,6 +5,37 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
+use crate::routing::INFINITY;
+
+/// Number of Hellos remembered in [`Neighbor::hello_history`].
+pub const HELLO_HISTORY_BITS: u8 = u32::BITS as u8;
+
+/// Most Hellos recorded as missed between two received ones.
+pub const MAX_MISSED_HELLOS: u32 = 16;
+
+/// Number of recent Hellos the ETX estimate averages over.
+pub const COST_WINDOW: u8 = 16;
+
+/// Default number of recent Hellos over which a neighbor's reachability is
+/// judged.
+pub const REACHABILITY_WINDOW: u8 = 16;
+
+/// Default number of Hello intervals of silence after which a neighbor
+/// is considered gone.
+pub const STALE_MULTIPLIER: u32 = 3;
+
+/// How a link cost is derived from Hello loss, per RFC 8966 Appendix A.2.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LinkCostStrategy {
+    /// 256 if at least 2 of the last 3 Hellos arrived, infinite otherwise;
+    /// suited to wired links.
+    #[default]
+    TwoOutOfThree,
+    /// Expected transmission count: 256 divided by the fraction of Hellos
+    /// received, multiplied with the neighbor's cost; suited to wireless.
+    Etx,
+}
+
 /// Representation of a Babel neighbor.
 #[derive(Debug, Clone)]
 pub struct Neighbor {
@@ -18,17 +49,33 @@ pub struct Neighbor {
     /// Hello interval (ms) as advertised by the neighbor.
     pub hello_interval_ms: Option<u16>,
     /// History bitmask of Hello reception: LSB = most recent.
-    pub hello_history: u16,
+    ///
+    /// Holds up to [`HELLO_HISTORY_BITS`] Hellos.
+    pub hello_history: u32,
+    /// Number of valid entries in `hello_history`: slots from before we
+    /// first heard the neighbor aren't counted as losses.
+    pub hello_history_len: u8,
 
     /// Time of last received Hello.
     pub last_hello_rx: Option<Instant>,
     /// Time of last received IHU.
     pub last_ihu_rx: Option<Instant>,
+    /// IHU interval (ms) as advertised in the neighbor's last IHU.
+    pub ihu_interval_ms: Option<u16>,
 
     /// Neighbor’s advertised receive cost toward us (from IHU TLV).
     pub rxcost: Option<u16>,
-    /// Our transmit cost toward this neighbor.
+    /// Our transmit cost toward this neighbor, derived from Hello loss
+    /// (see [`compute_txcost`](Self::compute_txcost)) unless set by hand.
     pub txcost: Option<u16>,
+    /// How `txcost` is derived and combined with `rxcost`.
+    pub cost_strategy: LinkCostStrategy,
+
+    /// Transmit timestamp of the neighbor's last timestamped Hello and our
+    /// clock when it arrived (µs), echoed in our IHUs.
+    pub hello_timestamp: Option<(u32, u32)>,
+    /// Round-trip time estimated from timestamped Hello/IHU exchanges.
+    pub rtt_ms: Option<u32>,
 }
 
 impl Neighbor {
@@ -40,36 +87,153 @@ impl Neighbor {
             last_hello_seqno: None,
             hello_interval_ms: None,
             hello_history: 0,
+            hello_history_len: 0,
             last_hello_rx: None,
             last_ihu_rx: None,
+            ihu_interval_ms: None,
             rxcost: None,
             txcost: None,
+            cost_strategy: LinkCostStrategy::default(),
+            hello_timestamp: None,
+            rtt_ms: None,
         }
     }
 
     /// Called when a Hello TLV is received from this neighbor.
+    ///
+    /// Hellos that should have arrived since the previous one, judging by
+    /// its interval, are recorded as missed (at most [`MAX_MISSED_HELLOS`]).
     pub fn note_hello(&mut self, seqno: u16, interval_ms: u16, now: Instant) {
+        let shift = 1 + self.missed_hellos(now);
         self.last_hello_seqno = Some(seqno);
         self.hello_interval_ms = Some(interval_ms);
         self.last_hello_rx = Some(now);
-        self.hello_history = (self.hello_history << 1) | 1;
+        self.hello_history = self.hello_history.checked_shl(shift).unwrap_or(0) | 1;
+        self.hello_history_len =
+            (self.hello_history_len as u32 + shift).min(HELLO_HISTORY_BITS as u32) as u8;
+    }
+
+    /// Number of Hellos missed between the last one received and `now`.
+    fn missed_hellos(&self, now: Instant) -> u32 {
+        let (Some(last), Some(interval_ms)) = (self.last_hello_rx, self.hello_interval_ms) else {
+            return 0;
+        };
+        if interval_ms == 0 {
+            return 0;
+        }
+
+        // Round to the nearest interval to absorb jitter.
+        let elapsed_ms = now.saturating_duration_since(last).as_millis();
+        let intervals = (elapsed_ms + interval_ms as u128 / 2) / interval_ms as u128;
+        (intervals.saturating_sub(1) as u32).min(MAX_MISSED_HELLOS)
     }
 
     /// Called when an IHU TLV is received from this neighbor.
-    pub fn note_ihu(&mut self, rxcost: u16, _interval_ms: u16, now: Instant) {
+    pub fn note_ihu(&mut self, rxcost: u16, interval_ms: u16, now: Instant) {
         self.rxcost = Some(rxcost);
+        self.ihu_interval_ms = Some(interval_ms);
         self.last_ihu_rx = Some(now);
     }
 
+    /// Whether the IHU hold time, 3.5 times the interval of the last IHU,
+    /// has run out by `now` without a new IHU.
+    ///
+    /// An rxcost not learned from an IHU, or from an IHU with a zero
+    /// interval, never expires.
+    pub fn rxcost_expired(&self, now: Instant) -> bool {
+        let (Some(last), Some(interval_ms)) = (self.last_ihu_rx, self.ihu_interval_ms) else {
+            return false;
+        };
+        if interval_ms == 0 {
+            return false;
+        }
+        let hold = Duration::from_millis(interval_ms as u64 * 7 / 2);
+        now.saturating_duration_since(last) > hold
+    }
+
+    /// Record the transmit timestamp of a Hello and our clock at arrival.
+    pub fn note_hello_timestamp(&mut self, transmit: u32, arrival: u32) {
+        self.hello_timestamp = Some((transmit, arrival));
+    }
+
+    /// Update the RTT from an IHU timestamp, per RFC 9616.
+    ///
+    /// `origin` is our Hello transmit time echoed back, `receive` the
+    /// neighbor's clock when that Hello arrived. The neighbor's transmit
+    /// time comes from the Hello sent alongside the IHU, which must have
+    /// been noted first; `arrival` is our clock now. All values are µs.
+    pub fn note_ihu_timestamp(&mut self, origin: u32, receive: u32, arrival: u32) {
+        let Some((transmit, _)) = self.hello_timestamp else {
+            return;
+        };
+        let total = arrival.wrapping_sub(origin);
+        let held = transmit.wrapping_sub(receive);
+        if let Some(rtt_us) = total.checked_sub(held) {
+            self.rtt_ms = Some(rtt_us / 1000);
+        }
+    }
+
+    /// Last time we heard anything (Hello or IHU) from this neighbor.
+    pub fn last_seen(&self) -> Option<Instant> {
+        self.last_hello_rx.max(self.last_ihu_rx)
+    }
+
     /// Set our transmit cost to this neighbor.
     pub fn set_txcost(&mut self, txcost: u16) {
         self.txcost = Some(txcost);
     }
 
-    /// Compute link cost (naive).
+    /// Transmit cost derived from the Hello history with our
+    /// [`cost_strategy`](Self::cost_strategy).
+    ///
+    /// ETX averages over the last `window` Hellos; 2-out-of-3 always looks
+    /// at the last 3. Only Hellos since the neighbor was first heard count.
+    pub fn compute_txcost(&self, window: u8) -> u16 {
+        let window = match self.cost_strategy {
+            LinkCostStrategy::TwoOutOfThree => 3,
+            LinkCostStrategy::Etx => window,
+        };
+        let slots = window.min(self.hello_history_len);
+        let received = (self.hello_history & history_mask(slots)).count_ones();
+        if received == 0 {
+            return INFINITY;
+        }
+
+        match self.cost_strategy {
+            LinkCostStrategy::TwoOutOfThree => {
+                if slots as u32 - received <= 1 {
+                    256
+                } else {
+                    INFINITY
+                }
+            }
+            LinkCostStrategy::Etx => (256 * slots as u32 / received).min(INFINITY as u32) as u16,
+        }
+    }
+
+    /// Cost of the link, combining the neighbor's `rxcost` with our
+    /// `txcost` per our [`cost_strategy`](Self::cost_strategy): the larger
+    /// of the two for 2-out-of-3, their product (scaled by 256) for ETX.
+    ///
+    /// An expired rxcost (see [`rxcost_expired`](Self::rxcost_expired))
+    /// counts as infinite: the neighbor no longer tells us it hears us.
     pub fn link_cost(&self) -> Option<u16> {
-        match (self.rxcost, self.txcost) {
-            (Some(rx), Some(tx)) => Some(rx.max(tx)),
+        self.link_cost_at(Instant::now())
+    }
+
+    /// [`link_cost`](Self::link_cost), with IHU expiry judged at `now`.
+    pub fn link_cost_at(&self, now: Instant) -> Option<u16> {
+        let rxcost = if self.rxcost.is_some() && self.rxcost_expired(now) {
+            Some(INFINITY)
+        } else {
+            self.rxcost
+        };
+        match (rxcost, self.txcost) {
+            (Some(rx), Some(tx)) if rx == INFINITY || tx == INFINITY => Some(INFINITY),
+            (Some(rx), Some(tx)) => Some(match self.cost_strategy {
+                LinkCostStrategy::TwoOutOfThree => rx.max(tx),
+                LinkCostStrategy::Etx => (rx as u32 * tx as u32 / 256).min(INFINITY as u32) as u16,
+            }),
             (Some(rx), None) => Some(rx),
             (None, Some(tx)) => Some(tx),
             _ => None,
@@ -77,10 +241,11 @@ impl Neighbor {
     }
 
     /// Whether the neighbor is reachable according to Hello history.
+    ///
+    /// `window` is the number of most recent Hellos considered, capped at
+    /// [`HELLO_HISTORY_BITS`].
     pub fn is_reachable(&self, window: u8) -> bool {
-        let k = window.min(16);
-        let mask = if k == 16 { u16::MAX } else { (1u16 << k) - 1 };
-        (self.hello_history & mask) != 0
+        (self.hello_history & history_mask(window)) != 0
     }
 
     /// Whether the neighbor has gone silent long enough to be considered stale.
@@ -97,16 +262,62 @@ impl Neighbor {
     }
 }
 
+/// Bitmask selecting the `window` most recent entries of a Hello history.
+fn history_mask(window: u8) -> u32 {
+    let k = window.min(HELLO_HISTORY_BITS);
+    if k == HELLO_HISTORY_BITS {
+        u32::MAX
+    } else {
+        (1u32 << k) - 1
+    }
+}
+
+/// Plain copy of one neighbor's state, see [`NeighborTable::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborSnapshot {
+    pub addr: SocketAddr,
+    pub iface_index: u32,
+    pub last_hello_seqno: Option<u16>,
+    /// Judged over the table's [`reachability_window`](NeighborTable::reachability_window).
+    pub reachable: bool,
+    pub link_cost: Option<u16>,
+    pub rtt_ms: Option<u32>,
+    /// Time since the last Hello, `None` if we never got one.
+    pub ms_since_hello: Option<u64>,
+}
+
 /// Table of all known neighbors.
-#[derive(Debug, Default)]
+///
+/// Neighbors are keyed by address and interface: the same (link-local)
+/// address heard on two interfaces is two different neighbors.
+#[derive(Debug)]
 pub struct NeighborTable {
-    neighbors: HashMap<SocketAddr, Neighbor>,
+    neighbors: HashMap<(SocketAddr, u32), Neighbor>,
+    cost_strategy: LinkCostStrategy,
+    reachability_window: u8,
+}
+
+impl Default for NeighborTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NeighborTable {
     pub fn new() -> Self {
         NeighborTable {
             neighbors: HashMap::new(),
+            cost_strategy: LinkCostStrategy::default(),
+            reachability_window: REACHABILITY_WINDOW,
+        }
+    }
+
+    /// An empty table whose neighbors derive their link cost with `strategy`.
+    pub fn with_cost_strategy(strategy: LinkCostStrategy) -> Self {
+        NeighborTable {
+            cost_strategy: strategy,
+            ..Self::new()
         }
     }
 
@@ -114,18 +325,48 @@ impl NeighborTable {
         self.neighbors.values()
     }
 
-    pub fn get(&self, addr: &SocketAddr) -> Option<&Neighbor> {
-        self.neighbors.get(addr)
+    /// Neighbors we heard a Hello from within the last `window` Hellos.
+    pub fn reachable(&self, window: u8) -> impl Iterator<Item = &Neighbor> {
+        self.all().filter(move |n| n.is_reachable(window))
+    }
+
+    /// Number of recent Hellos reachability is judged over by the table's
+    /// users (default: [`REACHABILITY_WINDOW`]).
+    pub fn reachability_window(&self) -> u8 {
+        self.reachability_window
+    }
+
+    /// Change the window returned by [`reachability_window`](Self::reachability_window).
+    pub fn set_reachability_window(&mut self, window: u8) {
+        self.reachability_window = window;
+    }
+
+    pub fn get(&self, addr: &SocketAddr, iface_index: u32) -> Option<&Neighbor> {
+        self.neighbors.get(&(*addr, iface_index))
     }
 
-    pub fn get_mut(&mut self, addr: &SocketAddr) -> Option<&mut Neighbor> {
-        self.neighbors.get_mut(addr)
+    pub fn get_mut(&mut self, addr: &SocketAddr, iface_index: u32) -> Option<&mut Neighbor> {
+        self.neighbors.get_mut(&(*addr, iface_index))
+    }
+
+    /// Address and interface of the least recently seen neighbor, if any.
+    ///
+    /// Neighbors we have never heard from count as the oldest.
+    pub fn oldest(&self) -> Option<(SocketAddr, u32)> {
+        self.neighbors
+            .values()
+            .min_by_key(|n| n.last_seen())
+            .map(|n| (n.addr, n.iface_index))
     }
 
     fn ensure_neighbor(&mut self, addr: SocketAddr, iface_index: u32) -> &mut Neighbor {
+        let strategy = self.cost_strategy;
         self.neighbors
-            .entry(addr)
-            .or_insert_with(|| Neighbor::new(addr, iface_index))
+            .entry((addr, iface_index))
+            .or_insert_with(|| Neighbor {
+                cost_strategy: strategy,
+                ..Neighbor::new(addr, iface_index)
+            })
     }
 
     /// Update state according to a received Hello TLV.
@@ -139,6 +380,7 @@ impl NeighborTable {
     ) {
         let n = self.ensure_neighbor(src, iface_index);
         n.note_hello(seqno, interval_ms, now);
+        n.txcost = Some(n.compute_txcost(COST_WINDOW));
     }
 
     /// Update state according to a received IHU TLV.
@@ -160,6 +402,20 @@ impl NeighborTable {
         n.set_txcost(txcost);
     }
 
+    /// Remove all neighbors on the given interface; return their addresses.
+    pub fn remove_by_iface(&mut self, iface_index: u32) -> Vec<SocketAddr> {
+        let mut removed = Vec::new();
+        self.neighbors.retain(|(addr, _), n| {
+            if n.iface_index == iface_index {
+                removed.push(*addr);
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
     /// Remove all stale neighbors; returns how many were removed.
     pub fn prune_stale(&mut self, now: Instant, multiplier: u32) -> usize {
         let before = self.neighbors.len();
@@ -167,12 +423,80 @@ impl NeighborTable {
         before - self.neighbors.len()
     }
 
-    /// Remove all stale neighbors; return their socket addresses.
-    pub fn prune_stale_with_addrs(&mut self, now: Instant, multiplier: u32) -> Vec<SocketAddr> {
+    /// Copy of every neighbor's state, sorted by address, e.g. for a
+    /// status endpoint.
+    pub fn snapshot(&self) -> Vec<NeighborSnapshot> {
+        self.snapshot_at(Instant::now())
+    }
+
+    /// [`snapshot`](Self::snapshot) as of `now`.
+    pub fn snapshot_at(&self, now: Instant) -> Vec<NeighborSnapshot> {
+        let mut out: Vec<NeighborSnapshot> = self
+            .neighbors
+            .values()
+            .map(|n| NeighborSnapshot {
+                addr: n.addr,
+                iface_index: n.iface_index,
+                last_hello_seqno: n.last_hello_seqno,
+                reachable: n.is_reachable(self.reachability_window),
+                link_cost: n.link_cost_at(now),
+                rtt_ms: n.rtt_ms,
+                ms_since_hello: n
+                    .last_hello_rx
+                    .map(|t| now.saturating_duration_since(t).as_millis() as u64),
+            })
+            .collect();
+        out.sort_by_key(|s| (s.addr, s.iface_index));
+        out
+    }
+
+    /// Render a `show neighbors` table, one neighbor per line, sorted by address.
+    ///
+    /// Reachability is judged over the last `window` Hellos; the history
+    /// column shows the most recent Hello on the right.
+    pub fn dump(&self, now: Instant, window: u8) -> String {
+        fn cost(c: Option<u16>) -> String {
+            c.map_or_else(|| "-".to_string(), |c| c.to_string())
+        }
+
+        let mut neighbors: Vec<&Neighbor> = self.neighbors.values().collect();
+        neighbors.sort_by_key(|n| (n.addr, n.iface_index));
+
+        let mut out = format!(
+            "{:<40} {:>5} {:>5} {:>6} {:>6} {:>6} {:>8}  {}\n",
+            "address", "iface", "reach", "rxcost", "txcost", "cost", "age", "history"
+        );
+        for n in neighbors {
+            let age = match n.last_seen() {
+                Some(t) => format!("{:.1}s", now.saturating_duration_since(t).as_secs_f64()),
+                None => "never".to_string(),
+            };
+            out.push_str(&format!(
+                "{:<40} {:>5} {:>5} {:>6} {:>6} {:>6} {:>8}  {:0width$b}\n",
+                n.addr.to_string(),
+                n.iface_index,
+                if n.is_reachable(window) { "yes" } else { "no" },
+                cost(n.rxcost),
+                cost(n.txcost),
+                cost(n.link_cost_at(now)),
+                age,
+                n.hello_history,
+                width = HELLO_HISTORY_BITS as usize,
+            ));
+        }
+        out
+    }
+
+    /// Remove all stale neighbors; return their addresses and interfaces.
+    pub fn prune_stale_with_addrs(
+        &mut self,
+        now: Instant,
+        multiplier: u32,
+    ) -> Vec<(SocketAddr, u32)> {
         let mut removed = Vec::new();
-        self.neighbors.retain(|addr, n| {
+        self.neighbors.retain(|key, n| {
             if n.is_stale(now, multiplier) {
-                removed.push(*addr);
+                removed.push(*key);
                 false
             } else {
                 true
@@ -203,6 +527,43 @@ mod tests {
         assert_eq!(n.hello_history & 1, 1);
     }
 
+    #[test]
+    fn reachable_skips_neighbors_without_hellos() {
+        let mut table = NeighborTable::new();
+        let other = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 11)), 6696);
+        let now = Instant::now();
+
+        table.update_on_hello(addr(), 1, 1, 4000, now);
+        table.update_on_ihu(other, 1, 256, 4000, now);
+        assert_eq!(table.get(&other, 1).unwrap().hello_history, 0);
+
+        let window = table.reachability_window();
+        let reachable: Vec<SocketAddr> = table.reachable(window).map(|n| n.addr).collect();
+        assert_eq!(reachable, vec![addr()]);
+        assert_eq!(table.all().count(), 2);
+    }
+
+    #[test]
+    fn snapshot_reflects_hellos() {
+        let mut table = NeighborTable::new();
+        let t0 = Instant::now();
+        table.update_on_hello(addr(), 3, 7, 4000, t0);
+
+        let snap = table.snapshot_at(t0 + Duration::from_millis(1500));
+        assert_eq!(
+            snap,
+            vec![NeighborSnapshot {
+                addr: addr(),
+                iface_index: 3,
+                last_hello_seqno: Some(7),
+                reachable: true,
+                link_cost: Some(256),
+                rtt_ms: None,
+                ms_since_hello: Some(1500),
+            }]
+        );
+    }
+
     #[test]
     fn hello_history_shifts_correctly() {
         let mut n = Neighbor::new(addr(), 1);
@@ -216,6 +577,47 @@ mod tests {
         assert!(n.is_reachable(3));
     }
 
+    #[test]
+    fn missed_hellos_are_recorded_as_zeros() {
+        let mut n = Neighbor::new(addr(), 1);
+        let t0 = Instant::now();
+        let interval = Duration::from_millis(1000);
+
+        n.note_hello(1, 1000, t0);
+        // Two Hellos lost, the next one arrives a little late.
+        n.note_hello(4, 1000, t0 + interval * 3 + Duration::from_millis(200));
+        assert_eq!(n.hello_history, 0b1001);
+        assert_eq!(n.hello_history_len, 4);
+
+        // A long silence counts as at most MAX_MISSED_HELLOS misses.
+        let t1 = t0 + interval * 3;
+        n.note_hello(5, 1000, t1 + interval * 100);
+        assert_eq!(n.hello_history, (0b1001 << 17) | 1);
+    }
+
+    #[test]
+    fn hello_history_supports_wide_windows() {
+        let mut n = Neighbor::new(addr(), 1);
+
+        // Only Hello received 20 intervals ago, everything since was lost.
+        n.hello_history = 1 << 20;
+        assert!(!n.is_reachable(16));
+        assert!(n.is_reachable(24));
+
+        // A full 32-bit history, with the oldest bit only.
+        n.hello_history = 1 << 31;
+        assert!(!n.is_reachable(24));
+        assert!(n.is_reachable(32));
+        assert!(n.is_reachable(40)); // capped at 32
+
+        // Old Hellos shift out after 32 receptions.
+        n.hello_history = 0b1;
+        for i in 0..31 {
+            n.note_hello(i, 1000, Instant::now());
+        }
+        assert_eq!(n.hello_history, u32::MAX);
+    }
+
     #[test]
     fn stale_neighbor_detection() {
         let mut n = Neighbor::new(addr(), 1);
@@ -235,6 +637,56 @@ mod tests {
         assert_eq!(n.link_cost(), Some(150));
     }
 
+    #[test]
+    fn txcost_follows_hello_loss() {
+        let with_history = |strategy, history: u32, len| Neighbor {
+            hello_history: history,
+            hello_history_len: len,
+            cost_strategy: strategy,
+            ..Neighbor::new(addr(), 1)
+        };
+
+        for strategy in [LinkCostStrategy::TwoOutOfThree, LinkCostStrategy::Etx] {
+            assert_eq!(with_history(strategy, 0b1111, 4).compute_txcost(4), 256);
+            assert_eq!(with_history(strategy, 0, 4).compute_txcost(4), INFINITY);
+            // A neighbor heard once hasn't lost anything yet.
+            assert_eq!(with_history(strategy, 0b1, 1).compute_txcost(4), 256);
+        }
+
+        // 3 of 4 received: ETX 4/3 ...
+        let etx = |history| with_history(LinkCostStrategy::Etx, history, 4).compute_txcost(4);
+        assert_eq!(etx(0b1011), 341);
+        assert!(etx(0b1011) > etx(0b1111));
+        // ... while 2 of the last 3 is still good enough for 2-out-of-3.
+        let wired =
+            |history| with_history(LinkCostStrategy::TwoOutOfThree, history, 4).compute_txcost(4);
+        assert_eq!(wired(0b1011), 256);
+        assert_eq!(wired(0b1001), INFINITY);
+    }
+
+    #[test]
+    fn etx_link_cost_multiplies_costs() {
+        let mut n = Neighbor::new(addr(), 1);
+        n.cost_strategy = LinkCostStrategy::Etx;
+        n.rxcost = Some(512);
+        n.txcost = Some(384);
+        assert_eq!(n.link_cost(), Some(768));
+
+        n.txcost = Some(INFINITY);
+        assert_eq!(n.link_cost(), Some(INFINITY));
+    }
+
+    #[test]
+    fn hellos_derive_txcost() {
+        let mut tbl = NeighborTable::with_cost_strategy(LinkCostStrategy::Etx);
+        let a = addr();
+        tbl.update_on_hello(a, 1, 1, 1000, Instant::now());
+
+        let n = tbl.get(&a, 1).unwrap();
+        assert_eq!(n.cost_strategy, LinkCostStrategy::Etx);
+        assert_eq!(n.txcost, Some(256));
+    }
+
     #[test]
     fn table_updates_neighbors() {
         let mut tbl = NeighborTable::default();
@@ -244,12 +696,62 @@ mod tests {
         tbl.update_on_hello(a, 2, 42, 3000, now);
         tbl.update_on_ihu(a, 2, 200, 3000, now);
 
-        let n = tbl.get(&a).unwrap();
+        let n = tbl.get(&a, 2).unwrap();
         assert_eq!(n.last_hello_seqno, Some(42));
         assert_eq!(n.rxcost, Some(200));
         assert_eq!(n.iface_index, 2);
     }
 
+    #[test]
+    fn rxcost_expires_after_ihu_hold_time() {
+        let mut n = Neighbor::new(addr(), 1);
+        let t0 = Instant::now();
+        n.note_hello(1, 1000, t0);
+        n.txcost = Some(256);
+        n.note_ihu(300, 1000, t0);
+
+        assert!(!n.rxcost_expired(t0 + Duration::from_millis(3500)));
+        assert_eq!(n.link_cost_at(t0 + Duration::from_millis(3500)), Some(300));
+
+        let later = t0 + Duration::from_millis(3600);
+        assert!(n.rxcost_expired(later));
+        assert_eq!(n.link_cost_at(later), Some(INFINITY));
+
+        // A fresh IHU brings the link back.
+        n.note_ihu(300, 1000, later);
+        assert_eq!(n.link_cost_at(later), Some(300));
+    }
+
+    #[test]
+    fn last_seen_picks_later_timestamp() {
+        let mut n = Neighbor::new(addr(), 1);
+        let now = Instant::now();
+        assert_eq!(n.last_seen(), None);
+
+        n.note_hello(1, 1000, now);
+        n.note_ihu(256, 1000, now + Duration::from_millis(300));
+        assert_eq!(n.last_seen(), Some(now + Duration::from_millis(300)));
+
+        n.note_hello(2, 1000, now + Duration::from_millis(900));
+        assert_eq!(n.last_seen(), Some(now + Duration::from_millis(900)));
+    }
+
+    #[test]
+    fn oldest_returns_least_recently_seen() {
+        let mut tbl = NeighborTable::default();
+        let a = addr();
+        let b = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 20)), 6696);
+        let now = Instant::now();
+        assert_eq!(tbl.oldest(), None);
+
+        tbl.update_on_hello(a, 1, 1, 1000, now);
+        tbl.update_on_hello(b, 1, 1, 1000, now + Duration::from_millis(100));
+        assert_eq!(tbl.oldest(), Some((a, 1)));
+
+        tbl.update_on_ihu(a, 1, 256, 1000, now + Duration::from_millis(200));
+        assert_eq!(tbl.oldest(), Some((b, 1)));
+    }
+
     #[test]
     fn prune_removes_stale_neighbors() {
         let mut tbl = NeighborTable::default();
@@ -261,6 +763,57 @@ mod tests {
 
         let removed = tbl.prune_stale(later, 3);
         assert_eq!(removed, 1);
-        assert!(tbl.get(&a).is_none());
+        assert!(tbl.get(&a, 1).is_none());
+    }
+
+    #[test]
+    fn same_address_on_two_interfaces_is_two_neighbors() {
+        let mut tbl = NeighborTable::default();
+        let a = addr();
+        let now = Instant::now();
+
+        tbl.update_on_hello(a, 1, 1, 1000, now);
+        tbl.update_on_hello(a, 2, 7, 1000, now);
+        tbl.update_on_ihu(a, 2, 512, 1000, now);
+
+        assert_eq!(tbl.all().count(), 2);
+        assert_eq!(tbl.get(&a, 1).unwrap().last_hello_seqno, Some(1));
+        assert_eq!(tbl.get(&a, 1).unwrap().rxcost, None);
+        assert_eq!(tbl.get(&a, 2).unwrap().last_hello_seqno, Some(7));
+        assert_eq!(tbl.get(&a, 2).unwrap().rxcost, Some(512));
+
+        assert_eq!(tbl.remove_by_iface(1), vec![a]);
+        assert!(tbl.get(&a, 1).is_none());
+        assert!(tbl.get(&a, 2).is_some());
+    }
+
+    #[test]
+    fn dump_lists_neighbors() {
+        let mut tbl = NeighborTable::default();
+        let a = addr();
+        let now = Instant::now();
+
+        tbl.update_on_hello(a, 1, 1, 1000, now);
+        tbl.update_on_ihu(a, 1, 256, 1000, now);
+
+        let dump = tbl.dump(now + Duration::from_millis(1500), 16);
+        let line = dump
+            .lines()
+            .find(|l| l.starts_with("192.0.2.10:6696"))
+            .unwrap();
+        assert!(line.contains(" yes "));
+        assert!(line.contains("1.5s"));
+        assert!(line.ends_with('1'));
+    }
+
+    #[test]
+    fn rtt_from_hello_and_ihu_timestamps() {
+        let mut n = Neighbor::new(addr(), 1);
+
+        // We sent a Hello at 1.000s; the neighbor got it at its 5.000s,
+        // answered at its 5.010s, and we received that at 1.050s.
+        n.note_hello_timestamp(5_010_000, 1_050_000);
+        n.note_ihu_timestamp(1_000_000, 5_000_000, 1_050_000);
+        assert_eq!(n.rtt_ms, Some(40));
     }
 }
@@ -4,16 +4,29 @@
 //! This wraps Packet + TLV + NeighborTable + RoutingTable into a usable component
 //! that can send hellos, IHUs, updates, receive packets, and maintain state.
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::{Duration, Instant};
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
 use crate::event::Event;
-use crate::neighbor::NeighborTable;
-use crate::packet::{BABEL_PORT, MULTICAST_V4_ADDR, Packet};
-use crate::routing::{Route, RouteKey, RoutingTable};
-use crate::tlv::Tlv;
+use crate::metrics::NodeMetrics;
+use crate::neighbor::{
+    COST_WINDOW, LinkCostStrategy, Neighbor, NeighborTable, REACHABILITY_WINDOW, STALE_MULTIPLIER,
+};
+use crate::packet::{self, BABEL_PORT, MULTICAST_V4_ADDR, MULTICAST_V6_ADDR, Packet};
+use crate::routing::{
+    AdditiveMetric, INFINITY, MetricComputer, Route, RouteChange, RouteKey, RoutingTable,
+    best_changed, route_hold_time, seqno_gt,
+};
+use crate::tlv::{AddressFamily, DefaultPrefixes, SubTlv, Tlv, ae_family};
+use crate::trace::PcapWriter;
 
 /// A statically advertised prefix (e.g. "this node owns 192.0.2.0/24").
 #[derive(Debug, Clone)]
@@ -28,13 +41,121 @@ pub struct AdvertisedPrefix {
     pub metric: u16,
 }
 
+impl AdvertisedPrefix {
+    /// Build a prefix, rejecting one that [`validate`](Self::validate)
+    /// wouldn't accept.
+    pub fn new(ae: u8, plen: u8, prefix: Vec<u8>, metric: u16) -> Result<Self, String> {
+        let p = AdvertisedPrefix {
+            ae,
+            plen,
+            prefix,
+            metric,
+        };
+        p.validate()?;
+        Ok(p)
+    }
+
+    /// Routing table key for this prefix.
+    pub fn key(&self) -> RouteKey {
+        RouteKey {
+            ae: self.ae,
+            plen: self.plen,
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    /// Check that the prefix can be put on the wire as-is.
+    ///
+    /// `prefix` must hold exactly `ceil(plen / 8)` bytes and `plen` must fit
+    /// the address family given by `ae`.
+    pub fn validate(&self) -> Result<(), String> {
+        let max_plen = match (self.ae, ae_family(self.ae)) {
+            (0, _) => 0,
+            (_, Some(family)) => family.max_plen(),
+            (other, None) => return Err(format!("unsupported AE {other}")),
+        };
+        if self.plen > max_plen {
+            return Err(format!(
+                "plen {} exceeds {} for AE {}",
+                self.plen, max_plen, self.ae
+            ));
+        }
+
+        let expected = (self.plen as usize).div_ceil(8);
+        if self.prefix.len() != expected {
+            return Err(format!(
+                "prefix has {} bytes, plen {} needs {}",
+                self.prefix.len(),
+                self.plen,
+                expected
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// Configuration for a Babel node.
 #[derive(Debug, Clone)]
 pub struct BabelConfig {
     pub hello_interval_ms: u16,
     pub ihu_interval_ms: u16,
     pub update_interval_ms: u16,
+    /// Window (ms) within which an identical triggered Update is not resent.
+    pub update_dedup_window_ms: u16,
     pub advertised_prefixes: Vec<AdvertisedPrefix>,
+    /// Address and prefix length of the node's interface, if known.
+    pub interface_address: Option<(IpAddr, u8)>,
+    /// Advertise the subnet of `interface_address` on startup.
+    pub advertise_connected: bool,
+    /// Our own addresses, used as the NextHop of the Updates we send.
+    pub local_addresses: Vec<IpAddr>,
+    /// Interface MTU override; queried from the interface when `None`.
+    pub mtu: Option<usize>,
+    /// Drop IPv6 packets whose source is not link-local.
+    pub require_link_local_source: bool,
+    /// Send Hellos and Updates to the Babel multicast group.
+    pub multicast: bool,
+    /// Peers that also receive our Hellos, IHUs and Updates by unicast.
+    pub unicast_peers: Vec<SocketAddr>,
+    /// Fail to start if the multicast group can't be joined. When false,
+    /// a node with unicast peers falls back to unicast-only.
+    pub require_multicast: bool,
+    /// Put RFC 9616 timestamps in our Hellos and IHUs so neighbors (and we)
+    /// can measure RTT.
+    pub timestamps: bool,
+    /// Period (ms) after which the seqno of our own routes is increased;
+    /// `None` bumps it only on demand.
+    pub route_seqno_interval_ms: Option<u32>,
+    /// Don't advertise learned routes on the interface they were learned
+    /// on. Only safe on links where all neighbors hear each other.
+    pub split_horizon: bool,
+    /// TTL / hop limit of the multicast we send.
+    pub multicast_ttl: u32,
+    /// Interface the socket is bound to with `SO_BINDTODEVICE`, if any.
+    pub bind_device: Option<String>,
+    /// How neighbors' link costs are derived from Hello loss.
+    pub link_cost_strategy: LinkCostStrategy,
+    /// Smoothed-metric margin a route must win by to replace the best
+    /// route; 0 switches on any improvement.
+    pub hysteresis_margin: u16,
+    /// pcap file every datagram sent or received on the socket is
+    /// appended to, if any.
+    pub packet_trace: Option<PathBuf>,
+    /// Seqno of our own routes at startup.
+    pub initial_seqno: u16,
+    /// Spread periodic Hellos, IHUs and Updates over ±25% of their
+    /// interval, so nodes started together don't send in lockstep.
+    pub jitter: bool,
+    /// Seed of the jitter RNG; seeded from the OS when `None`.
+    pub jitter_seed: Option<u64>,
+    /// Hello intervals of silence after which a neighbor is dropped.
+    pub stale_multiplier: u32,
+    /// Number of recent Hellos a neighbor's reachability is judged over.
+    pub reachability_window: u8,
+    /// RFC 8967 HMAC-SHA256 key: our packets are signed with it and
+    /// received packets without a valid HMAC for it, or replayed, are
+    /// dropped.
+    pub hmac_key: Option<Vec<u8>>,
 }
 
 impl Default for BabelConfig {
@@ -43,7 +164,30 @@ impl Default for BabelConfig {
             hello_interval_ms: 4000,
             ihu_interval_ms: 4000,
             update_interval_ms: 10000,
+            update_dedup_window_ms: 1000,
             advertised_prefixes: Vec::new(),
+            interface_address: None,
+            advertise_connected: false,
+            local_addresses: Vec::new(),
+            mtu: None,
+            require_link_local_source: false,
+            multicast: true,
+            unicast_peers: Vec::new(),
+            require_multicast: true,
+            timestamps: false,
+            route_seqno_interval_ms: None,
+            split_horizon: false,
+            multicast_ttl: packet::MULTICAST_TTL,
+            bind_device: None,
+            link_cost_strategy: LinkCostStrategy::default(),
+            hysteresis_margin: 0,
+            packet_trace: None,
+            initial_seqno: 1,
+            jitter: true,
+            jitter_seed: None,
+            stale_multiplier: STALE_MULTIPLIER,
+            reachability_window: REACHABILITY_WINDOW,
+            hmac_key: None,
         }
     }
 }
@@ -72,40 +216,342 @@ impl BabelConfig {
         self
     }
 
+    /// Set the window (in milliseconds) during which a triggered Update
+    /// identical to the last one sent for the same prefix is suppressed.
+    pub fn update_dedup_window_ms(mut self, value: u16) -> Self {
+        self.update_dedup_window_ms = value;
+        self
+    }
+
     /// Add a statically advertised prefix.
     pub fn with_advertised_prefix(mut self, prefix: AdvertisedPrefix) -> Self {
         self.advertised_prefixes.push(prefix);
         self
     }
+
+    /// Like [`with_advertised_prefix`](Self::with_advertised_prefix), but
+    /// fails if the prefix doesn't [`validate`](AdvertisedPrefix::validate).
+    pub fn try_with_advertised_prefix(self, prefix: AdvertisedPrefix) -> Result<Self, String> {
+        prefix.validate()?;
+        Ok(self.with_advertised_prefix(prefix))
+    }
+
+    /// Set the address and prefix length (netmask) of the node's interface.
+    pub fn interface_address(mut self, addr: IpAddr, plen: u8) -> Self {
+        self.interface_address = Some((addr, plen));
+        self
+    }
+
+    /// Automatically advertise the connected subnet of the interface
+    /// (see [`interface_address`](Self::interface_address)).
+    pub fn advertise_connected(mut self, value: bool) -> Self {
+        self.advertise_connected = value;
+        self
+    }
+
+    /// Add one of our own addresses. Outgoing Updates are preceded by a
+    /// NextHop TLV carrying the local address of the matching family.
+    pub fn with_local_address(mut self, addr: IpAddr) -> Self {
+        self.local_addresses.push(addr);
+        self
+    }
+
+    /// Override the interface MTU used to size outgoing packets.
+    pub fn mtu(mut self, value: usize) -> Self {
+        self.mtu = Some(value);
+        self
+    }
+
+    /// Only accept IPv6 packets from link-local source addresses, as babeld
+    /// does. Packets from other sources are dropped and counted.
+    pub fn require_link_local_source(mut self, value: bool) -> Self {
+        self.require_link_local_source = value;
+        self
+    }
+
+    /// Enable or disable sending to the Babel multicast group. Disabling it
+    /// gives pure unicast operation with the configured peers.
+    pub fn multicast(mut self, value: bool) -> Self {
+        self.multicast = value;
+        self
+    }
+
+    /// Add a peer that is sent Hellos, IHUs and Updates by unicast, e.g.
+    /// the other end of a point-to-point link.
+    pub fn add_unicast_peer(mut self, addr: SocketAddr) -> Self {
+        self.unicast_peers.push(addr);
+        self
+    }
+
+    /// Whether failing to join the multicast group is fatal (default: true).
+    pub fn require_multicast(mut self, value: bool) -> Self {
+        self.require_multicast = value;
+        self
+    }
+
+    /// Send timestamps in Hellos and IHUs for RTT measurement.
+    pub fn timestamps(mut self, value: bool) -> Self {
+        self.timestamps = value;
+        self
+    }
+
+    /// Increase the seqno of our own routes every `value` milliseconds.
+    pub fn route_seqno_interval_ms(mut self, value: u32) -> Self {
+        self.route_seqno_interval_ms = Some(value);
+        self
+    }
+
+    /// Enable split horizon (RFC 8966, Section 3.7.4).
+    pub fn split_horizon(mut self, value: bool) -> Self {
+        self.split_horizon = value;
+        self
+    }
+
+    /// Set the TTL / hop limit of our multicast (default: 1, as Babel is
+    /// link-local). Only unusual setups, e.g. tunnels, need more.
+    pub fn multicast_ttl(mut self, value: u32) -> Self {
+        self.multicast_ttl = value;
+        self
+    }
+
+    /// Only send and receive on the interface called `name`, so stray
+    /// packets arriving on other interfaces are never seen. Linux only;
+    /// ignored with a warning elsewhere.
+    pub fn bind_to_device(mut self, name: String) -> Self {
+        self.bind_device = Some(name);
+        self
+    }
+
+    /// Choose how link costs are derived from Hello loss (default:
+    /// 2-out-of-3, for wired links).
+    pub fn link_cost_strategy(mut self, value: LinkCostStrategy) -> Self {
+        self.link_cost_strategy = value;
+        self
+    }
+
+    /// Only switch to a better route once its smoothed metric is lower
+    /// than the current best's by more than `value`, so near-equal routes
+    /// don't flap (default: 0, no hysteresis).
+    pub fn hysteresis_margin(mut self, value: u16) -> Self {
+        self.hysteresis_margin = value;
+        self
+    }
+
+    /// Append every datagram the node sends or receives on its socket to
+    /// the pcap file at `path`, for a look in Wireshark. Packets handed out
+    /// by [`BabelNode::tick`] or fed to [`BabelNode::ingest`] go through
+    /// the caller's I/O and are not traced.
+    pub fn packet_trace(mut self, path: PathBuf) -> Self {
+        self.packet_trace = Some(path);
+        self
+    }
+
+    /// Start the seqno of our own routes at `value` (default: 1).
+    ///
+    /// Neighbors remember the seqno we last announced, so after a restart
+    /// routes starting over from a lower seqno look older and stay
+    /// unfeasible until the seqno catches up. Seed this from a value saved
+    /// with [`BabelNode::save_seqno`], or from the wall clock at boot.
+    pub fn initial_seqno(mut self, value: u16) -> Self {
+        self.initial_seqno = value;
+        self
+    }
+
+    /// Randomize each wait between periodic Hellos, IHUs and Updates to
+    /// 75–125% of the configured interval, as babeld does (default: on).
+    /// Turn it off for reproducible timing, e.g. in tests.
+    pub fn jitter(mut self, value: bool) -> Self {
+        self.jitter = value;
+        self
+    }
+
+    /// Seed the jitter RNG, so the jittered intervals are reproducible.
+    pub fn jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_seed = Some(seed);
+        self
+    }
+
+    /// Drop a neighbor after this many of its Hello intervals without a
+    /// Hello (default: 3). Raise it for lossy links, lower it for faster
+    /// failover.
+    pub fn stale_multiplier(mut self, value: u32) -> Self {
+        self.stale_multiplier = value;
+        self
+    }
+
+    /// Judge a neighbor reachable if any of its last `value` Hellos
+    /// arrived (default: 16).
+    pub fn reachability_window(mut self, value: u8) -> Self {
+        self.reachability_window = value;
+        self
+    }
+
+    /// Authenticate our packets with an RFC 8967 HMAC-SHA256 under `key`,
+    /// and drop received packets that don't carry one or are replayed.
+    pub fn hmac_key(mut self, key: Vec<u8>) -> Self {
+        self.hmac_key = Some(key);
+        self
+    }
+
+    /// All local addresses, including the interface address if set.
+    fn effective_local_addresses(&self) -> Vec<IpAddr> {
+        let mut addrs = self.local_addresses.clone();
+        if let Some((addr, _)) = self.interface_address
+            && !addrs.contains(&addr)
+        {
+            addrs.push(addr);
+        }
+        addrs
+    }
+
+    /// All prefixes to advertise, including the connected subnet if enabled.
+    fn effective_advertised_prefixes(&self) -> Vec<AdvertisedPrefix> {
+        let mut prefixes = self.advertised_prefixes.clone();
+
+        if self.advertise_connected
+            && let Some((addr, plen)) = self.interface_address
+        {
+            let key = RouteKey::from_addr(addr, plen);
+            let already = prefixes
+                .iter()
+                .any(|p| p.ae == key.ae && p.plen == key.plen && p.prefix == key.prefix);
+            if !already {
+                prefixes.push(AdvertisedPrefix {
+                    ae: key.ae,
+                    plen: key.plen,
+                    prefix: key.prefix,
+                    metric: 0,
+                });
+            }
+        }
+
+        prefixes
+    }
 }
 
+/// How often the routing table is garbage-collected.
+const GC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long [`BabelNode::send_with_ack`] waits for an Ack before
+/// retransmitting; also the interval put in its AckRequest.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Retransmissions of an unacknowledged packet before giving up.
+const ACK_RETRIES: u8 = 3;
+
+/// Hop count of the SeqnoRequests we originate: enough to reach the source
+/// across any sane network.
+const SEQNO_REQUEST_HOP_COUNT: u8 = 64;
+
 /// A simple synchronous Babel node.
 pub struct BabelNode {
-    socket: UdpSocket,
+    /// One socket per address family (or interface); never empty.
+    sockets: Vec<UdpSocket>,
+    /// Socket `recv_once` tries first, so busy sockets don't starve others.
+    next_recv: Cell<usize>,
     router_id: [u8; 8],
     seqno: u16,
+    route_seqno: u16,
+    route_seqno_interval: Option<Duration>,
+    last_route_seqno_bump: Option<Instant>,
+    last_gc: Option<Instant>,
+    timestamps: bool,
+    clock_epoch: Instant,
 
     hello_interval: Duration,
     last_hello: Option<Instant>,
+    /// Jittered wait from `last_hello` to the next periodic Hello.
+    hello_wait: Duration,
 
     ihu_interval: Duration,
     last_ihu: Option<Instant>,
+    ihu_wait: Duration,
 
     update_interval: Duration,
     last_update_advert: Option<Instant>,
+    update_wait: Duration,
+
+    /// Source of timer jitter; `None` when jitter is off.
+    jitter_rng: Option<StdRng>,
     advertised_prefixes: Vec<AdvertisedPrefix>,
+    local_addresses: Vec<IpAddr>,
+
+    max_payload: usize,
+
+    update_dedup_window: Duration,
+    last_triggered: HashMap<RouteKey, SentUpdate>,
+    /// Keys whose best route changed since the last poll or tick.
+    triggered_pending: Vec<RouteKey>,
+    pending_acks: Vec<PendingAck>,
+    /// Packets sent with [`send_with_ack`](Self::send_with_ack), oldest first.
+    awaited_acks: Vec<AwaitedAck>,
+    next_ack_opaque: u16,
+    send_queue: Vec<OutgoingPacket>,
+    link_cost_overrides: HashMap<SocketAddr, u16>,
+    metric_computer: Box<dyn MetricComputer>,
+    holddowns: HashMap<RouteKey, Instant>,
+    require_link_local_source: bool,
+    dropped_non_link_local: u64,
+    /// Traffic counters; the neighbor and route counts are filled in by
+    /// [`metrics`](Self::metrics).
+    counters: NodeMetrics,
+    trace: Option<PcapWriter>,
+    multicast: bool,
+    unicast_peers: Vec<SocketAddr>,
+    split_horizon: bool,
+    stale_multiplier: u32,
+    hmac_key: Option<Vec<u8>>,
+    /// RFC 8967 packet counter for our next signed packet, and its index.
+    pc: Cell<u32>,
+    pc_index: Cell<[u8; 4]>,
+    /// Per neighbor, the last packet counter seen under each of its
+    /// indices.
+    neighbor_pcs: HashMap<SocketAddr, Vec<(Vec<u8>, u32)>>,
 
     pub iface_index: u32,
     pub neighbors: NeighborTable,
     pub routes: RoutingTable,
-    source_info: HashMap<SocketAddr, SourceInfo>,
     events: Vec<Event>,
+    prefix_subscribers: Vec<(RouteKey, Sender<Event>)>,
+    event_callbacks: Vec<EventCallback>,
+}
+
+/// Callback registered with [`BabelNode::on_event`].
+pub type EventCallback = Box<dyn FnMut(&Event)>;
+
+/// What we last put on the wire in a triggered Update for a given prefix.
+#[derive(Debug, Clone, Copy)]
+struct SentUpdate {
+    router_id: [u8; 8],
+    seqno: u16,
+    metric: u16,
+    at: Instant,
+}
+
+/// A packet produced by [`BabelNode::tick`], to be sent by the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingPacket {
+    pub dest: SocketAddr,
+    /// Full packet bytes, Babel header included.
+    pub bytes: Vec<u8>,
+}
+
+/// An Ack we owe a neighbor, to be sent before `deadline`.
+#[derive(Debug, Clone, Copy)]
+struct PendingAck {
+    dest: SocketAddr,
+    opaque: u16,
+    deadline: Instant,
 }
 
-#[derive(Debug, Default, Clone)]
-struct SourceInfo {
-    router_id: Option<[u8; 8]>,
-    next_hop: Option<IpAddr>,
+/// A packet we sent with an AckRequest and no Ack has come back for yet.
+#[derive(Debug, Clone)]
+struct AwaitedAck {
+    dest: SocketAddr,
+    opaque: u16,
+    bytes: Vec<u8>,
+    retry_at: Instant,
+    retries_left: u8,
 }
 
 impl BabelNode {
@@ -116,27 +562,208 @@ impl BabelNode {
         router_id: [u8; 8],
         config: BabelConfig,
     ) -> io::Result<Self> {
-        let socket = Packet::bind_multicast_v4(iface_addr)?;
-        socket.set_nonblocking(true)?;
+        let socket = Packet::bind_reusable((Ipv4Addr::UNSPECIFIED, BABEL_PORT).into())?;
+        Self::with_v4_multicast_socket(socket, iface_addr, iface_index, router_id, config)
+    }
+
+    /// Join the IPv4 multicast group with `socket` and build the node on it.
+    ///
+    /// If the join fails but unicast peers are configured and multicast
+    /// isn't required, the node runs unicast-only.
+    fn with_v4_multicast_socket(
+        socket: UdpSocket,
+        iface_addr: Ipv4Addr,
+        iface_index: u32,
+        router_id: [u8; 8],
+        config: BabelConfig,
+    ) -> io::Result<Self> {
+        let mut config = config;
+        Self::join_or_fall_back(&mut config, Packet::join_multicast_v4(&socket, iface_addr))?;
+
+        if !iface_addr.is_unspecified() {
+            config.local_addresses.push(IpAddr::V4(iface_addr));
+        }
+        Self::with_socket(socket, iface_index, router_id, config)
+    }
+
+    /// Create a Babel node joined to IPv6 multicast on the given interface.
+    pub fn new_v6_multicast(
+        iface_index: u32,
+        router_id: [u8; 8],
+        config: BabelConfig,
+    ) -> io::Result<Self> {
+        let socket = Packet::bind_reusable((Ipv6Addr::UNSPECIFIED, BABEL_PORT).into())?;
+        Self::with_v6_multicast_socket(socket, iface_index, router_id, config)
+    }
+
+    /// IPv6 counterpart of `with_v4_multicast_socket`.
+    fn with_v6_multicast_socket(
+        socket: UdpSocket,
+        iface_index: u32,
+        router_id: [u8; 8],
+        config: BabelConfig,
+    ) -> io::Result<Self> {
+        let mut config = config;
+        Self::join_or_fall_back(&mut config, Packet::join_multicast_v6(&socket, iface_index))?;
+        Self::with_socket(socket, iface_index, router_id, config)
+    }
+
+    /// Create a dual-stack Babel node, joined to IPv4 multicast on the
+    /// interface with address `v4_iface` and to IPv6 multicast on the
+    /// interface with index `v6_iface_index`.
+    ///
+    /// The node listens on one socket per family; replies go out on the
+    /// socket of the destination's family.
+    pub fn new_dual_stack(
+        v4_iface: Ipv4Addr,
+        v6_iface_index: u32,
+        router_id: [u8; 8],
+        config: BabelConfig,
+    ) -> io::Result<Self> {
+        let mut config = config;
+        let v4 = Packet::bind_reusable((Ipv4Addr::UNSPECIFIED, BABEL_PORT).into())?;
+        let v6 = Packet::bind_reusable_v6_only((Ipv6Addr::UNSPECIFIED, BABEL_PORT).into())?;
+        Self::join_or_fall_back(&mut config, Packet::join_multicast_v4(&v4, v4_iface))?;
+        Self::join_or_fall_back(&mut config, Packet::join_multicast_v6(&v6, v6_iface_index))?;
+
+        if !v4_iface.is_unspecified() {
+            config.local_addresses.push(IpAddr::V4(v4_iface));
+        }
+        Self::with_sockets(vec![v4, v6], v6_iface_index, router_id, config)
+    }
+
+    /// Handle the outcome of a multicast join: a failure is fatal unless
+    /// unicast peers are configured and multicast isn't required, in which
+    /// case multicast is switched off.
+    fn join_or_fall_back(config: &mut BabelConfig, joined: io::Result<()>) -> io::Result<()> {
+        if let Err(e) = joined {
+            if config.require_multicast || config.unicast_peers.is_empty() {
+                return Err(e);
+            }
+            eprintln!("[BabelNode] multicast join failed ({e}); continuing unicast-only");
+            config.multicast = false;
+        }
+        Ok(())
+    }
+
+    /// Create a Babel node on an already-bound socket.
+    ///
+    /// The socket is switched to non-blocking mode. Useful for tests or
+    /// setups that need custom socket options.
+    pub fn with_socket(
+        socket: UdpSocket,
+        iface_index: u32,
+        router_id: [u8; 8],
+        config: BabelConfig,
+    ) -> io::Result<Self> {
+        Self::with_sockets(vec![socket], iface_index, router_id, config)
+    }
+
+    /// Create a Babel node listening on several already-bound sockets,
+    /// e.g. one IPv4 and one IPv6 socket.
+    ///
+    /// Each destination is sent to from the first socket of its address
+    /// family, or the first socket if none matches. Multicast goes to the
+    /// group of every socket's family.
+    pub fn with_sockets(
+        sockets: Vec<UdpSocket>,
+        iface_index: u32,
+        router_id: [u8; 8],
+        config: BabelConfig,
+    ) -> io::Result<Self> {
+        if sockets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a node needs at least one socket",
+            ));
+        }
+
+        let mut v6 = false;
+        for socket in &sockets {
+            socket.set_nonblocking(true)?;
+            Packet::set_multicast_ttl(socket, config.multicast_ttl)?;
+            if let Some(name) = &config.bind_device {
+                Packet::bind_to_device(socket, name)?;
+            }
+            v6 |= socket.local_addr()?.is_ipv6();
+        }
+
+        let mtu = config.mtu.unwrap_or_else(|| {
+            packet::interface_name(iface_index)
+                .and_then(|name| packet::interface_mtu(&name))
+                .unwrap_or(packet::DEFAULT_MTU)
+        });
+
+        let hello_interval = Duration::from_millis(config.hello_interval_ms as u64);
+        let ihu_interval = Duration::from_millis(config.ihu_interval_ms as u64);
+        let update_interval = Duration::from_millis(config.update_interval_ms as u64);
+        let jitter_rng = config.jitter.then(|| match config.jitter_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        });
 
         let mut node = BabelNode {
-            socket,
+            sockets,
+            next_recv: Cell::new(0),
             router_id,
             seqno: 1,
-            hello_interval: Duration::from_millis(config.hello_interval_ms as u64),
+            route_seqno: config.initial_seqno,
+            route_seqno_interval: config
+                .route_seqno_interval_ms
+                .map(|ms| Duration::from_millis(ms as u64)),
+            last_route_seqno_bump: None,
+            last_gc: None,
+            timestamps: config.timestamps,
+            clock_epoch: Instant::now(),
+            hello_interval,
             last_hello: None,
-            ihu_interval: Duration::from_millis(config.ihu_interval_ms as u64),
+            hello_wait: hello_interval,
+            ihu_interval,
             last_ihu: None,
-            update_interval: Duration::from_millis(config.update_interval_ms as u64),
+            ihu_wait: ihu_interval,
+            update_interval,
             last_update_advert: None,
-            advertised_prefixes: config.advertised_prefixes,
+            update_wait: update_interval,
+            jitter_rng,
+            advertised_prefixes: config.effective_advertised_prefixes(),
+            local_addresses: config.effective_local_addresses(),
+            max_payload: packet::udp_payload_size(mtu, v6),
+            update_dedup_window: Duration::from_millis(config.update_dedup_window_ms as u64),
+            last_triggered: HashMap::new(),
+            triggered_pending: Vec::new(),
+            pending_acks: Vec::new(),
+            awaited_acks: Vec::new(),
+            next_ack_opaque: 0,
+            send_queue: Vec::new(),
+            link_cost_overrides: HashMap::new(),
+            metric_computer: Box::new(AdditiveMetric),
+            holddowns: HashMap::new(),
+            require_link_local_source: config.require_link_local_source,
+            dropped_non_link_local: 0,
+            counters: NodeMetrics::default(),
+            trace: config
+                .packet_trace
+                .as_ref()
+                .map(PcapWriter::open)
+                .transpose()?,
+            multicast: config.multicast,
+            unicast_peers: config.unicast_peers,
+            split_horizon: config.split_horizon,
+            stale_multiplier: config.stale_multiplier,
+            hmac_key: config.hmac_key,
+            pc: Cell::new(0),
+            pc_index: Cell::new(rand::random()),
+            neighbor_pcs: HashMap::new(),
             iface_index,
-            neighbors: NeighborTable::new(),
-            routes: RoutingTable::new(),
-            source_info: HashMap::new(),
+            neighbors: NeighborTable::with_cost_strategy(config.link_cost_strategy),
+            routes: RoutingTable::with_hysteresis_margin(config.hysteresis_margin),
             events: Vec::new(),
+            prefix_subscribers: Vec::new(),
+            event_callbacks: Vec::new(),
         };
 
+        node.neighbors
+            .set_reachability_window(config.reachability_window);
         // Register our own advertised prefixes as local routes on startup.
         node.install_local_advertised_routes();
 
@@ -145,31 +772,176 @@ impl BabelNode {
 
     /// One non-blocking iteration of the node: send timers, receive, prune.
     pub fn poll(&mut self) -> io::Result<()> {
-        if let Err(e) = self.maybe_send_hello() {
+        self.poll_at(Instant::now())
+    }
+
+    /// Like [`poll`](Self::poll), but with an explicit notion of "now".
+    ///
+    /// Pending Acks are flushed before any other timer so that their
+    /// deadlines are honored even when the periodic sends are slow.
+    pub fn poll_at(&mut self, now: Instant) -> io::Result<()> {
+        self.maybe_bump_route_seqno(now);
+
+        if let Err(e) = self.flush_pending_acks(now) {
+            eprintln!("[BabelNode] error sending Ack: {e}");
+        }
+
+        self.queue_triggered_updates(now);
+        self.queue_retransmissions(now);
+        if let Err(e) = self.flush_send_queue() {
+            eprintln!("[BabelNode] error sending queued packet: {e}");
+        }
+
+        if let Err(e) = self.maybe_send_hello_at(now) {
             eprintln!("[BabelNode] error sending hello: {e}");
         }
 
-        if let Err(e) = self.maybe_send_ihus() {
+        if let Err(e) = self.maybe_send_ihus_at(now) {
             eprintln!("[BabelNode] error sending IHU: {e}");
         }
 
-        if let Err(e) = self.maybe_send_updates() {
+        if let Err(e) = self.maybe_send_updates_at(now) {
             eprintln!("[BabelNode] error sending Update: {e}");
         }
 
         if let Some((tlvs, src)) = self.recv_once()? {
-            self.handle_tlvs_from(src, &tlvs);
+            self.handle_tlvs_at(src, &tlvs, now);
         }
 
         // Neighbor pruning => NeighborDown events
-        let now = Instant::now();
-        for addr in self.neighbors.prune_stale_with_addrs(now, 3) {
-            self.push_event(Event::NeighborDown(addr));
-        }
+        self.prune_stale_neighbors(now);
+
+        self.expire_routes(now);
+
+        self.maybe_gc(now);
 
         Ok(())
     }
 
+    /// Fire all timers due at `now` and return the packets to send.
+    ///
+    /// Unlike [`poll_at`](Self::poll_at) this never touches the socket, so
+    /// the node can be driven by a caller that does its own I/O; received
+    /// packets are fed back through [`ingest`](Self::ingest).
+    pub fn tick(&mut self, now: Instant) -> Vec<OutgoingPacket> {
+        self.maybe_bump_route_seqno(now);
+
+        let mut out = Vec::new();
+
+        let mut acks = std::mem::take(&mut self.pending_acks);
+        acks.sort_by_key(|a| a.deadline);
+        for ack in acks {
+            out.push(OutgoingPacket {
+                dest: ack.dest,
+                bytes: Packet::build_ack(ack.opaque)
+                    .to_bytes()
+                    .expect("an Ack fits"),
+            });
+        }
+        self.queue_triggered_updates(now);
+        self.queue_retransmissions(now);
+        out.append(&mut self.send_queue);
+
+        let dests = self.destinations();
+
+        if is_due(self.last_hello, self.hello_wait, now) {
+            if let Some(bytes) = encode(&Packet::with_tlvs(vec![self.hello_tlv(now)])) {
+                for dest in &dests {
+                    out.push(OutgoingPacket {
+                        dest: *dest,
+                        bytes: bytes.clone(),
+                    });
+                }
+            }
+            self.seqno = self.seqno.wrapping_add(1);
+            self.last_hello = Some(now);
+            self.hello_wait = self.jittered(self.hello_interval);
+            self.counters.hellos_sent += 1;
+        }
+
+        if self.neighbors.all().next().is_some() && is_due(self.last_ihu, self.ihu_wait, now) {
+            for bytes in self.ihu_packets().iter().filter_map(encode) {
+                for dest in &dests {
+                    out.push(OutgoingPacket {
+                        dest: *dest,
+                        bytes: bytes.clone(),
+                    });
+                }
+            }
+            self.last_ihu = Some(now);
+            self.ihu_wait = self.jittered(self.ihu_interval);
+        }
+
+        if !self.advertised_prefixes.is_empty()
+            && is_due(self.last_update_advert, self.update_wait, now)
+        {
+            for bytes in self.static_update_packets().iter().filter_map(encode) {
+                for dest in &dests {
+                    out.push(OutgoingPacket {
+                        dest: *dest,
+                        bytes: bytes.clone(),
+                    });
+                }
+            }
+            self.last_update_advert = Some(now);
+            self.update_wait = self.jittered(self.update_interval);
+        }
+
+        self.prune_stale_neighbors(now);
+
+        self.expire_routes(now);
+
+        self.maybe_gc(now);
+
+        if self.hmac_key.is_some() {
+            for pkt in &mut out {
+                pkt.bytes = self.signed(&pkt.bytes, pkt.dest);
+            }
+        }
+        self.counters.packets_sent += out.len() as u64;
+        out
+    }
+
+    /// Feed a packet received from `src` by the caller's own I/O.
+    ///
+    /// Same as [`handle_packet_bytes`](Self::handle_packet_bytes).
+    pub fn ingest(&mut self, raw: &[u8], src: SocketAddr) -> Result<(), String> {
+        self.handle_packet_bytes(raw, src)
+    }
+
+    /// Derive a router-id from the MAC address of a network interface.
+    ///
+    /// Reads the hardware address from sysfs (Linux) and expands it to a
+    /// modified EUI-64, the same convention babeld uses.
+    pub fn router_id_from_mac(iface: &str) -> io::Result<[u8; 8]> {
+        let path = format!("/sys/class/net/{iface}/address");
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::router_id_from_mac_bytes(parse_mac(&text)?))
+    }
+
+    /// Expand a 48-bit MAC address into a modified EUI-64 router-id.
+    pub fn router_id_from_mac_bytes(mac: [u8; 6]) -> [u8; 8] {
+        [
+            mac[0] ^ 0x02,
+            mac[1],
+            mac[2],
+            0xff,
+            0xfe,
+            mac[3],
+            mac[4],
+            mac[5],
+        ]
+    }
+
+    /// Derive a router-id from the interface identifier (low 64 bits) of an
+    /// IPv6 address, e.g. a link-local address.
+    pub fn router_id_from_v6(addr: Ipv6Addr) -> [u8; 8] {
+        let octets = addr.octets();
+        let mut router_id = [0u8; 8];
+        router_id.copy_from_slice(&octets[8..]);
+        router_id
+    }
+
     /// Current router-id of this node.
     pub fn router_id(&self) -> [u8; 8] {
         self.router_id
@@ -179,11 +951,129 @@ impl BabelNode {
         self.seqno
     }
 
+    /// Seqno carried by the Updates for our own prefixes.
+    pub fn route_seqno(&self) -> u16 {
+        self.route_seqno
+    }
+
+    /// Increase the seqno of our own routes and re-install them with it.
+    pub fn bump_route_seqno(&mut self) {
+        self.route_seqno = self.route_seqno.wrapping_add(1);
+        self.install_local_advertised_routes();
+    }
+
+    /// Write our route seqno to `path`, so a restarted node can pick up
+    /// where this one left off with [`load_seqno`](Self::load_seqno).
+    pub fn save_seqno<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, format!("{}\n", self.route_seqno))
+    }
+
+    /// Restore the route seqno saved at `path` and bump it once, so our
+    /// next Updates are newer than any sent since the save.
+    ///
+    /// The restored seqno replaces the current one even if it looks
+    /// older, e.g. after wrapping around.
+    pub fn load_seqno<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let seqno: u16 = text
+            .trim()
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed seqno"))?;
+        // Our routes at the current seqno would win over the restored one.
+        for p in &self.advertised_prefixes {
+            self.routes
+                .remove(&p.key(), self.router_id, None, self.iface_index);
+        }
+        self.route_seqno = seqno;
+        self.bump_route_seqno();
+        Ok(())
+    }
+
+    /// Bump the route seqno if the configured interval has elapsed.
+    fn maybe_bump_route_seqno(&mut self, now: Instant) {
+        let interval = match self.route_seqno_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        match self.last_route_seqno_bump {
+            None => self.last_route_seqno_bump = Some(now),
+            Some(last) if now.duration_since(last) >= interval => {
+                self.bump_route_seqno();
+                self.last_route_seqno_bump = Some(now);
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// The node's (first) socket, for drivers doing their own I/O on it.
+    #[cfg(feature = "async")]
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        &self.sockets[0]
+    }
+
+    /// The shortest of the Hello, IHU and Update intervals.
+    #[cfg(feature = "async")]
+    pub(crate) fn shortest_interval(&self) -> Duration {
+        self.hello_interval
+            .min(self.ihu_interval)
+            .min(self.update_interval)
+    }
+
+    /// Snapshot of the node's neighbor and route counts and traffic
+    /// counters, e.g. to export with
+    /// [`NodeMetrics::to_prometheus_text`].
+    pub fn metrics(&self) -> NodeMetrics {
+        let mut keys: Vec<&RouteKey> = Vec::new();
+        for r in self.routes.all() {
+            if !keys.contains(&&r.key) {
+                keys.push(&r.key);
+            }
+        }
+
+        NodeMetrics {
+            neighbors_total: self.neighbors.all().count() as u64,
+            neighbors_reachable: self.reachable_neighbors().count() as u64,
+            routes_total: self.routes.all().len() as u64,
+            routes_best: keys
+                .into_iter()
+                .filter(|k| self.routes.best_route(k).is_some())
+                .count() as u64,
+            routes_retracted: self
+                .routes
+                .all()
+                .iter()
+                .filter(|r| r.is_retracted())
+                .count() as u64,
+            ..self.counters.clone()
+        }
+    }
+
+    /// Number of packets dropped because of a non-link-local IPv6 source
+    /// (see [`BabelConfig::require_link_local_source`]).
+    pub fn dropped_non_link_local(&self) -> u64 {
+        self.dropped_non_link_local
+    }
+
+    /// Largest UDP payload (Babel header included) we put in one datagram.
+    ///
+    /// Derived from the interface MTU minus IP/UDP headers, unless
+    /// overridden with [`BabelConfig::mtu`].
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
     /// Immutable view of all known neighbors.
     pub fn neighbors(&self) -> impl Iterator<Item = &crate::neighbor::Neighbor> {
         self.neighbors.all()
     }
 
+    /// Neighbors we heard a Hello from recently enough to route through.
+    pub fn reachable_neighbors(&self) -> impl Iterator<Item = &crate::neighbor::Neighbor> {
+        self.neighbors
+            .reachable(self.neighbors.reachability_window())
+    }
+
     /// Immutable view of all known routes.
     pub fn routes(&self) -> &[crate::routing::Route] {
         self.routes.all()
@@ -194,359 +1084,3912 @@ impl BabelNode {
         self.routes.best_route(key)
     }
 
-    /// Send a multicast Hello.
-    pub fn send_hello(&mut self) -> io::Result<usize> {
-        let flags: u16 = 0;
+    /// The Hello TLV we would send at `now`.
+    fn hello_tlv(&self, now: Instant) -> Tlv {
         let interval_ms: u16 = self
             .hello_interval
             .as_millis()
             .try_into()
             .unwrap_or(u16::MAX);
 
-        let pkt = Packet::build_hello(flags, self.seqno, interval_ms);
-        let dest: SocketAddr = (MULTICAST_V4_ADDR, BABEL_PORT).into();
-
-        let buf = pkt.to_bytes();
-        let sent_bytes = self.socket.send_to(&buf, dest)?;
-
-        self.seqno = self.seqno.wrapping_add(1);
-        self.last_hello = Some(Instant::now());
-        Ok(sent_bytes)
-    }
+        let mut sub_tlvs = Vec::new();
+        if self.timestamps {
+            sub_tlvs.push(SubTlv::Timestamp {
+                origin: self.timestamp_us(now),
+                receive: None,
+            });
+        }
 
-    /// Send a Hello if enough time has passed.
-    pub fn maybe_send_hello(&mut self) -> io::Result<Option<usize>> {
-        let now = Instant::now();
-        match self.last_hello {
-            None => {
-                let n = self.send_hello()?;
-                Ok(Some(n))
-            }
-            Some(last) if now.duration_since(last) >= self.hello_interval => {
-                let n = self.send_hello()?;
-                Ok(Some(n))
-            }
-            Some(_) => Ok(None),
+        Tlv::Hello {
+            flags: 0,
+            seqno: self.seqno,
+            interval: interval_ms,
+            sub_tlvs,
         }
     }
 
-    /// Send IHUs to all known neighbors.
-    fn send_ihus(&mut self) -> io::Result<usize> {
-        let mut total_bytes = 0usize;
+    /// Our clock for RFC 9616 timestamps: microseconds, wrapping.
+    fn timestamp_us(&self, now: Instant) -> u32 {
+        now.saturating_duration_since(self.clock_epoch).as_micros() as u32
+    }
 
+    /// The IHU TLV we would send to neighbor `n` right now.
+    fn ihu_tlv(&self, n: &Neighbor) -> Tlv {
         let interval_ms: u16 = self.ihu_interval.as_millis().try_into().unwrap_or(u16::MAX);
-        let rxcost: u16 = 256;
+        // How well we hear the neighbor's Hellos, so it learns about loss
+        // on its side of the link.
+        let rxcost = n.txcost.unwrap_or_else(|| n.compute_txcost(COST_WINDOW));
 
-        for n in self.neighbors.all() {
-            let ip = n.addr.ip();
-            let (ae, addr_opt) = match ip {
-                IpAddr::V4(v4) => (1u8, Some(IpAddr::V4(v4))),
-                IpAddr::V6(v6) => (2u8, Some(IpAddr::V6(v6))),
-            };
+        let ip = n.addr.ip();
+        let (ae, addr) = match ip {
+            IpAddr::V4(v4) => (1u8, Some(IpAddr::V4(v4))),
+            IpAddr::V6(v6) => (2u8, Some(IpAddr::V6(v6))),
+        };
 
-            let pkt = Packet::build_ihu(ae, rxcost, interval_ms, addr_opt);
-            let buf = pkt.to_bytes();
-            total_bytes += self.socket.send_to(&buf, n.addr)?;
+        let mut sub_tlvs = Vec::new();
+        if self.timestamps
+            && let Some((origin, receive)) = n.hello_timestamp
+        {
+            sub_tlvs.push(SubTlv::Timestamp {
+                origin,
+                receive: Some(receive),
+            });
         }
 
-        Ok(total_bytes)
-    }
-
-    /// Send IHUs if enough time has passed.
-    pub fn maybe_send_ihus(&mut self) -> io::Result<Option<usize>> {
-        if self.neighbors.all().next().is_none() {
-            return Ok(None);
+        Tlv::Ihu {
+            ae,
+            rxcost,
+            interval: interval_ms,
+            addr,
+            sub_tlvs,
         }
+    }
 
-        let now = Instant::now();
-        match self.last_ihu {
-            None => {
-                let n = self.send_ihus()?;
-                self.last_ihu = Some(now);
-                Ok(Some(n))
-            }
-            Some(last) if now.duration_since(last) >= self.ihu_interval => {
-                let n = self.send_ihus()?;
-                self.last_ihu = Some(now);
-                Ok(Some(n))
-            }
-            Some(_) => Ok(None),
-        }
+    /// The IHUs for all known neighbors, packed into as few packets as fit
+    /// in `max_payload`. Each IHU carries its neighbor's address, so they
+    /// all go to every destination.
+    fn ihu_packets(&self) -> Vec<Packet> {
+        Packet::pack_within(
+            self.neighbors.all().map(|n| self.ihu_tlv(n)).collect(),
+            self.max_payload.saturating_sub(Packet::HEADER_LEN),
+        )
     }
 
-    /// Send Updates for statically configured prefixes (multicast).
-    fn send_static_updates(&mut self) -> io::Result<usize> {
-        if self.advertised_prefixes.is_empty() {
-            return Ok(0);
-        }
+    /// Whether an IHU for `addr` is meant for us (RFC 8966 §4.6.9): it has
+    /// no address, or one of ours. Without a known address of its family,
+    /// e.g. on a wildcard socket, we can't tell and take it.
+    fn ihu_is_for_us(&self, addr: Option<IpAddr>) -> bool {
+        let Some(addr) = addr else {
+            return true;
+        };
+        let mut ours = self
+            .local_addresses
+            .iter()
+            .copied()
+            .chain(
+                self.sockets
+                    .iter()
+                    .filter_map(|s| s.local_addr().ok())
+                    .map(|local| local.ip())
+                    .filter(|ip| !ip.is_unspecified()),
+            )
+            .filter(|ip| ip.is_ipv4() == addr.is_ipv4())
+            .peekable();
+        ours.peek().is_none() || ours.any(|ip| ip == addr)
+    }
 
-        let mut total_bytes = 0usize;
+    /// Update TLVs for all statically advertised prefixes.
+    fn static_update_tlvs(&self) -> Vec<Tlv> {
         let interval_ms: u16 = self
             .update_interval
             .as_millis()
             .try_into()
             .unwrap_or(u16::MAX);
 
-        let dest: SocketAddr = (MULTICAST_V4_ADDR, BABEL_PORT).into();
+        self.advertised_prefixes
+            .iter()
+            .map(|p| self.prefix_update_tlv(p, p.metric, interval_ms))
+            .collect()
+    }
 
-        for p in &self.advertised_prefixes {
-            // Build RouterId + Update in the same packet
-            let router_tlv = Tlv::RouterId {
-                router_id: self.router_id,
-                sub_tlvs: Vec::new(),
-            };
+    /// Update TLV announcing our own prefix `p` with `metric`.
+    fn prefix_update_tlv(&self, p: &AdvertisedPrefix, metric: u16, interval_ms: u16) -> Tlv {
+        Tlv::Update {
+            ae: p.ae,
+            flags: 0,
+            plen: p.plen,
+            omitted: 0,
+            interval: interval_ms,
+            seqno: self.route_seqno,
+            metric,
+            prefix: p.prefix.clone(),
+            sub_tlvs: Vec::new(),
+        }
+    }
 
-            let update_tlv = Tlv::Update {
-                ae: p.ae,
-                flags: 0,
-                plen: p.plen,
-                omitted: 0,
-                interval: interval_ms,
-                seqno: self.seqno,
-                metric: p.metric,
-                prefix: p.prefix.clone(),
-                sub_tlvs: Vec::new(),
-            };
+    /// The packets `send_static_updates` puts on the wire, without I/O.
+    fn static_update_packets(&self) -> Vec<Packet> {
+        self.pack_updates(self.static_update_tlvs())
+    }
+
+    /// The packets `shutdown` puts on the wire: a retraction of each of our
+    /// own prefixes.
+    fn retraction_packets(&self) -> Vec<Packet> {
+        self.pack_updates(
+            self.advertised_prefixes
+                .iter()
+                .map(|p| self.prefix_update_tlv(p, INFINITY, 0))
+                .collect(),
+        )
+    }
+
+    /// Pack Updates for our own prefixes into as few packets as fit in
+    /// `max_payload`. Each packet starts with our RouterId and has a NextHop
+    /// before its first Update of each family we know a local address for,
+    /// so it can be understood on its own.
+    fn pack_updates(&self, updates: Vec<Tlv>) -> Vec<Packet> {
+        let router_tlv = Tlv::RouterId {
+            router_id: self.router_id,
+            sub_tlvs: Vec::new(),
+        };
+        // Room taken in every packet by the header, RouterId and NextHops.
+        let overhead = Packet::HEADER_LEN
+            + router_tlv.wire_len()
+            + [true, false]
+                .into_iter()
+                .filter_map(|v4| self.local_next_hop(v4))
+                .map(|tlv| tlv.wire_len())
+                .sum::<usize>();
 
-            let pkt = Packet::with_tlvs(vec![router_tlv, update_tlv]);
-            let buf = pkt.to_bytes();
-            total_bytes += self.socket.send_to(&buf, dest)?;
+        Packet::pack_within(updates, self.max_payload.saturating_sub(overhead))
+            .into_iter()
+            .map(|chunk| {
+                let mut tlvs = vec![router_tlv.clone()];
+                tlvs.extend(chunk.tlvs().iter().cloned());
+                Packet::with_tlvs(self.with_next_hops(tlvs))
+            })
+            .collect()
+    }
+
+    /// Insert a NextHop TLV with our local address of the right family
+    /// before the first Update of each address family in `tlvs`.
+    ///
+    /// Families for which we know no local address are left alone, so the
+    /// receiver falls back to the packet's source address.
+    fn with_next_hops(&self, tlvs: Vec<Tlv>) -> Vec<Tlv> {
+        let mut out = Vec::with_capacity(tlvs.len() + 2);
+        let mut v4_done = false;
+        let mut v6_done = false;
+
+        for tlv in tlvs {
+            if let Tlv::Update { ae, .. } = tlv {
+                let (done, wanted_v4) = match ae_family(ae) {
+                    Some(AddressFamily::V4) => (&mut v4_done, true),
+                    Some(AddressFamily::V6) => (&mut v6_done, false),
+                    None => {
+                        out.push(tlv);
+                        continue;
+                    }
+                };
+
+                if !*done {
+                    *done = true;
+                    out.extend(self.local_next_hop(wanted_v4));
+                }
+            }
+            out.push(tlv);
         }
 
-        // Bump seqno once per batch
-        self.seqno = self.seqno.wrapping_add(1);
-        Ok(total_bytes)
+        out
     }
 
-    /// Send static Updates if enough time has passed.
-    pub fn maybe_send_updates(&mut self) -> io::Result<Option<usize>> {
-        if self.advertised_prefixes.is_empty() {
-            return Ok(None);
+    /// NextHop TLV for our first local address of the given family, if any.
+    fn local_next_hop(&self, v4: bool) -> Option<Tlv> {
+        let addr = self
+            .local_addresses
+            .iter()
+            .find(|a| a.is_ipv4() == v4)
+            .copied()?;
+        Some(Tlv::NextHop {
+            ae: if v4 { 1 } else { 2 },
+            addr: Some(addr),
+            sub_tlvs: Vec::new(),
+        })
+    }
+
+    /// Assemble everything we multicast in one periodic cycle, without I/O.
+    ///
+    /// The packet holds a Hello, one IHU per known neighbor, and, if we
+    /// advertise anything, a RouterId followed by our (prefix-compressed)
+    /// Updates.
+    pub fn build_periodic_packet(&self) -> Packet {
+        self.build_periodic_packet_at(Instant::now())
+    }
+
+    /// Like [`build_periodic_packet`](Self::build_periodic_packet), with an
+    /// explicit "now" for the Hello's timestamp.
+    pub fn build_periodic_packet_at(&self, now: Instant) -> Packet {
+        let mut pkt = Packet::new();
+        pkt.add_tlv(self.hello_tlv(now));
+
+        for n in self.neighbors.all() {
+            pkt.add_tlv(self.ihu_tlv(n));
         }
 
-        let now = Instant::now();
-        match self.last_update_advert {
-            None => {
-                let n = self.send_static_updates()?;
-                self.last_update_advert = Some(now);
-                Ok(Some(n))
+        let updates = Packet::compress_updates(self.static_update_tlvs());
+        if !updates.is_empty() {
+            pkt.add_tlv(Tlv::RouterId {
+                router_id: self.router_id,
+                sub_tlvs: Vec::new(),
+            });
+            for tlv in self.with_next_hops(updates) {
+                pkt.add_tlv(tlv);
             }
-            Some(last) if now.duration_since(last) >= self.update_interval => {
-                let n = self.send_static_updates()?;
-                self.last_update_advert = Some(now);
-                Ok(Some(n))
+        }
+
+        pkt
+    }
+
+    /// Where our Hellos and Updates go: the multicast group (unless
+    /// disabled) plus every configured unicast peer.
+    fn destinations(&self) -> Vec<SocketAddr> {
+        let mut dests = Vec::with_capacity(self.unicast_peers.len() + 2);
+        if self.multicast {
+            for socket in &self.sockets {
+                let group: SocketAddr = match socket.local_addr() {
+                    // ff02::6 is link-scoped, so it needs the interface.
+                    Ok(local) if local.is_ipv6() => {
+                        SocketAddrV6::new(MULTICAST_V6_ADDR, BABEL_PORT, 0, self.iface_index).into()
+                    }
+                    _ => (MULTICAST_V4_ADDR, BABEL_PORT).into(),
+                };
+                if !dests.contains(&group) {
+                    dests.push(group);
+                }
             }
-            Some(_) => Ok(None),
         }
+        dests.extend(self.unicast_peers.iter().copied());
+        dests
     }
 
-    /// Receive one packet (non-blocking).
-    pub fn recv_once(&self) -> io::Result<Option<(Vec<Tlv>, SocketAddr)>> {
-        let mut buf = [0u8; 1500];
+    /// Send `buf` to every destination, returning the total bytes sent.
+    ///
+    /// All destinations are tried; the first error (if any) is returned.
+    fn send_to_all(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut total_bytes = 0usize;
+        let mut first_err = None;
+
+        for dest in self.destinations() {
+            match self.send_raw(buf, dest) {
+                Ok(n) => {
+                    total_bytes += n;
+                    self.counters.packets_sent += 1;
+                }
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
 
-        match Packet::recv(&self.socket, &mut buf) {
-            Ok((tlvs, src)) => Ok(Some((tlvs, src))),
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
-            Err(e) => Err(e),
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(total_bytes),
         }
     }
 
-    /// Helper: install a route into the table and emit RouteUpdated / BestRouteChanged events.
-    fn install_route_and_emit_events(&mut self, key: RouteKey, route: Route) {
-        let old_best = self.routes.best_route(&key).cloned();
+    /// Send several packets to `dest`; returns how many datagrams went out.
+    ///
+    /// Stops at the first hard error. If the socket would block, the packet
+    /// and all that follow are queued for the next poll and the count sent
+    /// so far is returned.
+    pub fn send_packets(&mut self, packets: Vec<Packet>, dest: SocketAddr) -> io::Result<usize> {
+        let mut sent = 0usize;
 
-        let changed = self.routes.install_or_update(route);
-        if !changed {
-            return;
+        let mut iter = packets.into_iter();
+        while let Some(pkt) = iter.next() {
+            let bytes = pkt.to_bytes()?;
+            match self.send_raw(&bytes, dest) {
+                Ok(_) => {
+                    sent += 1;
+                    self.counters.packets_sent += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.send_queue.push(OutgoingPacket { dest, bytes });
+                    self.send_queue.extend(
+                        iter.filter_map(|pkt| encode(&pkt))
+                            .map(|bytes| OutgoingPacket { dest, bytes }),
+                    );
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
         }
 
-        if let Some(best) = self.routes.best_route(&key).cloned() {
-            // RouteUpdated: some path for this key changed (we expose the current best).
-            self.push_event(Event::RouteUpdated(key.clone(), best.clone()));
+        Ok(sent)
+    }
 
-            // Did the best route actually change?
-            let best_changed = match old_best {
-                None => true,
-                Some(ref old) => {
-                    old.metric != best.metric
-                        || old.seqno != best.seqno
-                        || old.router_id != best.router_id
-                        || old.next_hop != best.next_hop
-                }
-            };
+    /// Send the queued packets, e.g. those [`send_packets`](Self::send_packets)
+    /// couldn't send yet.
+    ///
+    /// If the socket would block, the rest stays queued for the next poll.
+    /// A packet failing with any other error is dropped and counted, so one
+    /// unreachable destination doesn't hold up the queue; the first such
+    /// error is returned once the others are sent.
+    fn flush_send_queue(&mut self) -> io::Result<usize> {
+        let mut sent = 0usize;
+        let mut first_err = None;
 
-            if best_changed {
-                self.push_event(Event::BestRouteChanged(key.clone(), best.clone()));
+        let queue = std::mem::take(&mut self.send_queue);
+        let mut iter = queue.into_iter();
+        while let Some(pkt) = iter.next() {
+            match self.send_raw(&pkt.bytes, pkt.dest) {
+                Ok(_) => {
+                    sent += 1;
+                    self.counters.packets_sent += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.send_queue.push(pkt);
+                    self.send_queue.extend(iter);
+                    break;
+                }
+                Err(e) => {
+                    self.counters.send_errors += 1;
+                    first_err.get_or_insert(e);
+                }
             }
+        }
 
-            println!(
-                "[BabelNode] new/updated route installed; best now: {}",
-                best.summary()
-            );
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(sent),
         }
     }
 
-    /// Register our own advertised prefixes as local routes.
-    fn install_local_advertised_routes(&mut self) {
-        // Clone prefixes so we don't hold an immutable borrow of `self`
-        // while calling a `&mut self` method.
-        let prefixes = self.advertised_prefixes.clone();
-        let router_id = self.router_id;
-        let iface_index = self.iface_index;
-        let seqno = self.seqno; // starting local seqno for our own routes
+    /// Send a Hello to the multicast group and all unicast peers.
+    pub fn send_hello(&mut self) -> io::Result<usize> {
+        self.send_hello_at(Instant::now())
+    }
 
-        for p in prefixes {
-            let key = RouteKey {
-                ae: p.ae,
-                plen: p.plen,
-                prefix: p.prefix.clone(),
-            };
+    fn send_hello_at(&mut self, now: Instant) -> io::Result<usize> {
+        let pkt = Packet::with_tlvs(vec![self.hello_tlv(now)]);
 
-            let route = Route {
-                key: key.clone(),
-                metric: p.metric,
-                seqno,
-                router_id,
-                next_hop: None,
-                iface_index,
-            };
+        let buf = pkt.to_bytes()?;
+        let sent_bytes = self.send_to_all(&buf)?;
 
-            self.install_route_and_emit_events(key, route);
+        self.seqno = self.seqno.wrapping_add(1);
+        self.last_hello = Some(now);
+        self.counters.hellos_sent += 1;
+        Ok(sent_bytes)
+    }
+
+    /// Send a Hello if enough time has passed.
+    pub fn maybe_send_hello(&mut self) -> io::Result<Option<usize>> {
+        self.maybe_send_hello_at(Instant::now())
+    }
+
+    fn maybe_send_hello_at(&mut self, now: Instant) -> io::Result<Option<usize>> {
+        if !is_due(self.last_hello, self.hello_wait, now) {
+            return Ok(None);
         }
+
+        let n = self.send_hello_at(now)?;
+        self.hello_wait = self.jittered(self.hello_interval);
+        Ok(Some(n))
     }
 
-    /// Process TLVs received from a given source, emitting events as needed.
-    pub fn handle_tlvs_from(&mut self, src: SocketAddr, tlvs: &[Tlv]) {
-        let now = Instant::now();
-        let src_ip = src.ip();
-        let iface_index = self.iface_index;
+    /// Send IHUs to all known neighbors, batched as in
+    /// [`build_periodic_packet`](Self::build_periodic_packet), to the
+    /// multicast group and all unicast peers.
+    fn send_ihus(&mut self) -> io::Result<usize> {
+        let mut total_bytes = 0usize;
 
-        // If we ever get packets that clearly come from ourselves, ignore them.
-        if let Ok(local_addr) = self.socket.local_addr() {
-            if src_ip == local_addr.ip() {
-                // Same IP and same port -> almost certainly self.
-                if src.port() == local_addr.port() {
-                    eprintln!("[BabelNode] ignoring packet from self: {}", src);
-                    return;
-                }
-            }
+        for pkt in self.ihu_packets() {
+            total_bytes += self.send_to_all(&pkt.to_bytes()?)?;
         }
 
-        for tlv in tlvs {
-            match tlv {
-                Tlv::Hello {
-                    seqno, interval, ..
-                } => {
-                    let is_new = self.neighbors.get(&src).is_none();
-                    self.neighbors
-                        .update_on_hello(src, iface_index, *seqno, *interval, now);
+        Ok(total_bytes)
+    }
 
-                    if is_new {
-                        if let Some(n) = self.neighbors.get(&src).cloned() {
-                            self.push_event(Event::NeighborUp(src, n));
-                        }
-                    }
-                }
+    /// Send IHUs if enough time has passed.
+    pub fn maybe_send_ihus(&mut self) -> io::Result<Option<usize>> {
+        self.maybe_send_ihus_at(Instant::now())
+    }
 
-                Tlv::Ihu {
-                    rxcost, interval, ..
-                } => {
-                    self.neighbors
-                        .update_on_ihu(src, iface_index, *rxcost, *interval, now);
-                }
+    fn maybe_send_ihus_at(&mut self, now: Instant) -> io::Result<Option<usize>> {
+        if self.neighbors.all().next().is_none() {
+            return Ok(None);
+        }
 
-                Tlv::RouterId { router_id, .. } => {
-                    let sinfo = self.source_info.entry(src).or_default();
-                    sinfo.router_id = Some(*router_id);
-                }
+        if !is_due(self.last_ihu, self.ihu_wait, now) {
+            return Ok(None);
+        }
 
-                Tlv::NextHop { ae: _, addr, .. } => {
-                    let sinfo = self.source_info.entry(src).or_default();
-                    sinfo.next_hop = addr.or(Some(src_ip));
-                }
+        let n = self.send_ihus()?;
+        self.last_ihu = Some(now);
+        self.ihu_wait = self.jittered(self.ihu_interval);
+        Ok(Some(n))
+    }
 
-                Tlv::Update {
-                    ae,
-                    flags: _,
-                    plen,
-                    omitted: _,
-                    interval: _,
-                    seqno,
-                    metric,
-                    prefix,
-                    sub_tlvs: _,
-                } => {
-                    // This is where we register new routes from *remote routers*.
-                    let router_id_opt = self.source_info.get(&src).and_then(|si| si.router_id);
+    /// Send Updates for statically configured prefixes (multicast and unicast peers).
+    fn send_static_updates(&mut self) -> io::Result<usize> {
+        if self.advertised_prefixes.is_empty() {
+            return Ok(0);
+        }
 
-                    if let Some(router_id) = router_id_opt {
-                        let nexthop_opt = self
-                            .source_info
-                            .get(&src)
-                            .and_then(|si| si.next_hop)
-                            .or(Some(src_ip));
+        let mut total_bytes = 0usize;
 
-                        let key = RouteKey {
-                            ae: *ae,
-                            plen: *plen,
-                            prefix: prefix.clone(),
-                        };
+        for pkt in self.static_update_packets() {
+            let buf = pkt.to_bytes()?;
+            total_bytes += self.send_to_all(&buf)?;
+        }
 
-                        let route = Route {
-                            key: key.clone(),
-                            metric: *metric,
-                            seqno: *seqno,
-                            router_id,
-                            next_hop: nexthop_opt,
-                            iface_index,
-                        };
+        Ok(total_bytes)
+    }
 
-                        self.install_route_and_emit_events(key, route);
-                    } else {
-                        eprintln!(
-                            "[BabelNode] ignoring Update from {}: unknown router-id",
-                            src
-                        );
-                    }
-                }
+    /// Retract all our own prefixes, as babeld does on SIGTERM, so that
+    /// neighbors drop them now rather than when they expire.
+    ///
+    /// Call this right before dropping the node; dropping it closes the
+    /// socket, which also leaves the multicast group.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        for pkt in self.retraction_packets() {
+            self.send_to_all(&pkt.to_bytes()?)?;
+        }
+        Ok(())
+    }
 
-                Tlv::RouteRequest { .. } => {
-                    // TODO: respond with matching Update(s)
-                }
+    /// Send `tlvs` to `dest` in one packet that asks for an Ack, and
+    /// retransmit it every [`ACK_TIMEOUT`] until the Ack arrives, at most
+    /// [`ACK_RETRIES`] times. The packet goes out on the next poll or tick.
+    ///
+    /// Returns the opaque value of the AckRequest. If the TLVs don't
+    /// encode, nothing is sent.
+    pub fn send_with_ack(&mut self, dest: SocketAddr, tlvs: Vec<Tlv>) -> u16 {
+        let opaque = self.next_ack_opaque;
+        self.next_ack_opaque = opaque.wrapping_add(1);
 
-                Tlv::SeqnoRequest { .. } => {
-                    // TODO: respond with appropriate Update
-                }
+        let mut pkt = Packet::build_ack_request(opaque, ACK_TIMEOUT.as_millis() as u16);
+        for tlv in tlvs {
+            pkt.add_tlv(tlv);
+        }
+        let Some(bytes) = encode(&pkt) else {
+            return opaque;
+        };
+        self.send_queue.push(OutgoingPacket {
+            dest,
+            bytes: bytes.clone(),
+        });
+        self.awaited_acks.push(AwaitedAck {
+            dest,
+            opaque,
+            bytes,
+            retry_at: Instant::now() + ACK_TIMEOUT,
+            retries_left: ACK_RETRIES,
+        });
+        opaque
+    }
 
-                _ => {
-                    // Other TLVs currently ignored.
-                }
+    /// Queue another copy of every packet whose Ack is overdue, and give
+    /// up on those out of retries.
+    fn queue_retransmissions(&mut self, now: Instant) {
+        let mut awaited = std::mem::take(&mut self.awaited_acks);
+        awaited.retain_mut(|a| {
+            if now < a.retry_at {
+                return true;
             }
-        }
+            if a.retries_left == 0 {
+                eprintln!("[BabelNode] no Ack {} from {}; giving up", a.opaque, a.dest);
+                return false;
+            }
+            self.send_queue.push(OutgoingPacket {
+                dest: a.dest,
+                bytes: a.bytes.clone(),
+            });
+            a.retries_left -= 1;
+            a.retry_at = now + ACK_TIMEOUT;
+            true
+        });
+        self.awaited_acks = awaited;
     }
 
-    fn push_event(&mut self, ev: Event) {
-        self.events.push(ev);
+    /// Ask neighbors for a route to `key` with a RouteRequest, e.g. on a
+    /// forwarding miss. It goes out on the next poll or tick.
+    pub fn request_route(&mut self, key: &RouteKey) {
+        let pkt = Packet::build_route_request(key.ae, key.plen, key.prefix.clone());
+        self.queue_to_all(&pkt);
     }
 
-    /// Take and return all pending events since the last call.
-    pub fn drain_events(&mut self) -> Vec<Event> {
-        std::mem::take(&mut self.events)
+    /// Ask for a newer seqno of `key` from `router_id` with a SeqnoRequest,
+    /// e.g. to get out of a starvation. It goes out on the next poll or
+    /// tick.
+    ///
+    /// The requested seqno is one more than the newest we know for that
+    /// source, from our routes or feasibility distances; 1 if we know none.
+    pub fn request_seqno(&mut self, key: &RouteKey, router_id: [u8; 8]) {
+        let known = self
+            .routes
+            .routes_for(key)
+            .filter(|r| r.router_id == router_id)
+            .map(|r| r.seqno)
+            .chain(self.routes.sources().get(key, router_id).map(|(s, _)| s))
+            .reduce(|a, b| if seqno_gt(b, a) { b } else { a });
+        let seqno = known.map_or(1, |s| s.wrapping_add(1));
+
+        let pkt = Packet::build_seqno_request(
+            key.ae,
+            key.plen,
+            seqno,
+            SEQNO_REQUEST_HOP_COUNT,
+            router_id,
+            key.prefix.clone(),
+        );
+        self.queue_to_all(&pkt);
     }
 
-    /// Convenience: poll the node and return any events produced.
-    pub fn poll_with_events(&mut self) -> io::Result<Vec<Event>> {
-        self.poll()?;
-        Ok(self.drain_events())
+    /// Send static Updates if enough time has passed.
+    pub fn maybe_send_updates(&mut self) -> io::Result<Option<usize>> {
+        self.maybe_send_updates_at(Instant::now())
     }
 
-    /// Simple blocking event loop for a Babel node (demo mode).
-    pub fn run(&mut self) -> io::Result<()> {
-        println!("[BabelNode] running, router-id = {:?}", self.router_id);
+    fn maybe_send_updates_at(&mut self, now: Instant) -> io::Result<Option<usize>> {
+        if self.advertised_prefixes.is_empty() {
+            return Ok(None);
+        }
 
-        loop {
-            self.poll()?;
-            std::thread::sleep(Duration::from_millis(10));
+        if !is_due(self.last_update_advert, self.update_wait, now) {
+            return Ok(None);
+        }
+
+        let n = self.send_static_updates()?;
+        self.last_update_advert = Some(now);
+        self.update_wait = self.jittered(self.update_interval);
+        Ok(Some(n))
+    }
+
+    /// Wait until the next periodic send: `interval`, or with jitter on, a
+    /// random duration between 75% and 125% of it.
+    fn jittered(&mut self, interval: Duration) -> Duration {
+        match &mut self.jitter_rng {
+            Some(rng) => interval.mul_f64(rng.random_range(0.75..=1.25)),
+            None => interval,
         }
     }
+
+    /// Send a triggered Update for the current best route of `key`.
+    ///
+    /// Returns `Ok(None)` if there is no route for `key` (and none was
+    /// announced that needs retracting), if split horizon
+    /// forbids advertising it, or if an identical Update was already sent
+    /// for it within the dedup window.
+    pub fn send_triggered_update(&mut self, key: &RouteKey) -> io::Result<Option<usize>> {
+        let tlvs = match self.triggered_update_tlvs(key, Instant::now()) {
+            Some(tlvs) => tlvs,
+            None => return Ok(None),
+        };
+
+        let buf = Packet::with_tlvs(tlvs).to_bytes()?;
+        Ok(Some(self.send_to_all(&buf)?))
+    }
+
+    /// Build the TLVs of a triggered Update for `key`, recording it as sent.
+    ///
+    /// With no route left for `key`, this is a retraction of what we last
+    /// announced for it. Returns `None` if there is nothing to announce or
+    /// retract, if split horizon forbids advertising the route, or if the
+    /// Update would duplicate the last one sent for this key within the
+    /// dedup window.
+    fn triggered_update_tlvs(&mut self, key: &RouteKey, now: Instant) -> Option<Vec<Tlv>> {
+        let best = self.routes.best_route(key).cloned();
+
+        let sent = match &best {
+            Some(best) => {
+                // All our Updates leave on `iface_index`, so a route learned
+                // there would only be echoed back toward where it came from.
+                if self.split_horizon
+                    && best.next_hop.is_some()
+                    && best.iface_index == self.iface_index
+                {
+                    return None;
+                }
+                SentUpdate {
+                    router_id: best.router_id,
+                    seqno: best.seqno,
+                    metric: best.metric,
+                    at: now,
+                }
+            }
+            None => {
+                let last = self
+                    .last_triggered
+                    .get(key)
+                    .filter(|last| last.metric != INFINITY)?;
+                SentUpdate {
+                    metric: INFINITY,
+                    at: now,
+                    ..*last
+                }
+            }
+        };
+
+        if let Some(last) = self.last_triggered.get(key)
+            && last.router_id == sent.router_id
+            && last.seqno == sent.seqno
+            && last.metric == sent.metric
+            && now.duration_since(last.at) < self.update_dedup_window
+        {
+            return None;
+        }
+
+        self.last_triggered.insert(key.clone(), sent);
+        // What we announce bounds what we may accept back (feasibility).
+        if let Some(best) = &best {
+            self.routes.note_advertised(best, now);
+        }
+
+        let interval_ms: u16 = self
+            .update_interval
+            .as_millis()
+            .try_into()
+            .unwrap_or(u16::MAX);
+
+        Some(self.with_next_hops(vec![
+            Tlv::RouterId {
+                router_id: sent.router_id,
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: key.ae,
+                flags: 0,
+                plen: key.plen,
+                omitted: 0,
+                interval: interval_ms,
+                seqno: sent.seqno,
+                metric: sent.metric,
+                prefix: key.prefix.clone(),
+                sub_tlvs: Vec::new(),
+            },
+        ]))
+    }
+
+    /// Mark `key` for a triggered Update on the next poll or tick.
+    fn schedule_triggered_update(&mut self, key: &RouteKey) {
+        if !self.triggered_pending.contains(key) {
+            self.triggered_pending.push(key.clone());
+        }
+    }
+
+    /// Move the triggered Updates scheduled since the last poll or tick to
+    /// the send queue. Each goes through the dedup window, so a flapping
+    /// route can't flood the link.
+    fn queue_triggered_updates(&mut self, now: Instant) {
+        for key in std::mem::take(&mut self.triggered_pending) {
+            if let Some(tlvs) = self.triggered_update_tlvs(&key, now) {
+                self.queue_to_all(&Packet::with_tlvs(tlvs));
+            }
+        }
+    }
+
+    /// Put `bytes` on the send queue for every destination.
+    fn queue_to_all(&mut self, pkt: &Packet) {
+        let Some(bytes) = encode(pkt) else {
+            return;
+        };
+        for dest in self.destinations() {
+            self.send_queue.push(OutgoingPacket {
+                dest,
+                bytes: bytes.clone(),
+            });
+        }
+    }
+
+    /// Send every queued Ack to its requester.
+    ///
+    /// Acks are never held past the poll in which they were queued; a
+    /// warning is logged if one is sent after its deadline anyway.
+    fn flush_pending_acks(&mut self, now: Instant) -> io::Result<usize> {
+        let mut total_bytes = 0usize;
+
+        let mut pending = std::mem::take(&mut self.pending_acks);
+        pending.sort_by_key(|a| a.deadline);
+
+        let mut iter = pending.into_iter();
+        while let Some(ack) = iter.next() {
+            if now > ack.deadline {
+                eprintln!(
+                    "[BabelNode] Ack {} to {} is late by {:?}",
+                    ack.opaque,
+                    ack.dest,
+                    now.duration_since(ack.deadline)
+                );
+            }
+
+            let bytes = Packet::build_ack(ack.opaque)
+                .to_bytes()
+                .expect("an Ack fits");
+            match self.send_raw(&bytes, ack.dest) {
+                Ok(n) => {
+                    total_bytes += n;
+                    self.counters.packets_sent += 1;
+                }
+                Err(e) => {
+                    // Keep the failed Ack and everything after it for the next poll.
+                    self.pending_acks.push(ack);
+                    self.pending_acks.extend(iter);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(total_bytes)
+    }
+
+    /// Receive one packet (non-blocking).
+    ///
+    /// With several sockets, each call starts at the socket after the one
+    /// that last had a packet, so all of them get their turn.
+    ///
+    /// A datagram that fails authentication or doesn't parse is counted in
+    /// [`metrics`](Self::metrics) and dropped, returning `Ok(None)`.
+    pub fn recv_once(&mut self) -> io::Result<Option<(Vec<Tlv>, SocketAddr)>> {
+        let mut buf = [0u8; 1500];
+
+        let count = self.sockets.len();
+        let start = self.next_recv.get() % count;
+        for i in (start..count).chain(0..start) {
+            let socket = &self.sockets[i];
+            let (n, src) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            self.next_recv.set(i + 1);
+            if let Ok(local) = socket.local_addr() {
+                self.trace_datagram(src, local, &buf[..n]);
+            }
+            if !self.hmac_ok(&buf[..n], src) {
+                eprintln!("[BabelNode] dropping packet from {src}: HMAC verification failed");
+                self.counters.auth_failures += 1;
+                return Ok(None);
+            }
+
+            return match Packet::from_bytes(&buf[..n]) {
+                Ok(pkt) if !self.pc_fresh(src, pkt.tlvs()) => {
+                    eprintln!("[BabelNode] dropping packet from {src}: replayed");
+                    self.counters.auth_failures += 1;
+                    Ok(None)
+                }
+                Ok(pkt) => Ok(Some((pkt.into_tlvs(), src))),
+                Err(e) => {
+                    eprintln!("[BabelNode] dropping packet from {src}: {e}");
+                    self.counters.parse_errors += 1;
+                    Ok(None)
+                }
+            };
+        }
+        Ok(None)
+    }
+
+    /// The socket to send to `dest` from: the first of its address family,
+    /// or our first socket if there is none.
+    fn socket_for(&self, dest: SocketAddr) -> &UdpSocket {
+        self.sockets
+            .iter()
+            .find(|s| {
+                s.local_addr()
+                    .is_ok_and(|local| local.is_ipv6() == dest.is_ipv6())
+            })
+            .unwrap_or(&self.sockets[0])
+    }
+
+    /// Send `buf` to `dest` on our socket, recording it in the packet trace.
+    ///
+    /// With an HMAC key, the packet is signed on the way out.
+    fn send_raw(&self, buf: &[u8], dest: SocketAddr) -> io::Result<usize> {
+        let signed;
+        let buf = if self.hmac_key.is_some() {
+            signed = self.signed(buf, dest);
+            &signed
+        } else {
+            buf
+        };
+        let socket = self.socket_for(dest);
+        let n = socket.send_to(buf, dest)?;
+        if let Ok(local) = socket.local_addr() {
+            self.trace_datagram(local, dest, buf);
+        }
+        Ok(n)
+    }
+
+    /// Our address as seen by `dest`: the address of the socket we send
+    /// to it from, or a configured local address if that socket is bound
+    /// to the wildcard address.
+    fn source_for(&self, dest: SocketAddr) -> Option<SocketAddr> {
+        let mut local = self.socket_for(dest).local_addr().ok()?;
+        if local.ip().is_unspecified()
+            && let Some(ip) = self
+                .local_addresses
+                .iter()
+                .find(|ip| ip.is_ipv6() == local.is_ipv6())
+        {
+            local.set_ip(*ip);
+        }
+        Some(local)
+    }
+
+    /// `buf` with our next packet counter added to its body and an HMAC
+    /// trailer for our key and `dest` appended.
+    fn signed(&self, buf: &[u8], dest: SocketAddr) -> Vec<u8> {
+        let mut out = buf.to_vec();
+        if let (Some(key), Some(src)) = (&self.hmac_key, self.source_for(dest)) {
+            let (pc, index) = self.next_pc();
+            if let Err(e) = Packet::append_pc(&mut out, pc, &index) {
+                eprintln!("[BabelNode] error adding packet counter: {e}");
+            }
+            Packet::append_hmac(&mut out, key, src, dest);
+        }
+        out
+    }
+
+    /// Take the packet counter and index for our next signed packet.
+    ///
+    /// A new random index starts when the counter wraps, so no (index,
+    /// counter) pair is ever used twice.
+    fn next_pc(&self) -> (u32, [u8; 4]) {
+        let (pc, index) = (self.pc.get(), self.pc_index.get());
+        match pc.checked_add(1) {
+            Some(next) => self.pc.set(next),
+            None => {
+                self.pc.set(0);
+                self.pc_index.set(rand::random());
+            }
+        }
+        (pc, index)
+    }
+
+    /// Whether the authenticated packet with `tlvs` from `src` is not a
+    /// replay, recording its packet counter if so; always true without a
+    /// key.
+    ///
+    /// The packet must carry a [`Tlv::Pc`] newer than the last one seen
+    /// from `src` under the same index. A new index is accepted: the
+    /// challenge RFC 8967 uses to check it is not implemented.
+    fn pc_fresh(&mut self, src: SocketAddr, tlvs: &[Tlv]) -> bool {
+        if self.hmac_key.is_none() {
+            return true;
+        }
+        let Some((pc, index)) = tlvs.iter().find_map(|tlv| match tlv {
+            Tlv::Pc { pc, index } => Some((*pc, index)),
+            _ => None,
+        }) else {
+            return false;
+        };
+
+        let seen = self.neighbor_pcs.entry(src).or_default();
+        match seen.iter_mut().find(|(i, _)| i == index) {
+            Some((_, last)) if pc <= *last => return false,
+            Some((_, last)) => *last = pc,
+            None => seen.push((index.clone(), pc)),
+        }
+        true
+    }
+
+    /// Whether `buf` from `src` carries a valid HMAC for our key; always
+    /// true without a key.
+    ///
+    /// The destination address is covered by the HMAC but not known to
+    /// us, so each address the packet may have been sent to is tried: the
+    /// multicast groups, our sockets' addresses and our local addresses.
+    fn hmac_ok(&self, buf: &[u8], src: SocketAddr) -> bool {
+        let Some(key) = &self.hmac_key else {
+            return true;
+        };
+        let mut dsts: Vec<SocketAddr> = vec![
+            (MULTICAST_V4_ADDR, BABEL_PORT).into(),
+            (MULTICAST_V6_ADDR, BABEL_PORT).into(),
+        ];
+        for local in self.sockets.iter().filter_map(|s| s.local_addr().ok()) {
+            dsts.push(local);
+            dsts.extend(
+                self.local_addresses
+                    .iter()
+                    .map(|ip| SocketAddr::new(*ip, local.port())),
+            );
+        }
+        dsts.into_iter()
+            .any(|dst| Packet::verify_hmac(buf, key, src, dst))
+    }
+
+    /// Append a datagram to the packet trace, if enabled.
+    ///
+    /// A failing trace is logged and otherwise ignored.
+    fn trace_datagram(&self, src: SocketAddr, dst: SocketAddr, buf: &[u8]) {
+        if let Some(trace) = &self.trace
+            && let Err(e) = trace.write_datagram(src, dst, buf)
+        {
+            eprintln!("[BabelNode] error writing packet trace: {e}");
+        }
+    }
+
+    /// Helper: install a route into the table and emit RouteUpdated / BestRouteChanged events.
+    ///
+    /// A change of best route to a learned one also schedules a triggered
+    /// Update; our own prefixes go out with the periodic Updates.
+    fn install_route_and_emit_events(&mut self, key: RouteKey, route: Route) {
+        let outcome = self.routes.install_or_update(route);
+        if outcome.change == RouteChange::Unchanged {
+            return;
+        }
+
+        if let Some(best) = self.routes.best_route(&key).cloned() {
+            // RouteUpdated: some path for this key changed (we expose the current best).
+            self.push_event(Event::RouteUpdated(key.clone(), best.clone()));
+
+            // Did the best route actually change?
+            if outcome.best_changed {
+                self.push_event(Event::BestRouteChanged(key.clone(), best.clone()));
+                if best.next_hop.is_some() {
+                    self.schedule_triggered_update(&key);
+                }
+            }
+
+            println!(
+                "[BabelNode] new/updated route installed; best now: {}",
+                best.summary()
+            );
+        }
+    }
+
+    /// Emit events for keys whose routes were just removed or re-costed.
+    ///
+    /// `old_bests` holds each affected key with its best route from before
+    /// the change. Keys left without any usable route produce
+    /// `RouteRetracted`, keys whose best route differs produce
+    /// `BestRouteChanged`. Both schedule a triggered Update.
+    fn emit_best_route_events(&mut self, old_bests: Vec<(RouteKey, Option<Route>)>) {
+        for (key, old_best) in old_bests {
+            self.routes.reselect(&key);
+            match self.routes.best_route(&key).cloned() {
+                None => {
+                    self.schedule_triggered_update(&key);
+                    self.push_event(Event::RouteRetracted(key));
+                }
+                Some(best) => {
+                    if best_changed(old_best.as_ref(), &best) {
+                        self.schedule_triggered_update(&key);
+                        self.push_event(Event::BestRouteChanged(key, best));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Metric of a route advertised with `advertised` by the neighbor `src`.
+    ///
+    /// A link cost override wins; otherwise the metric computer decides.
+    fn compute_metric(&self, src: SocketAddr, advertised: u16) -> u16 {
+        if let Some(cost) = self.link_cost_overrides.get(&src) {
+            return advertised.saturating_add(*cost);
+        }
+        match self.neighbors.get(&src, self.iface_index) {
+            Some(n) => self.metric_computer.compute(advertised, n),
+            None => self
+                .metric_computer
+                .compute(advertised, &Neighbor::new(src, self.iface_index)),
+        }
+    }
+
+    /// Replace the policy used to compute metrics of learned routes.
+    ///
+    /// The default is [`AdditiveMetric`]. Routes already in the table keep
+    /// their metric until they are re-announced or re-costed.
+    pub fn set_metric_computer(&mut self, computer: Box<dyn MetricComputer>) {
+        self.metric_computer = computer;
+    }
+
+    /// Pin the cost of the link to neighbor `addr`, overriding the computed one.
+    ///
+    /// Routes through that neighbor are re-costed immediately. A cost of
+    /// [`INFINITY`](crate::routing::INFINITY) disables the link for routing.
+    pub fn set_link_cost(&mut self, addr: SocketAddr, cost: u16) {
+        self.link_cost_overrides.insert(addr, cost);
+        self.recompute_metrics_via(addr);
+    }
+
+    /// Drop a link cost override set with [`set_link_cost`](Self::set_link_cost).
+    pub fn clear_link_cost(&mut self, addr: SocketAddr) {
+        if self.link_cost_overrides.remove(&addr).is_some() {
+            self.recompute_metrics_via(addr);
+        }
+    }
+
+    fn recompute_metrics_via(&mut self, addr: SocketAddr) {
+        let mut old_bests: Vec<(RouteKey, Option<Route>)> = Vec::new();
+        for r in self.routes.all() {
+            if r.next_hop == Some(addr.ip()) && !old_bests.iter().any(|(k, _)| k == &r.key) {
+                old_bests.push((r.key.clone(), self.routes.best_route(&r.key).cloned()));
+            }
+        }
+
+        let mut routes = std::mem::take(&mut self.routes);
+        for r in routes.routes_via_mut(addr.ip()) {
+            r.set_metric(self.compute_metric(addr, r.advertised_metric));
+        }
+        self.routes = routes;
+
+        self.emit_best_route_events(old_bests);
+    }
+
+    /// Handle a wildcard retraction (AE 0, infinite metric) from `src`:
+    /// every route through that neighbor becomes unreachable.
+    fn retract_all_via(&mut self, src: SocketAddr, now: Instant) {
+        let mut old_bests: Vec<(RouteKey, Option<Route>)> = Vec::new();
+        for r in self.routes.all() {
+            if r.next_hop == Some(src.ip()) && !old_bests.iter().any(|(k, _)| k == &r.key) {
+                old_bests.push((r.key.clone(), self.routes.best_route(&r.key).cloned()));
+            }
+        }
+
+        for r in self.routes.routes_via_mut(src.ip()) {
+            r.set_metric(INFINITY);
+            r.advertised_metric = INFINITY;
+            r.last_updated = now;
+        }
+
+        self.emit_best_route_events(old_bests);
+    }
+
+    /// Garbage-collect the routing and source tables every [`GC_INTERVAL`].
+    fn maybe_gc(&mut self, now: Instant) {
+        if is_due(self.last_gc, GC_INTERVAL, now) {
+            self.routes.gc(now);
+            self.last_gc = Some(now);
+        }
+    }
+
+    /// Drop learned routes that weren't refreshed within their hold time,
+    /// emitting `RouteRetracted` / `BestRouteChanged` as appropriate.
+    fn expire_routes(&mut self, now: Instant) {
+        let mut old_bests: Vec<(RouteKey, Option<Route>)> = Vec::new();
+        for r in self.routes.all() {
+            if r.next_hop.is_some()
+                && r.expires_at <= now
+                && !old_bests.iter().any(|(k, _)| k == &r.key)
+            {
+                old_bests.push((r.key.clone(), self.routes.best_route(&r.key).cloned()));
+            }
+        }
+
+        if !self.routes.prune_expired(now).is_empty() {
+            self.emit_best_route_events(old_bests);
+        }
+    }
+
+    /// Force-remove all learned routes for `key` and ignore new Updates for
+    /// it until `holddown` has elapsed.
+    ///
+    /// Our own (local) route for the prefix, if any, is kept.
+    pub fn drop_route(&mut self, key: &RouteKey, holddown: Duration) {
+        self.drop_route_at(key, holddown, Instant::now());
+    }
+
+    fn drop_route_at(&mut self, key: &RouteKey, holddown: Duration, now: Instant) {
+        self.holddowns.insert(key.clone(), now + holddown);
+
+        let old_best = self.routes.best_route(key).cloned();
+        if self.routes.remove_learned(key) > 0 {
+            self.emit_best_route_events(vec![(key.clone(), old_best)]);
+        }
+    }
+
+    /// Whether Updates for `key` are currently being ignored.
+    fn in_holddown(&mut self, key: &RouteKey, now: Instant) -> bool {
+        match self.holddowns.get(key) {
+            Some(until) if now < *until => true,
+            Some(_) => {
+                self.holddowns.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Drop neighbors we stopped hearing from, along with the routes
+    /// through them.
+    fn prune_stale_neighbors(&mut self, now: Instant) {
+        for (addr, iface_index) in self
+            .neighbors
+            .prune_stale_with_addrs(now, self.stale_multiplier)
+        {
+            self.push_event(Event::NeighborDown(addr));
+
+            let mut old_bests: Vec<(RouteKey, Option<Route>)> = Vec::new();
+            for r in self.routes.all() {
+                if r.next_hop == Some(addr.ip())
+                    && r.iface_index == iface_index
+                    && !old_bests.iter().any(|(k, _)| k == &r.key)
+                {
+                    old_bests.push((r.key.clone(), self.routes.best_route(&r.key).cloned()));
+                }
+            }
+            if !self
+                .routes
+                .remove_by_next_hop(addr.ip(), iface_index)
+                .is_empty()
+            {
+                self.emit_best_route_events(old_bests);
+            }
+        }
+    }
+
+    /// Withdraw everything learned over an interface that went down.
+    ///
+    /// Removes all learned routes and all neighbors on `iface_index` at once,
+    /// emitting `NeighborDown`, `RouteRetracted` and `BestRouteChanged` events.
+    pub fn interface_down(&mut self, iface_index: u32) {
+        let mut old_bests: Vec<(RouteKey, Option<Route>)> = Vec::new();
+        for r in self.routes.all() {
+            if r.iface_index == iface_index
+                && r.next_hop.is_some()
+                && !old_bests.iter().any(|(k, _)| k == &r.key)
+            {
+                old_bests.push((r.key.clone(), self.routes.best_route(&r.key).cloned()));
+            }
+        }
+
+        self.routes.retract_all_from_iface(iface_index);
+        self.emit_best_route_events(old_bests);
+
+        for addr in self.neighbors.remove_by_iface(iface_index) {
+            self.push_event(Event::NeighborDown(addr));
+        }
+    }
+
+    /// Register our own advertised prefixes as local routes.
+    fn install_local_advertised_routes(&mut self) {
+        // Clone prefixes so we don't hold an immutable borrow of `self`
+        // while calling a `&mut self` method.
+        let prefixes = self.advertised_prefixes.clone();
+
+        for p in &prefixes {
+            self.install_local_route(p);
+        }
+    }
+
+    /// Install the local route for one of our advertised prefixes.
+    fn install_local_route(&mut self, p: &AdvertisedPrefix) {
+        let key = p.key();
+        let route = Route {
+            key: key.clone(),
+            metric: p.metric,
+            advertised_metric: p.metric,
+            smoothed_metric: p.metric,
+            seqno: self.route_seqno,
+            router_id: self.router_id,
+            next_hop: None,
+            iface_index: self.iface_index,
+            last_updated: Instant::now(),
+            expires_at: Instant::now(),
+        };
+
+        self.install_route_and_emit_events(key, route);
+    }
+
+    /// Start advertising a prefix at runtime.
+    ///
+    /// The prefix is validated first (see [`AdvertisedPrefix::validate`]), so
+    /// a malformed prefix is rejected instead of producing a bad Update.
+    /// Our route seqno is bumped, so that neighbors accept the new Update
+    /// even if it replaces one with a better metric, and the Update goes
+    /// out on the next poll or tick.
+    pub fn advertise_prefix(&mut self, prefix: AdvertisedPrefix) -> Result<(), String> {
+        prefix.validate()?;
+
+        let key = prefix.key();
+        self.advertised_prefixes.retain(|p| p.key() != key);
+        self.advertised_prefixes.push(prefix);
+        self.bump_route_seqno();
+        self.schedule_triggered_update(&key);
+        Ok(())
+    }
+
+    /// Stop advertising one of our prefixes at runtime.
+    ///
+    /// The local route is removed and a retraction is queued for the next
+    /// poll or tick. Returns whether we were advertising `key`.
+    pub fn retract_prefix(&mut self, key: &RouteKey) -> bool {
+        let Some(pos) = self
+            .advertised_prefixes
+            .iter()
+            .position(|p| &p.key() == key)
+        else {
+            return false;
+        };
+        let prefix = self.advertised_prefixes.remove(pos);
+
+        let old_best = self.routes.best_route(key).cloned();
+        self.routes
+            .remove(key, self.router_id, None, self.iface_index);
+
+        let retraction = self.prefix_update_tlv(&prefix, INFINITY, 0);
+        for pkt in self.pack_updates(vec![retraction]) {
+            self.queue_to_all(&pkt);
+        }
+        self.last_triggered.insert(
+            key.clone(),
+            SentUpdate {
+                router_id: self.router_id,
+                seqno: self.route_seqno,
+                metric: INFINITY,
+                at: Instant::now(),
+            },
+        );
+
+        self.emit_best_route_events(vec![(key.clone(), old_best)]);
+        true
+    }
+
+    /// Decode a raw packet (header included) from `src` and process it.
+    ///
+    /// Returns the parse error if `buf` isn't a valid packet, or an error
+    /// if it fails HMAC verification under our key or is a replay; nothing
+    /// is processed in that case.
+    pub fn handle_packet_bytes(&mut self, buf: &[u8], src: SocketAddr) -> Result<(), String> {
+        if !self.hmac_ok(buf, src) {
+            self.counters.auth_failures += 1;
+            return Err("HMAC verification failed".to_string());
+        }
+        let pkt = Packet::from_bytes(buf).inspect_err(|_| self.counters.parse_errors += 1)?;
+        if !self.pc_fresh(src, pkt.tlvs()) {
+            self.counters.auth_failures += 1;
+            return Err("replayed packet".to_string());
+        }
+        self.handle_tlvs_from(src, pkt.tlvs());
+        Ok(())
+    }
+
+    /// Process TLVs received from a given source, emitting events as needed.
+    pub fn handle_tlvs_from(&mut self, src: SocketAddr, tlvs: &[Tlv]) {
+        self.handle_tlvs_at(src, tlvs, Instant::now())
+    }
+
+    /// Like [`handle_tlvs_from`](Self::handle_tlvs_from), with an explicit "now".
+    pub fn handle_tlvs_at(&mut self, src: SocketAddr, tlvs: &[Tlv], now: Instant) {
+        let src_ip = src.ip();
+        let iface_index = self.iface_index;
+
+        // If we ever get packets that clearly come from ourselves, ignore them.
+        // Same IP and same port -> almost certainly self.
+        if self
+            .sockets
+            .iter()
+            .filter_map(|s| s.local_addr().ok())
+            .any(|local| local == src)
+        {
+            eprintln!("[BabelNode] ignoring packet from self: {}", src);
+            return;
+        }
+
+        if self.require_link_local_source
+            && let IpAddr::V6(v6) = src_ip
+            && !v6.is_unicast_link_local()
+        {
+            self.dropped_non_link_local += 1;
+            eprintln!(
+                "[BabelNode] dropping packet from non-link-local source {}",
+                src
+            );
+            return;
+        }
+
+        self.counters.packets_received += 1;
+        self.counters.tlvs_parsed += tlvs.len() as u64;
+
+        // RouterId, NextHop and the default prefix only apply within the
+        // packet they appear in, to the Updates that follow them (NextHop
+        // and the default prefix per address family).
+        let mut router_id_opt: Option<[u8; 8]> = None;
+        let mut next_hop_v4: Option<IpAddr> = None;
+        let mut next_hop_v6: Option<IpAddr> = None;
+        let mut default_prefixes = DefaultPrefixes::new();
+
+        for tlv in tlvs {
+            // We parse the (mandatory) Source Prefix sub-TLV but have no
+            // source-specific routes, so it is as unsupported as an unknown
+            // one: taking the TLV as a plain destination route would misroute.
+            let source_specific = tlv
+                .sub_tlvs()
+                .iter()
+                .any(|st| matches!(st, SubTlv::SourcePrefix { .. }));
+            if tlv.has_unknown_mandatory_subtlv() || source_specific {
+                eprintln!(
+                    "[BabelNode] ignoring TLV from {} with unsupported mandatory sub-TLV",
+                    src
+                );
+                continue;
+            }
+
+            match tlv {
+                Tlv::AckRequest {
+                    opaque, interval, ..
+                } => {
+                    // The Ack must reach the requester within `interval`.
+                    self.pending_acks.push(PendingAck {
+                        dest: src,
+                        opaque: *opaque,
+                        deadline: now + Duration::from_millis(*interval as u64),
+                    });
+                }
+
+                Tlv::Hello {
+                    seqno, interval, ..
+                } => {
+                    let window = self.neighbors.reachability_window();
+                    let was_reachable = self
+                        .neighbors
+                        .get(&src, iface_index)
+                        .map(|n| n.is_reachable(window));
+                    let is_new = was_reachable.is_none();
+                    self.neighbors
+                        .update_on_hello(src, iface_index, *seqno, *interval, now);
+
+                    if let Some(was) = was_reachable
+                        && let Some(n) = self.neighbors.get(&src, iface_index)
+                        && n.is_reachable(window) != was
+                    {
+                        self.push_event(Event::NeighborReachabilityChanged(src, !was));
+                    }
+
+                    if let Some((transmit, None)) = tlv.timestamp() {
+                        let arrival = self.timestamp_us(now);
+                        if let Some(n) = self.neighbors.get_mut(&src, iface_index) {
+                            n.note_hello_timestamp(transmit, arrival);
+                        }
+                    }
+
+                    if is_new && let Some(n) = self.neighbors.get(&src, iface_index).cloned() {
+                        self.push_event(Event::NeighborUp(src, n));
+                    }
+                }
+
+                Tlv::Ihu {
+                    rxcost,
+                    interval,
+                    addr,
+                    ..
+                } => {
+                    if !self.ihu_is_for_us(*addr) {
+                        continue;
+                    }
+                    self.neighbors
+                        .update_on_ihu(src, iface_index, *rxcost, *interval, now);
+
+                    if let Some((origin, Some(receive))) = tlv.timestamp() {
+                        let arrival = self.timestamp_us(now);
+                        if let Some(n) = self.neighbors.get_mut(&src, iface_index) {
+                            n.note_ihu_timestamp(origin, receive, arrival);
+                        }
+                    }
+                }
+
+                Tlv::RouterId { router_id, .. } => {
+                    router_id_opt = Some(*router_id);
+                }
+
+                Tlv::NextHop { ae, addr, .. } => {
+                    let addr = addr.unwrap_or(src_ip);
+                    match ae_family(*ae) {
+                        Some(AddressFamily::V4) => next_hop_v4 = Some(addr),
+                        Some(AddressFamily::V6) => next_hop_v6 = Some(addr),
+                        None => {}
+                    }
+                }
+
+                Tlv::Update {
+                    ae,
+                    flags,
+                    plen,
+                    omitted,
+                    interval,
+                    seqno,
+                    metric,
+                    prefix,
+                    sub_tlvs: _,
+                } => {
+                    // Expand even Updates we end up ignoring, so the default
+                    // prefix stays in step with the sender's.
+                    let prefix = match default_prefixes.expand(*ae, *flags, *omitted, prefix) {
+                        Ok(prefix) => prefix,
+                        Err(e) => {
+                            eprintln!("[BabelNode] ignoring Update from {}: {}", src, e);
+                            continue;
+                        }
+                    };
+
+                    if *ae == 0 {
+                        // AE 0 carries no prefix; the only valid use is a
+                        // wildcard retraction of everything from `src`.
+                        if *plen == 0 && *metric == INFINITY {
+                            self.retract_all_via(src, now);
+                        }
+                        continue;
+                    }
+
+                    // This is where we register new routes from *remote routers*.
+                    if let Some(router_id) = router_id_opt {
+                        let nexthop_opt = match ae_family(*ae) {
+                            Some(AddressFamily::V4) => next_hop_v4,
+                            Some(AddressFamily::V6) => next_hop_v6,
+                            None => None,
+                        }
+                        .or(Some(src_ip));
+
+                        let key = RouteKey {
+                            ae: *ae,
+                            plen: *plen,
+                            prefix,
+                        };
+
+                        if self.in_holddown(&key, now) {
+                            eprintln!(
+                                "[BabelNode] ignoring Update from {}: prefix is held down",
+                                src
+                            );
+                            continue;
+                        }
+
+                        if *metric == INFINITY {
+                            // A retraction: forget this path right away.
+                            let old_best = self.routes.best_route(&key).cloned();
+                            if self
+                                .routes
+                                .remove(&key, router_id, nexthop_opt, iface_index)
+                            {
+                                self.emit_best_route_events(vec![(key, old_best)]);
+                            }
+                            continue;
+                        }
+
+                        let route_metric = self.compute_metric(src, *metric);
+                        let route = Route {
+                            key: key.clone(),
+                            metric: route_metric,
+                            advertised_metric: *metric,
+                            smoothed_metric: route_metric,
+                            seqno: *seqno,
+                            router_id,
+                            next_hop: nexthop_opt,
+                            iface_index,
+                            last_updated: now,
+                            expires_at: now + route_hold_time(*interval),
+                        };
+
+                        self.install_route_and_emit_events(key, route);
+                    } else {
+                        eprintln!(
+                            "[BabelNode] ignoring Update from {}: unknown router-id",
+                            src
+                        );
+                    }
+                }
+
+                Tlv::RouteRequest { ae: 0, plen: 0, .. } => {
+                    // Wildcard request: answer with a full dump of our
+                    // prefixes, sent on the next poll or tick.
+                    for bytes in self.static_update_packets().iter().filter_map(encode) {
+                        self.send_queue.push(OutgoingPacket { dest: src, bytes });
+                    }
+                }
+
+                Tlv::RouteRequest { .. } => {
+                    // TODO: respond with matching Update(s)
+                }
+
+                Tlv::SeqnoRequest {
+                    ae,
+                    plen,
+                    seqno,
+                    hop_count,
+                    router_id,
+                    prefix,
+                    ..
+                } => {
+                    let key = RouteKey {
+                        ae: *ae,
+                        plen: *plen,
+                        prefix: prefix.clone(),
+                    };
+                    self.handle_seqno_request(src, key, *seqno, *hop_count, *router_id, now);
+                }
+
+                Tlv::Ack { opaque, .. } => {
+                    self.awaited_acks
+                        .retain(|a| !(a.opaque == *opaque && a.dest.ip() == src_ip));
+                }
+
+                _ => {
+                    // Other TLVs currently ignored.
+                }
+            }
+        }
+    }
+
+    /// Answer a SeqnoRequest from `src` (RFC 8966 §3.8.1.2).
+    ///
+    /// For one of our own prefixes we bump the route seqno if asked for a
+    /// newer one, then announce. For a learned prefix we announce if our
+    /// route already satisfies the request or is for a different router-id,
+    /// and otherwise forward it with a decremented hop count towards the
+    /// next hop of our best route.
+    /// Whatever we send is queued for the next poll or tick.
+    fn handle_seqno_request(
+        &mut self,
+        src: SocketAddr,
+        key: RouteKey,
+        seqno: u16,
+        hop_count: u8,
+        router_id: [u8; 8],
+        now: Instant,
+    ) {
+        let satisfied = if self.advertised_prefixes.iter().any(|p| p.key() == key) {
+            if router_id == self.router_id && seqno_gt(seqno, self.route_seqno) {
+                self.bump_route_seqno();
+            }
+            true
+        } else {
+            match self.routes.best_route(&key) {
+                Some(best) => best.router_id != router_id || !seqno_gt(seqno, best.seqno),
+                None => return,
+            }
+        };
+
+        if satisfied {
+            if let Some(tlvs) = self.triggered_update_tlvs(&key, now) {
+                self.queue_to_all(&Packet::with_tlvs(tlvs));
+            }
+            return;
+        }
+
+        let next_hop = match self.routes.best_route(&key).and_then(|r| r.next_hop) {
+            Some(ip) if hop_count > 1 && ip != src.ip() => ip,
+            _ => return,
+        };
+        let dest = self
+            .neighbors
+            .all()
+            .find(|n| n.addr.ip() == next_hop)
+            .map(|n| n.addr)
+            .unwrap_or(SocketAddr::new(next_hop, BABEL_PORT));
+
+        let forwarded = Tlv::SeqnoRequest {
+            ae: key.ae,
+            plen: key.plen,
+            seqno,
+            hop_count: hop_count - 1,
+            router_id,
+            prefix: key.prefix,
+            sub_tlvs: Vec::new(),
+        };
+        if let Some(bytes) = encode(&Packet::with_tlvs(vec![forwarded])) {
+            self.send_queue.push(OutgoingPacket { dest, bytes });
+        }
+    }
+
+    fn push_event(&mut self, ev: Event) {
+        if let Some(key) = ev.route_key() {
+            // Forward to matching subscribers, forgetting those that hung up.
+            self.prefix_subscribers
+                .retain(|(filter, tx)| !filter.contains(key) || tx.send(ev.clone()).is_ok());
+        }
+        for callback in &mut self.event_callbacks {
+            callback(&ev);
+        }
+        self.events.push(ev);
+    }
+
+    /// Call `f` with every event as it happens, from inside `poll`, `tick`
+    /// or whichever call produced it.
+    ///
+    /// Events are still queued for [`drain_events`](Self::drain_events) as
+    /// well.
+    pub fn on_event(&mut self, f: EventCallback) {
+        self.event_callbacks.push(f);
+    }
+
+    /// Receive route events for prefixes within `filter` as they happen.
+    ///
+    /// Neighbor events are not forwarded. Events are still queued for
+    /// [`drain_events`](Self::drain_events) as well.
+    pub fn subscribe_prefix(&mut self, filter: RouteKey) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.prefix_subscribers.push((filter, tx));
+        rx
+    }
+
+    /// Take and return all pending events since the last call.
+    pub fn drain_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Convenience: poll the node and return any events produced.
+    pub fn poll_with_events(&mut self) -> io::Result<Vec<Event>> {
+        self.poll()?;
+        Ok(self.drain_events())
+    }
+
+    /// Simple blocking event loop for a Babel node (demo mode).
+    pub fn run(&mut self) -> io::Result<()> {
+        println!("[BabelNode] running, router-id = {:?}", self.router_id);
+
+        loop {
+            self.poll()?;
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Serialize `pkt` for the send queue, logging and skipping it if one of
+/// its TLVs doesn't fit its length field.
+fn encode(pkt: &Packet) -> Option<Vec<u8>> {
+    pkt.to_bytes()
+        .inspect_err(|e| eprintln!("[BabelNode] dropping packet that failed to encode: {e}"))
+        .ok()
+}
+
+/// Parse a MAC address as sysfs prints it, e.g. `02:fc:00:00:00:01\n`.
+fn parse_mac(text: &str) -> io::Result<[u8; 6]> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed MAC");
+
+    let mut mac = [0u8; 6];
+    let mut parts = text.trim().split(':');
+    for byte in mac.iter_mut() {
+        *byte = parts
+            .next()
+            .filter(|p| p.len() == 2 && p.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|p| u8::from_str_radix(p, 16).ok())
+            .ok_or_else(malformed)?;
+    }
+    if parts.next().is_some() {
+        return Err(malformed());
+    }
+    Ok(mac)
+}
+
+/// Whether a timer last fired at `last` with period `interval` is due at `now`.
+fn is_due(last: Option<Instant>, interval: Duration, now: Instant) -> bool {
+    match last {
+        None => true,
+        Some(last) => now.duration_since(last) >= interval,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A node on a loopback socket, with exact timer intervals.
+    fn test_node(config: BabelConfig) -> BabelNode {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("bind failed");
+        BabelNode::with_socket(socket, 1, [0, 0, 0, 0, 0, 0, 0, 1], config.jitter(false)).unwrap()
+    }
+
+    fn local_prefix() -> AdvertisedPrefix {
+        AdvertisedPrefix {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 0, 1],
+            metric: 0,
+        }
+    }
+
+    fn local_key() -> RouteKey {
+        RouteKey {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 0, 1],
+        }
+    }
+
+    #[test]
+    fn ack_request_is_answered_within_interval() {
+        let mut node = test_node(BabelConfig::new());
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let t0 = Instant::now();
+        let req = Tlv::AckRequest {
+            opaque: 0x1234,
+            interval: 200,
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_at(peer_addr, &[req], t0);
+        assert_eq!(node.pending_acks.len(), 1);
+        assert_eq!(
+            node.pending_acks[0].deadline,
+            t0 + Duration::from_millis(200)
+        );
+
+        // The very next poll, well inside the interval, flushes the Ack.
+        let t1 = t0 + Duration::from_millis(10);
+        node.poll_at(t1).unwrap();
+        assert!(node.pending_acks.is_empty());
+
+        let mut buf = [0u8; 1500];
+        let (tlvs, _) = Packet::recv(&peer, &mut buf).expect("no Ack received");
+        assert_eq!(
+            tlvs,
+            vec![Tlv::Ack {
+                opaque: 0x1234,
+                sub_tlvs: Vec::new()
+            }]
+        );
+    }
+
+    #[test]
+    fn ack_requests_are_answered_and_retransmitted() {
+        let mut a = test_node(BabelConfig::new().multicast(false));
+        let mut b = test_node(BabelConfig::new().multicast(false));
+        let a_addr: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let b_addr: SocketAddr = "192.0.2.2:6696".parse().unwrap();
+        let t0 = Instant::now();
+        a.tick(t0);
+        b.tick(t0);
+
+        let opaque = a.send_with_ack(b_addr, vec![Tlv::PadN { n: 2 }]);
+        let sent = a.tick(t0);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, b_addr);
+
+        // No Ack yet: the packet goes out again after the timeout.
+        assert!(a.tick(t0 + ACK_TIMEOUT / 2).is_empty());
+        let again = a.tick(t0 + ACK_TIMEOUT * 2);
+        assert_eq!(again, sent);
+
+        b.ingest(&again[0].bytes, a_addr).unwrap();
+        let acks = b.tick(t0 + ACK_TIMEOUT * 2);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].dest, a_addr);
+        assert!(matches!(
+            Packet::from_bytes(&acks[0].bytes).unwrap().tlvs(),
+            [Tlv::Ack { opaque: o, .. }] if *o == opaque
+        ));
+
+        a.ingest(&acks[0].bytes, b_addr).unwrap();
+        assert!(a.awaited_acks.is_empty());
+        assert!(a.tick(t0 + ACK_TIMEOUT * 10).is_empty());
+
+        // Without an Ack it is given up on after the retries.
+        a.send_with_ack(b_addr, Vec::new());
+        let mut copies = 0;
+        for step in 0..10 {
+            copies += a.tick(t0 + ACK_TIMEOUT * (20 + 2 * step)).len();
+        }
+        assert_eq!(copies, 1 + ACK_RETRIES as usize);
+        assert!(a.awaited_acks.is_empty());
+    }
+
+    fn learned_route(prefix: Vec<u8>, iface_index: u32, last_octet: u8) -> Route {
+        Route {
+            key: RouteKey {
+                ae: 1,
+                plen: 24,
+                prefix,
+            },
+            metric: 256,
+            advertised_metric: 256,
+            smoothed_metric: 256,
+            seqno: 1,
+            router_id: [0, 0, 0, 0, 0, 0, 0, last_octet],
+            next_hop: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, last_octet))),
+            iface_index,
+            last_updated: Instant::now(),
+            expires_at: Instant::now() + Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn rtt_is_measured_on_the_given_clock() {
+        let config = BabelConfig::new().timestamps(true);
+        let mut a = test_node(config.clone());
+        let mut b = test_node(config);
+        let a_addr = a.sockets[0].local_addr().unwrap();
+        let b_addr = b.sockets[0].local_addr().unwrap();
+        let t0 = Instant::now() + Duration::from_secs(3600);
+        let ms = Duration::from_millis;
+
+        for pkt in a.tick(t0) {
+            let tlvs = Packet::from_bytes(&pkt.bytes).unwrap().into_tlvs();
+            b.handle_tlvs_at(a_addr, &tlvs, t0 + ms(10));
+        }
+        // b holds a's Hello for 10ms, the rest is the round trip.
+        for pkt in b.tick(t0 + ms(20)) {
+            let tlvs = Packet::from_bytes(&pkt.bytes).unwrap().into_tlvs();
+            a.handle_tlvs_at(b_addr, &tlvs, t0 + ms(30));
+        }
+
+        assert_eq!(a.neighbors.get(&b_addr, 1).unwrap().rtt_ms, Some(20));
+    }
+
+    #[test]
+    fn tick_garbage_collects_source_entries() {
+        let mut node = test_node(BabelConfig::new());
+        let t0 = Instant::now();
+        let route = learned_route(vec![10, 0, 1], 1, 10);
+        node.routes.note_advertised(&route, t0);
+
+        node.tick(t0);
+        assert!(
+            node.routes
+                .sources()
+                .get(&route.key, route.router_id)
+                .is_some()
+        );
+
+        node.tick(t0 + crate::routing::SOURCE_GC_TIME + Duration::from_secs(1));
+        assert!(
+            node.routes
+                .sources()
+                .get(&route.key, route.router_id)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn pruning_a_neighbor_keeps_routes_via_its_address_on_other_interfaces() {
+        let mut node = test_node(BabelConfig::new());
+        let t0 = Instant::now();
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        node.neighbors.update_on_hello(peer, 1, 1, 1000, t0);
+        node.routes
+            .install_or_update(learned_route(vec![10, 0, 1], 1, 10));
+        node.routes
+            .install_or_update(learned_route(vec![10, 0, 2], 2, 10));
+
+        // Still heard on iface 2, silent on iface 1.
+        let later = t0 + Duration::from_millis(3300);
+        node.neighbors.update_on_hello(peer, 2, 1, 1000, later);
+        node.tick(later);
+
+        assert!(node.neighbors.get(&peer, 1).is_none());
+        assert!(node.neighbors.get(&peer, 2).is_some());
+        let left: Vec<u32> = node.routes().iter().map(|r| r.iface_index).collect();
+        assert_eq!(left, vec![2]);
+    }
+
+    #[test]
+    fn interface_down_withdraws_only_that_interface() {
+        let mut node = test_node(BabelConfig::new());
+        let now = Instant::now();
+        let a: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let b: SocketAddr = "192.0.2.20:6696".parse().unwrap();
+        node.neighbors.update_on_hello(a, 1, 1, 1000, now);
+        node.neighbors.update_on_hello(b, 2, 1, 1000, now);
+
+        // 10.0.1.0/24 only via iface 1, 10.0.2.0/24 via both.
+        node.routes
+            .install_or_update(learned_route(vec![10, 0, 1], 1, 10));
+        node.routes
+            .install_or_update(learned_route(vec![10, 0, 2], 1, 10));
+        node.routes
+            .install_or_update(learned_route(vec![10, 0, 3], 2, 20));
+        let mut backup = learned_route(vec![10, 0, 2], 2, 20);
+        backup.metric = 512;
+        node.routes.install_or_update(backup);
+
+        node.interface_down(1);
+
+        assert!(node.routes().iter().all(|r| r.iface_index == 2));
+        assert_eq!(node.routes().len(), 2);
+        assert!(node.neighbors.get(&a, 1).is_none());
+        assert!(node.neighbors.get(&b, 2).is_some());
+
+        let events = node.drain_events();
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, Event::RouteRetracted(k) if k.prefix == vec![10, 0, 1]))
+        );
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::BestRouteChanged(k, r) if k.prefix == vec![10, 0, 2] && r.iface_index == 2
+        )));
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, Event::NeighborDown(addr) if *addr == a))
+        );
+    }
+
+    #[test]
+    fn periodic_packet_contains_expected_tlvs() {
+        let mut node = test_node(
+            BabelConfig::new()
+                .hello_interval_ms(1000)
+                .update_interval_ms(5000)
+                .with_advertised_prefix(local_prefix()),
+        );
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        node.neighbors
+            .update_on_hello(peer, 1, 7, 1000, Instant::now());
+
+        let bytes = node.build_periodic_packet().to_bytes().unwrap();
+        let tlvs = Tlv::parse_all(&bytes[4..]).unwrap();
+
+        assert_eq!(
+            tlvs,
+            vec![
+                Tlv::Hello {
+                    flags: 0,
+                    seqno: 1,
+                    interval: 1000,
+                    sub_tlvs: Vec::new(),
+                },
+                Tlv::Ihu {
+                    ae: 1,
+                    rxcost: 256,
+                    interval: 4000,
+                    addr: Some(peer.ip()),
+                    sub_tlvs: Vec::new(),
+                },
+                Tlv::RouterId {
+                    router_id: node.router_id(),
+                    sub_tlvs: Vec::new(),
+                },
+                Tlv::Update {
+                    ae: 1,
+                    flags: crate::tlv::UPDATE_FLAG_DEFAULT_PREFIX,
+                    plen: 24,
+                    omitted: 0,
+                    interval: 5000,
+                    seqno: 1,
+                    metric: 0,
+                    prefix: vec![10, 0, 1],
+                    sub_tlvs: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn connected_subnet_is_advertised() {
+        let node = test_node(
+            BabelConfig::new()
+                .interface_address(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 5)), 24)
+                .advertise_connected(true),
+        );
+
+        assert_eq!(node.advertised_prefixes.len(), 1);
+        assert_eq!(node.advertised_prefixes[0].prefix, vec![10, 0, 1]);
+        assert_eq!(node.advertised_prefixes[0].plen, 24);
+        assert!(node.best_route(&local_key()).is_some());
+    }
+
+    #[test]
+    fn connected_subnet_requires_flag() {
+        let node = test_node(
+            BabelConfig::new().interface_address(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 5)), 24),
+        );
+        assert!(node.advertised_prefixes.is_empty());
+    }
+
+    #[test]
+    fn update_packets_carry_next_hop_of_matching_family() {
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        let v6: IpAddr = "fd00::2".parse().unwrap();
+        let node = test_node(
+            BabelConfig::new()
+                .with_local_address(v4)
+                .with_local_address(v6)
+                .with_advertised_prefix(local_prefix())
+                .with_advertised_prefix(AdvertisedPrefix {
+                    ae: 2,
+                    plen: 32,
+                    prefix: vec![0x20, 0x01, 0x0d, 0xb8],
+                    metric: 0,
+                }),
+        );
+
+        let packets = node.static_update_packets();
+        assert_eq!(packets.len(), 1);
+        let bytes = packets[0].to_bytes().unwrap();
+        let tlvs = Tlv::parse_all(&bytes[4..]).unwrap();
+
+        for (want_ae, want_addr) in [(1u8, v4), (2u8, v6)] {
+            let nh = tlvs
+                .iter()
+                .position(|t| matches!(t, Tlv::NextHop { ae, .. } if *ae == want_ae))
+                .expect("NextHop missing");
+            let upd = tlvs
+                .iter()
+                .position(|t| matches!(t, Tlv::Update { ae, .. } if *ae == want_ae))
+                .unwrap();
+            assert!(nh < upd);
+            assert!(matches!(
+                &tlvs[nh],
+                Tlv::NextHop { addr: Some(a), .. } if *a == want_addr
+            ));
+        }
+    }
+
+    #[test]
+    fn static_updates_follow_router_id() {
+        let mut second = local_prefix();
+        second.prefix = vec![10, 0, 2];
+        let node = test_node(
+            BabelConfig::new()
+                .with_advertised_prefix(local_prefix())
+                .with_advertised_prefix(second),
+        );
+
+        let packets = node.static_update_packets();
+        assert_eq!(packets.len(), 1);
+        let pkt = Packet::from_bytes(&packets[0].to_bytes().unwrap()).unwrap();
+        let tlvs = pkt.tlvs();
+
+        assert!(matches!(
+            &tlvs[0],
+            Tlv::RouterId { router_id, .. } if *router_id == node.router_id()
+        ));
+        let updates = tlvs
+            .iter()
+            .filter(|t| matches!(t, Tlv::Update { .. }))
+            .count();
+        assert_eq!(updates, 2);
+        assert_eq!(
+            tlvs.iter()
+                .filter(|t| matches!(t, Tlv::RouterId { .. }))
+                .count(),
+            1
+        );
+    }
+
+    fn announce(node: &mut BabelNode, src: SocketAddr, metric: u16, seqno: u16) {
+        let tlvs = [
+            Tlv::RouterId {
+                router_id: [9; 8],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: 1,
+                flags: 0,
+                plen: 24,
+                omitted: 0,
+                interval: 1000,
+                seqno,
+                metric,
+                prefix: vec![10, 9, 9],
+                sub_tlvs: Vec::new(),
+            },
+        ];
+        node.handle_tlvs_from(src, &tlvs);
+    }
+
+    fn remote_key() -> RouteKey {
+        RouteKey {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 9, 9],
+        }
+    }
+
+    #[test]
+    fn link_cost_override_recosts_routes() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        announce(&mut node, peer, 100, 1);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 100);
+        node.drain_events();
+
+        node.set_link_cost(peer, 300);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 400);
+        assert!(
+            node.drain_events()
+                .iter()
+                .any(|e| matches!(e, Event::BestRouteChanged(_, r) if r.metric == 400))
+        );
+
+        // New Updates over the link keep using the override.
+        announce(&mut node, peer, 50, 2);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 350);
+
+        // An infinite cost disables the link.
+        node.set_link_cost(peer, crate::routing::INFINITY);
+        assert!(node.best_route(&remote_key()).is_none());
+        assert!(
+            node.drain_events()
+                .iter()
+                .any(|e| matches!(e, Event::RouteRetracted(k) if *k == remote_key()))
+        );
+
+        node.clear_link_cost(peer);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 50);
+    }
+
+    #[test]
+    fn lower_link_cost_next_hop_wins() {
+        let mut node = test_node(BabelConfig::new());
+        let near: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let far: SocketAddr = "192.0.2.20:6696".parse().unwrap();
+
+        for (peer, rxcost) in [(near, 96), (far, 512)] {
+            let ihu = Tlv::Ihu {
+                ae: 1,
+                rxcost,
+                interval: 400,
+                addr: None,
+                sub_tlvs: Vec::new(),
+            };
+            node.handle_tlvs_from(peer, &[ihu]);
+        }
+
+        announce(&mut node, far, 100, 1);
+        announce(&mut node, near, 100, 1);
+
+        let best = node.best_route(&remote_key()).unwrap();
+        assert_eq!(best.next_hop, Some(near.ip()));
+        assert_eq!(best.metric, 196);
+        assert!(node.routes().iter().any(|r| r.metric == 612));
+    }
+
+    #[test]
+    fn retraction_removes_route() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        announce(&mut node, peer, 100, 1);
+        assert!(node.best_route(&remote_key()).is_some());
+        node.drain_events();
+
+        announce(&mut node, peer, INFINITY, 2);
+        assert!(node.routes().iter().all(|r| r.key != remote_key()));
+        assert!(node.best_route(&remote_key()).is_none());
+        let events = node.drain_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::RouteRetracted(k) if *k == remote_key()));
+
+        // Retracting a route we never had is a no-op.
+        announce(&mut node, peer, INFINITY, 3);
+        assert!(node.drain_events().is_empty());
+    }
+
+    #[test]
+    fn split_horizon_suppresses_learned_routes() {
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let now = Instant::now();
+
+        let mut node = test_node(BabelConfig::new().split_horizon(true));
+        announce(&mut node, peer, 100, 1);
+        assert!(node.best_route(&remote_key()).is_some());
+        assert!(node.triggered_update_tlvs(&remote_key(), now).is_none());
+
+        // Our own prefixes are still announced.
+        node.advertise_prefix(local_prefix()).unwrap();
+        assert!(node.triggered_update_tlvs(&local_key(), now).is_some());
+
+        let mut node = test_node(BabelConfig::new());
+        announce(&mut node, peer, 100, 1);
+        assert!(node.triggered_update_tlvs(&remote_key(), now).is_some());
+    }
+
+    #[test]
+    fn same_seqno_metric_oscillation_is_ignored() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        announce(&mut node, peer, 100, 5);
+        for metric in [300, 100, 500, 200] {
+            announce(&mut node, peer, metric, 5);
+            assert_eq!(node.best_route(&remote_key()).unwrap().metric, 100);
+        }
+
+        announce(&mut node, peer, 80, 5);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 80);
+
+        // A newer seqno may carry a worse metric.
+        announce(&mut node, peer, 400, 6);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 400);
+    }
+
+    struct HopCount;
+
+    impl MetricComputer for HopCount {
+        fn compute(&self, advertised: u16, _neighbor: &Neighbor) -> u16 {
+            advertised.saturating_add(1)
+        }
+    }
+
+    #[test]
+    fn custom_metric_computer_overrides_default() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        node.neighbors
+            .update_on_ihu(peer, 1, 256, 1000, Instant::now());
+
+        announce(&mut node, peer, 100, 1);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 356);
+
+        node.set_metric_computer(Box::new(HopCount));
+        announce(&mut node, peer, 3, 2);
+        assert_eq!(node.best_route(&remote_key()).unwrap().metric, 4);
+    }
+
+    #[test]
+    fn advertise_prefix_at_runtime() {
+        let other = AdvertisedPrefix {
+            prefix: vec![10, 0, 2],
+            ..local_prefix()
+        };
+        let mut node = test_node(BabelConfig::new().with_advertised_prefix(other));
+        let t0 = Instant::now();
+        assert_eq!(tick_updates(&mut node, t0), vec![(0, vec![10, 0, 2])]);
+
+        node.advertise_prefix(local_prefix()).unwrap();
+        assert_eq!(node.best_route(&local_key()).unwrap().seqno, 2);
+        assert_eq!(node.static_update_tlvs().len(), 2);
+        // Announced right away rather than at the next Update interval.
+        let updates = tick_updates(&mut node, t0 + Duration::from_millis(10));
+        assert_eq!(updates, vec![(0, vec![10, 0, 1])]);
+    }
+
+    #[test]
+    fn retract_prefix_at_runtime() {
+        let mut node = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let t0 = Instant::now();
+        assert_eq!(tick_updates(&mut node, t0), vec![(0, vec![10, 0, 1])]);
+        node.drain_events();
+
+        assert!(node.retract_prefix(&local_key()));
+        assert!(node.best_route(&local_key()).is_none());
+        assert!(node.static_update_tlvs().is_empty());
+        assert!(matches!(
+            node.drain_events().as_slice(),
+            [Event::RouteRetracted(k)] if *k == local_key()
+        ));
+
+        let updates = tick_updates(&mut node, t0 + Duration::from_millis(10));
+        assert_eq!(updates, vec![(INFINITY, vec![10, 0, 1])]);
+
+        // Nothing left to retract.
+        assert!(!node.retract_prefix(&local_key()));
+    }
+
+    #[test]
+    fn advertise_prefix_rejects_mismatched_length() {
+        let mut node = test_node(BabelConfig::new());
+        let bad = AdvertisedPrefix {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 0],
+            metric: 0,
+        };
+
+        assert!(node.advertise_prefix(bad).is_err());
+        assert!(node.advertised_prefixes.is_empty());
+        assert!(node.routes().is_empty());
+    }
+
+    #[test]
+    fn advertised_prefix_new_validates() {
+        let ok = AdvertisedPrefix::new(1, 24, vec![10, 0, 1], 0).unwrap();
+        assert_eq!(ok.key(), local_key());
+
+        let short = AdvertisedPrefix::new(1, 24, vec![10, 0], 0).unwrap_err();
+        assert!(short.contains("needs 3"), "{short}");
+        let long = AdvertisedPrefix::new(1, 33, vec![10, 0, 1, 0, 0], 0).unwrap_err();
+        assert!(long.contains("exceeds 32"), "{long}");
+        assert!(AdvertisedPrefix::new(2, 129, vec![0; 17], 0).is_err());
+    }
+
+    #[test]
+    fn try_with_advertised_prefix_rejects_invalid() {
+        let bad = AdvertisedPrefix {
+            prefix: vec![10, 0],
+            ..local_prefix()
+        };
+        assert!(BabelConfig::new().try_with_advertised_prefix(bad).is_err());
+
+        let config = BabelConfig::new()
+            .try_with_advertised_prefix(local_prefix())
+            .unwrap();
+        assert_eq!(config.advertised_prefixes.len(), 1);
+    }
+
+    #[test]
+    fn multicast_ttl_is_configurable() {
+        let node = test_node(BabelConfig::new());
+        assert_eq!(node.sockets[0].multicast_ttl_v4().unwrap(), 1);
+
+        let node = test_node(BabelConfig::new().multicast_ttl(4));
+        assert_eq!(node.sockets[0].multicast_ttl_v4().unwrap(), 4);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn socket_can_be_bound_to_device() {
+        let node = test_node(BabelConfig::new().bind_to_device("lo".to_string()));
+        let device = socket2::SockRef::from(&node.sockets[0]).device().unwrap();
+        assert_eq!(device.as_deref(), Some(&b"lo"[..]));
+    }
+
+    #[test]
+    fn packet_trace_records_sent_and_received() {
+        let path = std::env::temp_dir().join(format!("babel_rs_trace_{}.pcap", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let config = BabelConfig::new()
+            .multicast(false)
+            .add_unicast_peer(peer.local_addr().unwrap())
+            .packet_trace(path.clone());
+        let mut node = test_node(config);
+        let node_addr = node.sockets[0].local_addr().unwrap();
+
+        node.send_hello().unwrap();
+        node.send_hello().unwrap();
+        let mut buf = [0u8; 1500];
+        peer.recv_from(&mut buf).unwrap();
+        peer.send_to(
+            &Packet::build_hello(0, 1, 400).to_bytes().unwrap(),
+            node_addr,
+        )
+        .unwrap();
+        let mut received = None;
+        for _ in 0..100 {
+            received = node.recv_once().unwrap();
+            if received.is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(received.is_some());
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&data[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&data[20..24], &crate::trace::LINKTYPE_NULL.to_le_bytes());
+
+        let mut records = 0;
+        let mut pos = 24;
+        while pos < data.len() {
+            let len = u32::from_le_bytes(data[pos + 8..pos + 12].try_into().unwrap());
+            pos += 16 + len as usize;
+            records += 1;
+        }
+        assert_eq!(pos, data.len());
+        assert_eq!(records, 3);
+    }
+
+    #[test]
+    fn max_payload_follows_mtu() {
+        let node = test_node(BabelConfig::new().mtu(1500));
+        assert_eq!(node.max_payload(), 1500 - 28);
+
+        // Interface 1 is loopback on Linux; otherwise the default MTU is used.
+        let node = test_node(BabelConfig::new());
+        let mtu = packet::interface_name(1)
+            .and_then(|name| packet::interface_mtu(&name))
+            .unwrap_or(packet::DEFAULT_MTU);
+        assert_eq!(node.max_payload(), mtu - 28);
+    }
+
+    #[test]
+    fn dropped_route_is_held_down() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let key = remote_key();
+
+        announce(&mut node, peer, 100, 1);
+        assert!(node.best_route(&key).is_some());
+        node.drain_events();
+
+        let t0 = Instant::now();
+        node.drop_route_at(&key, Duration::from_secs(30), t0);
+        assert!(node.best_route(&key).is_none());
+        assert!(
+            node.drain_events()
+                .iter()
+                .any(|e| matches!(e, Event::RouteRetracted(k) if *k == key))
+        );
+
+        // Re-announced within the hold-down: ignored.
+        announce(&mut node, peer, 100, 2);
+        assert!(node.best_route(&key).is_none());
+
+        // After the hold-down the prefix is learned again.
+        assert!(!node.in_holddown(&key, t0 + Duration::from_secs(31)));
+        announce(&mut node, peer, 100, 3);
+        assert!(node.best_route(&key).is_some());
+    }
+
+    fn hello(seqno: u16) -> Tlv {
+        Tlv::Hello {
+            flags: 0,
+            seqno,
+            interval: 1000,
+            sub_tlvs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn routes_go_down_with_their_neighbor() {
+        let mut node = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let t0 = Instant::now();
+
+        node.handle_tlvs_at(peer, &[hello(1)], t0);
+        announce(&mut node, peer, 100, 1);
+        assert!(node.best_route(&remote_key()).is_some());
+        node.drain_events();
+
+        // Stale after three Hello intervals, before the route's own hold
+        // time runs out.
+        node.tick(t0 + Duration::from_millis(3300));
+        let events = node.drain_events();
+        assert!(matches!(events[0], Event::NeighborDown(a) if a == peer));
+        assert!(matches!(&events[1], Event::RouteRetracted(k) if *k == remote_key()));
+        assert_eq!(events.len(), 2);
+
+        assert!(node.routes().iter().all(|r| r.next_hop.is_none()));
+        assert!(node.best_route(&local_key()).is_some());
+    }
+
+    #[test]
+    fn stale_multiplier_delays_neighbor_pruning() {
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let t0 = Instant::now();
+        let mut default = test_node(BabelConfig::new());
+        let mut patient = test_node(BabelConfig::new().stale_multiplier(5));
+
+        for node in [&mut default, &mut patient] {
+            node.handle_tlvs_at(peer, &[hello(1)], t0);
+            node.tick(t0 + Duration::from_millis(4000));
+        }
+        assert_eq!(default.neighbors().count(), 0);
+        assert_eq!(patient.neighbors().count(), 1);
+
+        patient.tick(t0 + Duration::from_millis(5500));
+        assert_eq!(patient.neighbors().count(), 0);
+    }
+
+    #[test]
+    fn reachability_window_is_configurable() {
+        let node = test_node(BabelConfig::new().reachability_window(4));
+        assert_eq!(node.neighbors.reachability_window(), 4);
+    }
+
+    #[test]
+    fn non_link_local_source_is_dropped() {
+        let mut node = test_node(BabelConfig::new().require_link_local_source(true));
+        let global: SocketAddr = "[2001:db8::1]:6696".parse().unwrap();
+        let link_local: SocketAddr = "[fe80::1]:6696".parse().unwrap();
+
+        node.handle_tlvs_from(global, &[hello(1)]);
+        assert_eq!(node.neighbors().count(), 0);
+        assert_eq!(node.dropped_non_link_local(), 1);
+
+        node.handle_tlvs_from(link_local, &[hello(1)]);
+        assert_eq!(node.neighbors().count(), 1);
+        assert_eq!(node.dropped_non_link_local(), 1);
+    }
+
+    #[test]
+    fn global_source_accepted_without_flag() {
+        let mut node = test_node(BabelConfig::new());
+        let global: SocketAddr = "[2001:db8::1]:6696".parse().unwrap();
+
+        node.handle_tlvs_from(global, &[hello(1)]);
+        assert_eq!(node.neighbors().count(), 1);
+        assert_eq!(node.dropped_non_link_local(), 0);
+    }
+
+    #[test]
+    fn unsendable_queued_packet_is_dropped() {
+        let mut node = test_node(BabelConfig::new().multicast(false));
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        // An IPv6 destination can't be reached from our IPv4 socket.
+        let bytes = Packet::build_ack(1).to_bytes().unwrap();
+        node.send_queue.push(OutgoingPacket {
+            dest: "[2001:db8::1]:6696".parse().unwrap(),
+            bytes: bytes.clone(),
+        });
+        node.send_queue.push(OutgoingPacket {
+            dest: peer.local_addr().unwrap(),
+            bytes: bytes.clone(),
+        });
+
+        assert!(node.flush_send_queue().is_err());
+        assert!(node.send_queue.is_empty());
+        assert_eq!(node.metrics().send_errors, 1);
+
+        let mut buf = [0u8; 64];
+        let (n, _) = peer.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &bytes[..]);
+
+        assert_eq!(node.flush_send_queue().unwrap(), 0);
+    }
+
+    #[test]
+    fn packets_without_a_valid_hmac_are_dropped() {
+        let key = b"shared secret".to_vec();
+        let mut a = test_node(BabelConfig::new().hmac_key(key.clone()));
+        let mut b = test_node(BabelConfig::new().hmac_key(key));
+        let mut other = test_node(BabelConfig::new().hmac_key(b"other".to_vec()));
+        let mut open = test_node(BabelConfig::new());
+        let a_addr = a.sockets[0].local_addr().unwrap();
+
+        let hellos = a.tick(Instant::now());
+        let signed = &hellos[0].bytes;
+        let (pkt, trailer) = Packet::from_bytes_with_remainder(signed).unwrap();
+        assert!(matches!(
+            Tlv::parse_all_strict(trailer).unwrap().as_slice(),
+            [Tlv::Hmac { .. }]
+        ));
+
+        assert!(other.ingest(signed, a_addr).is_err());
+        assert_eq!(other.neighbors().count(), 0);
+
+        let unsigned = pkt.to_bytes().unwrap();
+        assert!(b.ingest(&unsigned, a_addr).is_err());
+        assert_eq!(b.neighbors().count(), 0);
+        assert_eq!(b.metrics().auth_failures, 1);
+
+        b.ingest(signed, a_addr).unwrap();
+        assert_eq!(b.neighbors().count(), 1);
+        open.ingest(signed, a_addr).unwrap();
+        assert_eq!(open.neighbors().count(), 1);
+    }
+
+    #[test]
+    fn replayed_packets_are_dropped() {
+        let key = b"shared secret".to_vec();
+        let mut a = test_node(BabelConfig::new().hmac_key(key.clone()));
+        let mut b = test_node(BabelConfig::new().hmac_key(key.clone()));
+        let a_addr = a.sockets[0].local_addr().unwrap();
+        let b_addr = b.sockets[0].local_addr().unwrap();
+        let t0 = Instant::now();
+
+        let first = a.tick(t0).remove(0).bytes;
+        let (pkt, _) = Packet::from_bytes_with_remainder(&first).unwrap();
+        assert!(matches!(pkt.tlvs().last(), Some(Tlv::Pc { pc: 0, index }) if index.len() == 4));
+
+        b.ingest(&first, a_addr).unwrap();
+        assert!(b.ingest(&first, a_addr).is_err());
+        assert_eq!(b.metrics().auth_failures, 1);
+
+        let second = a.tick(t0 + Duration::from_secs(4)).remove(0).bytes;
+        b.ingest(&second, a_addr).unwrap();
+        assert!(b.ingest(&first, a_addr).is_err());
+
+        // A validly signed packet without a counter can't be told apart
+        // from a replay.
+        let no_pc = Packet::build_hello(0, 1, 400)
+            .sign_hmac(&key, a_addr, b_addr)
+            .unwrap();
+        assert!(b.ingest(&no_pc, a_addr).is_err());
+        assert_eq!(b.metrics().auth_failures, 3);
+
+        // A replay over the socket is dropped without an error.
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut from_peer = pkt.to_bytes().unwrap();
+        Packet::append_hmac(&mut from_peer, &key, peer.local_addr().unwrap(), b_addr);
+        peer.send_to(&from_peer, b_addr).unwrap();
+        peer.send_to(&from_peer, b_addr).unwrap();
+        for _ in 0..100 {
+            b.poll().unwrap();
+            if b.metrics().auth_failures == 4 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(b.metrics().auth_failures, 4);
+        assert_eq!(b.metrics().packets_received, 3);
+    }
+
+    #[test]
+    fn bad_datagrams_are_dropped_without_stopping_the_node() {
+        let unsigned = Packet::build_hello(0, 1, 400).to_bytes().unwrap();
+        let garbage = [42, 2, 0, 9, 1];
+
+        // With a key, the garbage fails authentication before parsing.
+        for (config, auth_failures, parse_errors) in [
+            (BabelConfig::new().hmac_key(b"shared secret".to_vec()), 2, 0),
+            (BabelConfig::new(), 0, 1),
+        ] {
+            let mut node = test_node(config);
+            let node_addr = node.sockets[0].local_addr().unwrap();
+            let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            peer.send_to(&garbage, node_addr).unwrap();
+            peer.send_to(&unsigned, node_addr).unwrap();
+
+            let mut metrics = node.metrics();
+            for _ in 0..100 {
+                node.poll().unwrap();
+                metrics = node.metrics();
+                if metrics.auth_failures + metrics.parse_errors + metrics.packets_received >= 2 {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(metrics.auth_failures, auth_failures);
+            assert_eq!(metrics.parse_errors, parse_errors);
+        }
+    }
+
+    #[test]
+    fn unicast_peers_become_neighbors() {
+        // Also with HMAC, which covers both peers' addresses.
+        for config in [
+            BabelConfig::new(),
+            BabelConfig::new().hmac_key(b"shared secret".to_vec()),
+        ] {
+            let sock_a = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let sock_b = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let addr_a = sock_a.local_addr().unwrap();
+            let addr_b = sock_b.local_addr().unwrap();
+
+            let config = config.multicast(false);
+            let mut a = BabelNode::with_socket(
+                sock_a,
+                1,
+                [0xa; 8],
+                config.clone().add_unicast_peer(addr_b),
+            )
+            .unwrap();
+            let mut b =
+                BabelNode::with_socket(sock_b, 1, [0xb; 8], config.add_unicast_peer(addr_a))
+                    .unwrap();
+
+            for _ in 0..50 {
+                a.poll().unwrap();
+                b.poll().unwrap();
+                if a.neighbors.get(&addr_b, 1).is_some() && b.neighbors.get(&addr_a, 1).is_some() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            assert!(a.neighbors.get(&addr_b, 1).is_some());
+            assert!(b.neighbors.get(&addr_a, 1).is_some());
+        }
+    }
+
+    #[test]
+    fn identical_updates_refresh_without_events() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let key = remote_key();
+        let t0 = Instant::now();
+
+        let tlvs = [
+            Tlv::RouterId {
+                router_id: [9; 8],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: 1,
+                flags: 0,
+                plen: 24,
+                omitted: 0,
+                interval: 1000,
+                seqno: 1,
+                metric: 100,
+                prefix: vec![10, 9, 9],
+                sub_tlvs: Vec::new(),
+            },
+        ];
+
+        node.handle_tlvs_at(peer, &tlvs, t0);
+        assert!(!node.drain_events().is_empty());
+
+        for i in 1..=5 {
+            let t = t0 + Duration::from_secs(i);
+            node.handle_tlvs_at(peer, &tlvs, t);
+            assert_eq!(node.best_route(&key).unwrap().last_updated, t);
+        }
+
+        assert!(node.drain_events().is_empty());
+        assert_eq!(node.routes().len(), 1);
+    }
+
+    #[test]
+    fn nodes_converge_via_tick_and_ingest() {
+        let mut a = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let mut b = BabelNode::with_socket(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            1,
+            [0, 0, 0, 0, 0, 0, 0, 2],
+            BabelConfig::new(),
+        )
+        .unwrap();
+        let a_addr: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let b_addr: SocketAddr = "192.0.2.2:6696".parse().unwrap();
+        let now = Instant::now();
+
+        for step in 0..2u64 {
+            let t = now + Duration::from_secs(5 * step);
+            for pkt in a.tick(t) {
+                b.ingest(&pkt.bytes, a_addr).unwrap();
+            }
+            for pkt in b.tick(t) {
+                a.ingest(&pkt.bytes, b_addr).unwrap();
+            }
+        }
+
+        assert!(a.neighbors().any(|n| n.addr == b_addr));
+        assert!(b.neighbors().any(|n| n.addr == a_addr));
+        let route = b.best_route(&local_key()).unwrap();
+        assert_eq!(route.router_id, a.router_id());
+        assert_eq!(route.next_hop, Some(a_addr.ip()));
+    }
+
+    #[test]
+    fn periodic_hellos_are_jittered() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let config = BabelConfig::new().hello_interval_ms(1000).jitter_seed(7);
+        let mut node = BabelNode::with_socket(socket, 1, [1; 8], config).unwrap();
+        let t0 = Instant::now();
+
+        // Step in 1 ms and note when each Hello goes out.
+        let mut sent_at = Vec::new();
+        for ms in 0..20_000u64 {
+            let now = t0 + Duration::from_millis(ms);
+            node.tick(now);
+            if node.last_hello == Some(now) {
+                sent_at.push(ms);
+            }
+        }
+
+        let gaps: Vec<u64> = sent_at.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(gaps.len() >= 15, "{gaps:?}");
+        assert!(
+            gaps.iter().all(|gap| (750..=1251).contains(gap)),
+            "{gaps:?}"
+        );
+        assert!(gaps.iter().any(|gap| gap.abs_diff(1000) > 50), "{gaps:?}");
+
+        // The same seed gives the same schedule.
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let config = BabelConfig::new().hello_interval_ms(1000).jitter_seed(7);
+        let mut again = BabelNode::with_socket(socket, 1, [1; 8], config).unwrap();
+        let mut replay = Vec::new();
+        for ms in 0..20_000u64 {
+            let now = t0 + Duration::from_millis(ms);
+            again.tick(now);
+            if again.last_hello == Some(now) {
+                replay.push(ms);
+            }
+        }
+        assert_eq!(replay, sent_at);
+    }
+
+    #[test]
+    fn metrics_count_hellos_and_neighbors() {
+        let mut a = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let mut b = test_node(BabelConfig::new());
+        let a_addr: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let t0 = Instant::now();
+
+        for step in 0..3u32 {
+            for pkt in a.tick(t0 + Duration::from_secs(4) * step) {
+                b.ingest(&pkt.bytes, a_addr).unwrap();
+            }
+        }
+        assert!(b.ingest(&[42, 2, 0, 9, 1], a_addr).is_err());
+
+        let sent = a.metrics();
+        assert_eq!(sent.hellos_sent, 3);
+        assert!(sent.packets_sent >= 3);
+        assert_eq!(sent.routes_total, 1);
+        assert_eq!(sent.routes_best, 1);
+
+        let received = b.metrics();
+        assert_eq!(received.neighbors_total, 1);
+        assert_eq!(received.neighbors_reachable, 1);
+        assert_eq!(received.packets_received, sent.packets_sent);
+        assert!(received.tlvs_parsed >= received.packets_received);
+        assert_eq!(received.parse_errors, 1);
+        assert_eq!(received.routes_best, 1);
+        assert_eq!(received.hellos_sent, 0);
+    }
+
+    #[test]
+    fn next_hop_does_not_outlive_its_packet() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let via = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 99));
+
+        let update = |prefix: Vec<u8>| Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen: 24,
+            omitted: 0,
+            interval: 1000,
+            seqno: 1,
+            metric: 100,
+            prefix,
+            sub_tlvs: Vec::new(),
+        };
+
+        node.handle_tlvs_from(
+            peer,
+            &[
+                Tlv::RouterId {
+                    router_id: [9; 8],
+                    sub_tlvs: Vec::new(),
+                },
+                Tlv::NextHop {
+                    ae: 1,
+                    addr: Some(via),
+                    sub_tlvs: Vec::new(),
+                },
+                update(vec![10, 9, 9]),
+                update(vec![10, 9, 8]),
+            ],
+        );
+        node.handle_tlvs_from(
+            peer,
+            &[
+                Tlv::RouterId {
+                    router_id: [9; 8],
+                    sub_tlvs: Vec::new(),
+                },
+                update(vec![10, 9, 7]),
+            ],
+        );
+
+        let next_hop = |prefix: Vec<u8>| {
+            let key = RouteKey {
+                ae: 1,
+                plen: 24,
+                prefix,
+            };
+            node.best_route(&key).unwrap().next_hop
+        };
+        assert_eq!(next_hop(vec![10, 9, 9]), Some(via));
+        assert_eq!(next_hop(vec![10, 9, 8]), Some(via));
+        assert_eq!(next_hop(vec![10, 9, 7]), Some(peer.ip()));
+    }
+
+    #[test]
+    fn send_packets_counts_datagrams() {
+        let mut node = test_node(BabelConfig::new());
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        let packets = (1..=3).map(Packet::build_ack).collect();
+        let sent = node
+            .send_packets(packets, rx.local_addr().unwrap())
+            .unwrap();
+        assert_eq!(sent, 3);
+
+        let mut buf = [0u8; 64];
+        for opaque in 1..=3 {
+            let n = rx.recv(&mut buf).unwrap();
+            let pkt = Packet::from_bytes(&buf[..n]).unwrap();
+            assert_eq!(pkt.tlvs(), Packet::build_ack(opaque).tlvs());
+        }
+    }
+
+    #[test]
+    fn event_callback_fires_during_poll() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut node = test_node(BabelConfig::new().multicast(false));
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&seen);
+        node.on_event(Box::new(move |ev| sink.borrow_mut().push(ev.clone())));
+
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let hello = Packet::with_tlvs(vec![Tlv::Hello {
+            flags: 0,
+            seqno: 1,
+            interval: 400,
+            sub_tlvs: Vec::new(),
+        }]);
+        hello
+            .send_via(&peer, node.sockets[0].local_addr().unwrap())
+            .unwrap();
+
+        for _ in 0..50 {
+            node.poll().unwrap();
+            if !seen.borrow().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let peer_addr = peer.local_addr().unwrap();
+        assert!(matches!(
+            seen.borrow().as_slice(),
+            [Event::NeighborUp(addr, _)] if *addr == peer_addr
+        ));
+        // The queue is still filled for drain_events users.
+        assert_eq!(node.drain_events().len(), 1);
+    }
+
+    #[test]
+    fn prefix_subscription_filters_route_events() {
+        let mut node = test_node(BabelConfig::new());
+        let rx = node.subscribe_prefix(RouteKey {
+            ae: 1,
+            plen: 8,
+            prefix: vec![10],
+        });
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        let update = |plen: u8, prefix: Vec<u8>| Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen,
+            omitted: 0,
+            interval: 1000,
+            seqno: 1,
+            metric: 100,
+            prefix,
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_from(
+            peer,
+            &[
+                Tlv::RouterId {
+                    router_id: [9; 8],
+                    sub_tlvs: Vec::new(),
+                },
+                update(24, vec![192, 168, 0]),
+                update(16, vec![10, 1]),
+            ],
+        );
+
+        let inside = RouteKey {
+            ae: 1,
+            plen: 16,
+            prefix: vec![10, 1],
+        };
+        let received: Vec<Event> = rx.try_iter().collect();
+        assert!(!received.is_empty());
+        assert!(received.iter().all(|e| e.route_key() == Some(&inside)));
+    }
+
+    /// Seqnos of all Updates in the packets of one `tick`.
+    fn update_seqnos(node: &mut BabelNode, now: Instant) -> Vec<u16> {
+        node.tick(now)
+            .iter()
+            .flat_map(|p| Packet::from_bytes(&p.bytes).unwrap().tlvs().to_vec())
+            .filter_map(|tlv| match tlv {
+                Tlv::Update { seqno, .. } => Some(seqno),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `(metric, prefix)` of all Updates in the packets of one `tick`.
+    fn tick_updates(node: &mut BabelNode, now: Instant) -> Vec<(u16, Vec<u8>)> {
+        node.tick(now)
+            .iter()
+            .flat_map(|p| Packet::from_bytes(&p.bytes).unwrap().tlvs().to_vec())
+            .filter_map(|tlv| match tlv {
+                Tlv::Update { metric, prefix, .. } => Some((metric, prefix)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn best_route_changes_trigger_updates() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let t0 = Instant::now();
+        assert!(tick_updates(&mut node, t0).is_empty());
+
+        // Well before the Update timer is due.
+        announce(&mut node, peer, 100, 1);
+        let updates = tick_updates(&mut node, t0 + Duration::from_millis(10));
+        assert_eq!(updates, vec![(100, vec![10, 9, 9])]);
+        assert!(tick_updates(&mut node, t0 + Duration::from_millis(20)).is_empty());
+
+        announce(&mut node, peer, INFINITY, 2);
+        let updates = tick_updates(&mut node, t0 + Duration::from_millis(30));
+        assert_eq!(updates, vec![(INFINITY, vec![10, 9, 9])]);
+    }
+
+    #[test]
+    fn readvertised_routes_carry_our_next_hop() {
+        let local = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let mut node = test_node(BabelConfig::new().with_local_address(local));
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let t0 = Instant::now();
+        node.tick(t0);
+
+        announce(&mut node, peer, 100, 1);
+        assert_eq!(
+            node.best_route(&remote_key()).unwrap().next_hop,
+            Some(peer.ip())
+        );
+
+        let tlvs: Vec<Tlv> = node
+            .tick(t0 + Duration::from_millis(10))
+            .iter()
+            .flat_map(|p| Packet::from_bytes(&p.bytes).unwrap().tlvs().to_vec())
+            .collect();
+        let upd = tlvs
+            .iter()
+            .position(|t| matches!(t, Tlv::Update { .. }))
+            .expect("triggered Update");
+        assert!(matches!(
+            &tlvs[upd - 1],
+            Tlv::NextHop { ae: 1, addr: Some(a), .. } if *a == local
+        ));
+    }
+
+    #[test]
+    fn initial_seqno_is_used_by_first_update() {
+        let config = BabelConfig::new()
+            .with_advertised_prefix(local_prefix())
+            .initial_seqno(1000);
+        let mut node = test_node(config);
+        assert_eq!(update_seqnos(&mut node, Instant::now()), vec![1000]);
+    }
+
+    #[test]
+    fn seqno_survives_save_and_load() {
+        let path = std::env::temp_dir().join(format!("babel_rs_seqno_{}", std::process::id()));
+        let node = test_node(BabelConfig::new().initial_seqno(u16::MAX));
+        node.save_seqno(&path).unwrap();
+
+        let mut restarted = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        restarted.load_seqno(&path).unwrap();
+        assert_eq!(restarted.route_seqno(), 0);
+        assert_eq!(restarted.best_route(&local_key()).unwrap().seqno, 0);
+
+        std::fs::write(&path, "soon").unwrap();
+        let err = restarted.load_seqno(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn route_seqno_is_stable_until_bumped() {
+        let other = AdvertisedPrefix {
+            prefix: vec![10, 0, 2],
+            ..local_prefix()
+        };
+        let config = BabelConfig::new()
+            .with_advertised_prefix(local_prefix())
+            .with_advertised_prefix(other)
+            .route_seqno_interval_ms(60_000);
+        let mut node = test_node(config);
+        let interval = Duration::from_millis(10_000);
+        let t0 = Instant::now();
+
+        let first = update_seqnos(&mut node, t0);
+        assert_eq!(first, vec![1, 1]);
+        assert_eq!(update_seqnos(&mut node, t0 + interval), first);
+
+        node.bump_route_seqno();
+        assert_eq!(update_seqnos(&mut node, t0 + interval * 2), vec![2, 2]);
+        assert_eq!(node.best_route(&local_key()).unwrap().seqno, 2);
+
+        // The long timer bumps it on its own.
+        assert_eq!(update_seqnos(&mut node, t0 + interval * 6), vec![3, 3]);
+    }
+
+    #[test]
+    fn router_id_applies_to_following_updates_only() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        let router_id = |id: u8| Tlv::RouterId {
+            router_id: [id; 8],
+            sub_tlvs: Vec::new(),
+        };
+        let update = |prefix: Vec<u8>| Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen: 24,
+            omitted: 0,
+            interval: 1000,
+            seqno: 1,
+            metric: 100,
+            prefix,
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_from(
+            peer,
+            &[
+                router_id(0xa),
+                update(vec![10, 9, 9]),
+                router_id(0xb),
+                update(vec![10, 9, 8]),
+            ],
+        );
+        // Without a RouterId of its own, a later packet's Update is ignored.
+        node.handle_tlvs_from(peer, &[update(vec![10, 9, 7])]);
+
+        let router = |prefix: Vec<u8>| {
+            let key = RouteKey {
+                ae: 1,
+                plen: 24,
+                prefix,
+            };
+            node.best_route(&key).map(|r| r.router_id)
+        };
+        assert_eq!(router(vec![10, 9, 9]), Some([0xa; 8]));
+        assert_eq!(router(vec![10, 9, 8]), Some([0xb; 8]));
+        assert_eq!(router(vec![10, 9, 7]), None);
+    }
+
+    #[test]
+    fn handle_packet_bytes_installs_route() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        #[rustfmt::skip]
+        let buf = [
+            42, 2, 0, 35,
+            // Hello: seqno 7, interval 400
+            4, 6, 0, 0, 0, 7, 0x01, 0x90,
+            // RouterId 09:09:..
+            6, 10, 0, 0, 9, 9, 9, 9, 9, 9, 9, 9,
+            // Update 10.9.9.0/24: interval 1000, seqno 1, metric 100
+            8, 13, 1, 0, 24, 0, 0x03, 0xe8, 0, 1, 0, 100, 10, 9, 9,
+        ];
+        node.handle_packet_bytes(&buf, peer).unwrap();
+
+        let route = node.best_route(&remote_key()).unwrap();
+        assert_eq!(route.router_id, [9; 8]);
+        assert_eq!(route.advertised_metric, 100);
+
+        let events = node.drain_events();
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, Event::NeighborUp(a, _) if *a == peer))
+        );
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, Event::BestRouteChanged(k, _) if *k == remote_key()))
+        );
+
+        assert!(node.handle_packet_bytes(&buf[..20], peer).is_err());
+    }
+
+    #[test]
+    fn failed_multicast_join_falls_back_to_unicast() {
+        // Not a local address, so joining the group on it fails.
+        let bogus = Ipv4Addr::new(198, 51, 100, 1);
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let socket = || UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let strict = BabelConfig::new().add_unicast_peer(peer);
+        assert!(BabelNode::with_v4_multicast_socket(socket(), bogus, 1, [1; 8], strict).is_err());
+
+        let no_peers = BabelConfig::new().require_multicast(false);
+        assert!(BabelNode::with_v4_multicast_socket(socket(), bogus, 1, [1; 8], no_peers).is_err());
+
+        let relaxed = BabelConfig::new()
+            .add_unicast_peer(peer)
+            .require_multicast(false);
+        let node =
+            BabelNode::with_v4_multicast_socket(socket(), bogus, 1, [1; 8], relaxed).unwrap();
+        assert_eq!(node.destinations(), vec![peer]);
+    }
+
+    #[test]
+    fn v6_nodes_exchange_hellos() {
+        let sock_a = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let sock_b = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let addr_a = sock_a.local_addr().unwrap();
+        let addr_b = sock_b.local_addr().unwrap();
+
+        let config = BabelConfig::new().multicast(false);
+        let mut a =
+            BabelNode::with_socket(sock_a, 1, [0xa; 8], config.clone().add_unicast_peer(addr_b))
+                .unwrap();
+        let mut b =
+            BabelNode::with_socket(sock_b, 1, [0xb; 8], config.add_unicast_peer(addr_a)).unwrap();
+
+        for _ in 0..50 {
+            a.poll().unwrap();
+            b.poll().unwrap();
+            if a.neighbors.get(&addr_b, 1).is_some() && b.neighbors.get(&addr_a, 1).is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(a.neighbors.get(&addr_b, 1).is_some());
+        assert!(b.neighbors.get(&addr_a, 1).is_some());
+
+        // With multicast on, Hellos go to the link-scoped v6 group.
+        let socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let multicast = BabelNode::with_socket(socket, 1, [1; 8], BabelConfig::new()).unwrap();
+        assert_eq!(
+            multicast.destinations(),
+            vec![SocketAddr::from(SocketAddrV6::new(
+                MULTICAST_V6_ADDR,
+                BABEL_PORT,
+                0,
+                1
+            ))]
+        );
+    }
+
+    #[test]
+    fn dual_stack_node_hears_both_families() {
+        let v4 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let v6 = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let node_v4 = v4.local_addr().unwrap();
+        let node_v6 = v6.local_addr().unwrap();
+
+        let peer_v4 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let peer_v6 = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let addr_v4 = peer_v4.local_addr().unwrap();
+        let addr_v6 = peer_v6.local_addr().unwrap();
+
+        let config = BabelConfig::new()
+            .multicast(false)
+            .add_unicast_peer(addr_v4)
+            .add_unicast_peer(addr_v6);
+        let mut node = BabelNode::with_sockets(vec![v4, v6], 1, [1; 8], config).unwrap();
+
+        let hello = Packet::build_hello(0, 1, 1000);
+        hello.send_via(&peer_v4, node_v4).unwrap();
+        hello.send_via(&peer_v6, node_v6).unwrap();
+        for _ in 0..50 {
+            node.poll().unwrap();
+            if node.neighbors.get(&addr_v4, 1).is_some()
+                && node.neighbors.get(&addr_v6, 1).is_some()
+            {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(node.neighbors.get(&addr_v4, 1).is_some());
+        assert!(node.neighbors.get(&addr_v6, 1).is_some());
+
+        // Each peer hears us from the socket of its own family.
+        let mut buf = [0u8; 1500];
+        for (peer, from) in [(&peer_v4, node_v4), (&peer_v6, node_v6)] {
+            peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            let (_, src) = peer.recv_from(&mut buf).unwrap();
+            assert_eq!(src, from);
+        }
+
+        let multicast = BabelNode::with_sockets(
+            vec![
+                UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap(),
+                UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap(),
+            ],
+            1,
+            [1; 8],
+            BabelConfig::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            multicast.destinations(),
+            vec![
+                SocketAddr::from((MULTICAST_V4_ADDR, BABEL_PORT)),
+                SocketAddrV6::new(MULTICAST_V6_ADDR, BABEL_PORT, 0, 1).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn update_with_unknown_mandatory_subtlv_is_ignored() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        let update = |sub_tlvs: Vec<SubTlv>| Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen: 24,
+            omitted: 0,
+            interval: 1000,
+            seqno: 1,
+            metric: 100,
+            prefix: vec![10, 9, 9],
+            sub_tlvs,
+        };
+        let router_id = Tlv::RouterId {
+            router_id: [9; 8],
+            sub_tlvs: Vec::new(),
+        };
+        let unknown = |stype| SubTlv::Unknown {
+            stype,
+            data: vec![1, 2],
+        };
+
+        node.handle_tlvs_from(peer, &[router_id.clone(), update(vec![unknown(130)])]);
+        assert!(node.best_route(&remote_key()).is_none());
+
+        // Unknown non-mandatory sub-TLVs are simply skipped.
+        node.handle_tlvs_from(peer, &[router_id, update(vec![unknown(5)])]);
+        assert!(node.best_route(&remote_key()).is_some());
+    }
+
+    #[test]
+    fn source_specific_update_installs_nothing() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        node.handle_tlvs_from(
+            peer,
+            &[
+                Tlv::RouterId {
+                    router_id: [9; 8],
+                    sub_tlvs: Vec::new(),
+                },
+                Tlv::Update {
+                    ae: 1,
+                    flags: 0,
+                    plen: 24,
+                    omitted: 0,
+                    interval: 1000,
+                    seqno: 1,
+                    metric: 100,
+                    prefix: vec![10, 9, 9],
+                    sub_tlvs: vec![SubTlv::SourcePrefix {
+                        plen: 16,
+                        source_prefix: vec![192, 168],
+                    }],
+                },
+            ],
+        );
+        assert!(node.best_route(&remote_key()).is_none());
+        assert!(node.routes().iter().all(|r| r.key != remote_key()));
+    }
+
+    #[test]
+    fn compressed_updates_are_expanded() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+
+        let full = |prefix: Vec<u8>| Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen: 24,
+            omitted: 0,
+            interval: 1000,
+            seqno: 1,
+            metric: 100,
+            prefix,
+            sub_tlvs: Vec::new(),
+        };
+        let mut tlvs = vec![Tlv::RouterId {
+            router_id: [9; 8],
+            sub_tlvs: Vec::new(),
+        }];
+        tlvs.extend(Packet::compress_updates(vec![
+            full(vec![10, 9, 9]),
+            full(vec![10, 9, 8]),
+        ]));
+        assert!(matches!(tlvs[2], Tlv::Update { omitted: 2, .. }));
+
+        node.handle_tlvs_from(peer, &tlvs);
+
+        let follow_up = RouteKey {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 9, 8],
+        };
+        assert!(node.best_route(&remote_key()).is_some());
+        assert_eq!(node.best_route(&follow_up).unwrap().key, follow_up);
+    }
+
+    #[test]
+    fn route_and_seqno_requests_reach_peers() {
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let config = BabelConfig::new()
+            .multicast(false)
+            .add_unicast_peer(peer_addr);
+        let mut node = test_node(config);
+        node.poll().unwrap();
+
+        let mut buf = [0u8; 1500];
+        let mut next_tlv = || loop {
+            let (n, _) = peer.recv_from(&mut buf).unwrap();
+            let tlvs = Packet::from_bytes(&buf[..n]).unwrap().into_tlvs();
+            if let Some(tlv) = tlvs
+                .into_iter()
+                .find(|t| matches!(t, Tlv::RouteRequest { .. } | Tlv::SeqnoRequest { .. }))
+            {
+                return tlv;
+            }
+        };
+
+        node.request_route(&remote_key());
+        node.poll().unwrap();
+        assert!(matches!(
+            next_tlv(),
+            Tlv::RouteRequest { ae: 1, plen: 24, prefix, .. } if prefix == remote_key().prefix
+        ));
+
+        announce(&mut node, "192.0.2.10:6696".parse().unwrap(), 100, 7);
+        node.request_seqno(&remote_key(), [9; 8]);
+        node.poll().unwrap();
+        assert!(matches!(
+            next_tlv(),
+            Tlv::SeqnoRequest { seqno: 8, hop_count: SEQNO_REQUEST_HOP_COUNT, router_id, .. }
+                if router_id == [9; 8]
+        ));
+    }
+
+    #[test]
+    fn wildcard_route_request_dumps_advertised_prefixes() {
+        let mut node = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let now = Instant::now();
+        node.tick(now);
+
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let request = Tlv::RouteRequest {
+            ae: 0,
+            plen: 0,
+            prefix: Vec::new(),
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_at(peer, &[request], now);
+
+        let replies: Vec<_> = node
+            .tick(now)
+            .into_iter()
+            .filter(|p| p.dest == peer)
+            .collect();
+        assert_eq!(replies.len(), 1);
+        let pkt = Packet::from_bytes(&replies[0].bytes).unwrap();
+        assert!(pkt.tlvs().iter().any(|t| matches!(
+            t,
+            Tlv::Update { ae: 1, plen: 24, prefix, .. } if *prefix == local_key().prefix
+        )));
+    }
+
+    #[test]
+    fn wildcard_retraction_drops_all_routes_from_source() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let other: SocketAddr = "192.0.2.20:6696".parse().unwrap();
+
+        announce(&mut node, peer, 100, 1);
+        let mut other_key = local_key();
+        other_key.prefix = vec![10, 7, 7];
+        node.handle_tlvs_from(
+            other,
+            &[
+                Tlv::RouterId {
+                    router_id: [7; 8],
+                    sub_tlvs: Vec::new(),
+                },
+                Tlv::Update {
+                    ae: 1,
+                    flags: 0,
+                    plen: 24,
+                    omitted: 0,
+                    interval: 1000,
+                    seqno: 1,
+                    metric: 100,
+                    prefix: other_key.prefix.clone(),
+                    sub_tlvs: Vec::new(),
+                },
+            ],
+        );
+        node.drain_events();
+
+        let retraction = Tlv::Update {
+            ae: 0,
+            flags: 0,
+            plen: 0,
+            omitted: 0,
+            interval: 1000,
+            seqno: 0,
+            metric: INFINITY,
+            prefix: Vec::new(),
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_from(peer, &[retraction]);
+
+        assert!(node.best_route(&remote_key()).is_none());
+        assert!(node.best_route(&other_key).is_some());
+        assert!(
+            node.drain_events()
+                .iter()
+                .any(|e| matches!(e, Event::RouteRetracted(k) if *k == remote_key()))
+        );
+    }
+
+    fn seqno_request(seqno: u16, hop_count: u8, router_id: [u8; 8], key: RouteKey) -> Tlv {
+        Tlv::SeqnoRequest {
+            ae: key.ae,
+            plen: key.plen,
+            seqno,
+            hop_count,
+            router_id,
+            prefix: key.prefix,
+            sub_tlvs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn seqno_request_for_own_prefix_bumps_and_announces() {
+        let mut node = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let now = Instant::now();
+        node.tick(now);
+        assert_eq!(node.route_seqno(), 1);
+
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let request = seqno_request(5, 16, node.router_id(), local_key());
+        node.handle_tlvs_at(peer, &[request], now);
+        assert_eq!(node.route_seqno(), 2);
+
+        let announced = node.tick(now).into_iter().any(|p| {
+            Packet::from_bytes(&p.bytes).unwrap().tlvs().iter().any(|t| {
+                matches!(t, Tlv::Update { seqno: 2, prefix, .. } if *prefix == local_key().prefix)
+            })
+        });
+        assert!(announced);
+    }
+
+    #[test]
+    fn seqno_request_for_foreign_prefix_is_forwarded() {
+        let mut node = test_node(BabelConfig::new());
+        let upstream: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let requester: SocketAddr = "192.0.2.20:6696".parse().unwrap();
+        let now = Instant::now();
+        announce(&mut node, upstream, 100, 1);
+        node.tick(now);
+
+        let request = seqno_request(3, 4, [9; 8], remote_key());
+        node.handle_tlvs_at(requester, &[request], now);
+
+        let forwarded: Vec<_> = node
+            .tick(now)
+            .into_iter()
+            .filter(|p| p.dest == upstream)
+            .collect();
+        assert_eq!(forwarded.len(), 1);
+        let pkt = Packet::from_bytes(&forwarded[0].bytes).unwrap();
+        assert_eq!(pkt.tlvs(), &[seqno_request(3, 3, [9; 8], remote_key())]);
+
+        // A request that has run out of hops goes nowhere.
+        let request = seqno_request(3, 1, [9; 8], remote_key());
+        node.handle_tlvs_at(requester, &[request], now);
+        assert!(node.tick(now).iter().all(|p| p.dest != upstream));
+    }
+
+    #[test]
+    fn seqno_request_for_other_router_id_is_answered() {
+        let mut node = test_node(BabelConfig::new());
+        let upstream: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let requester: SocketAddr = "192.0.2.20:6696".parse().unwrap();
+        let t0 = Instant::now();
+        announce(&mut node, upstream, 100, 1);
+        node.tick(t0);
+
+        // Our route is from [9; 8]; a newer seqno of [7; 8] is no reason
+        // to forward.
+        let now = t0 + Duration::from_millis(1500);
+        let request = seqno_request(3, 4, [7; 8], remote_key());
+        node.handle_tlvs_at(requester, &[request], now);
+
+        let tlvs: Vec<Tlv> = node
+            .tick(now)
+            .iter()
+            .flat_map(|p| Packet::from_bytes(&p.bytes).unwrap().into_tlvs())
+            .collect();
+        assert!(tlvs.iter().all(|t| !matches!(t, Tlv::SeqnoRequest { .. })));
+        assert!(tlvs.iter().any(|t| {
+            matches!(t, Tlv::Update { seqno: 1, prefix, .. } if *prefix == remote_key().prefix)
+        }));
+    }
+
+    #[test]
+    fn ihus_are_batched_into_one_packet() {
+        let mut node = test_node(BabelConfig::new());
+        let t0 = Instant::now();
+        let peers: Vec<SocketAddr> = (10..13)
+            .map(|i| format!("192.0.2.{i}:6696").parse().unwrap())
+            .collect();
+        for peer in &peers {
+            node.handle_tlvs_at(*peer, &[hello(1)], t0);
+        }
+
+        let with_ihus: Vec<Vec<Tlv>> = node
+            .tick(t0)
+            .iter()
+            .map(|p| Packet::from_bytes(&p.bytes).unwrap().into_tlvs())
+            .filter(|tlvs| matches!(tlvs.first(), Some(Tlv::Ihu { .. })))
+            .collect();
+        assert_eq!(with_ihus.len(), node.destinations().len());
+        let mut addrs: Vec<Option<IpAddr>> = with_ihus[0]
+            .iter()
+            .map(|tlv| match tlv {
+                Tlv::Ihu { addr, .. } => *addr,
+                _ => None,
+            })
+            .collect();
+        addrs.sort();
+        let want: Vec<Option<IpAddr>> = peers.iter().map(|p| Some(p.ip())).collect();
+        assert_eq!(addrs, want);
+    }
+
+    #[test]
+    fn ihus_for_other_nodes_are_ignored() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let ihu = |addr: Ipv4Addr| Tlv::Ihu {
+            ae: 1,
+            rxcost: 96,
+            interval: 400,
+            addr: Some(IpAddr::V4(addr)),
+            sub_tlvs: Vec::new(),
+        };
+
+        node.handle_tlvs_from(peer, &[ihu(Ipv4Addr::new(192, 0, 2, 99))]);
+        assert!(node.neighbors.get(&peer, 1).is_none());
+
+        node.handle_tlvs_from(peer, &[ihu(Ipv4Addr::LOCALHOST)]);
+        assert!(node.neighbors.get(&peer, 1).is_some());
+    }
+
+    #[test]
+    fn ihu_advertises_hello_loss() {
+        let mut node = test_node(BabelConfig::new().link_cost_strategy(LinkCostStrategy::Etx));
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let t0 = Instant::now();
+        let ihu_rxcost = |out: Vec<OutgoingPacket>| {
+            out.iter()
+                .flat_map(|p| Packet::from_bytes(&p.bytes).unwrap().into_tlvs())
+                .find_map(|tlv| match tlv {
+                    Tlv::Ihu { rxcost, addr, .. } if addr == Some(peer.ip()) => Some(rxcost),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        node.handle_tlvs_at(peer, &[hello(1)], t0);
+        node.handle_tlvs_at(peer, &[hello(2)], t0 + Duration::from_millis(1000));
+        assert_eq!(ihu_rxcost(node.tick(t0 + Duration::from_millis(1000))), 256);
+
+        // Every other Hello is lost from here on.
+        let mut now = t0 + Duration::from_millis(1000);
+        for seqno in [4, 6, 8] {
+            now += Duration::from_millis(2000);
+            node.handle_tlvs_at(peer, &[hello(seqno)], now);
+        }
+        node.last_ihu = None;
+        assert!(ihu_rxcost(node.tick(now)) > 256);
+    }
+
+    #[test]
+    fn first_hello_after_ihu_makes_neighbor_reachable() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let hello = |seqno| Tlv::Hello {
+            flags: 0,
+            seqno,
+            interval: 400,
+            sub_tlvs: Vec::new(),
+        };
+
+        // Known from an IHU only, so not reachable yet.
+        let ihu = Tlv::Ihu {
+            ae: 1,
+            rxcost: 96,
+            interval: 400,
+            addr: None,
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_from(peer, &[ihu]);
+        node.drain_events();
+
+        node.handle_tlvs_from(peer, &[hello(1)]);
+        let events = node.drain_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            Event::NeighborReachabilityChanged(a, true) if a == peer
+        ));
+
+        // Further Hellos don't change anything.
+        node.handle_tlvs_from(peer, &[hello(2)]);
+        assert!(node.drain_events().is_empty());
+    }
+
+    #[test]
+    fn unrefreshed_routes_expire() {
+        let mut node = test_node(BabelConfig::new());
+        let peer: SocketAddr = "192.0.2.10:6696".parse().unwrap();
+        let t0 = Instant::now();
+
+        // A 1 s Update interval: the route is held for 3.5 s.
+        let tlvs = [
+            Tlv::RouterId {
+                router_id: [9; 8],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: 1,
+                flags: 0,
+                plen: 24,
+                omitted: 0,
+                interval: 1000,
+                seqno: 1,
+                metric: 100,
+                prefix: vec![10, 9, 9],
+                sub_tlvs: Vec::new(),
+            },
+        ];
+        node.handle_tlvs_at(peer, &tlvs, t0);
+        node.drain_events();
+
+        node.tick(t0 + Duration::from_secs(3));
+        assert!(node.best_route(&remote_key()).is_some());
+
+        node.tick(t0 + Duration::from_secs(4));
+        assert!(node.best_route(&remote_key()).is_none());
+        assert!(
+            node.drain_events()
+                .iter()
+                .any(|e| matches!(e, Event::RouteRetracted(k) if *k == remote_key()))
+        );
+    }
+
+    #[test]
+    fn packets_leave_from_the_node_socket() {
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let config = BabelConfig::new()
+            .multicast(false)
+            .add_unicast_peer(peer_addr);
+        let mut node = test_node(config);
+        let node_addr = node.sockets[0].local_addr().unwrap();
+
+        // Make the peer a neighbor so it also gets an IHU.
+        let hello = Tlv::Hello {
+            flags: 0,
+            seqno: 1,
+            interval: 400,
+            sub_tlvs: Vec::new(),
+        };
+        node.handle_tlvs_from(peer_addr, &[hello]);
+        node.send_hello().unwrap();
+        node.send_ihus().unwrap();
+
+        let mut buf = [0u8; 1500];
+        for _ in 0..2 {
+            let (_, src) = peer.recv_from(&mut buf).unwrap();
+            assert_eq!(src, node_addr);
+        }
+    }
+
+    #[test]
+    fn shutdown_retracts_advertised_prefixes() {
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        let other = AdvertisedPrefix {
+            prefix: vec![10, 0, 2],
+            ..local_prefix()
+        };
+        let config = BabelConfig::new()
+            .multicast(false)
+            .add_unicast_peer(peer.local_addr().unwrap())
+            .with_advertised_prefix(local_prefix())
+            .with_advertised_prefix(other);
+        let mut node = test_node(config);
+        node.shutdown().unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (tlvs, _) = Packet::recv(&peer, &mut buf).unwrap();
+        assert!(
+            matches!(tlvs[0], Tlv::RouterId { router_id, .. } if router_id == node.router_id())
+        );
+        let retracted: Vec<(u16, Vec<u8>)> = tlvs
+            .into_iter()
+            .filter_map(|tlv| match tlv {
+                Tlv::Update { metric, prefix, .. } => Some((metric, prefix)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            retracted,
+            vec![(INFINITY, vec![10, 0, 1]), (INFINITY, vec![10, 0, 2])]
+        );
+    }
+
+    #[test]
+    fn router_id_from_mac_is_eui64() {
+        let mac = [0x02, 0xfc, 0x00, 0x00, 0x00, 0x01];
+        let id = BabelNode::router_id_from_mac_bytes(mac);
+        assert_eq!(id, [0x00, 0xfc, 0x00, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn sysfs_mac_is_parsed() {
+        assert_eq!(
+            parse_mac("02:fc:00:00:00:01\n").unwrap(),
+            [0x02, 0xfc, 0x00, 0x00, 0x00, 0x01]
+        );
+        assert_eq!(
+            parse_mac("DE:AD:be:ef:00:FF").unwrap(),
+            [0xde, 0xad, 0xbe, 0xef, 0x00, 0xff]
+        );
+
+        for bad in [
+            "",
+            "02:fc:00:00:00",
+            "02:fc:00:00:00:01:02",
+            "02:fc:00:00:00:zz",
+            "02:fc:00:00:00:100",
+            "02:fc::00:00:01",
+            "02-fc-00-00-00-01",
+            "+2:fc:00:00:00:01",
+        ] {
+            let err = parse_mac(bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn router_id_from_v6_uses_interface_id() {
+        let addr: Ipv6Addr = "fe80::fc:ff:fe00:1".parse().unwrap();
+        let id = BabelNode::router_id_from_v6(addr);
+        assert_eq!(id, [0x00, 0xfc, 0x00, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn duplicate_triggered_updates_are_suppressed() {
+        let mut node = test_node(
+            BabelConfig::new()
+                .update_dedup_window_ms(500)
+                .with_advertised_prefix(local_prefix()),
+        );
+        let key = local_key();
+        let now = Instant::now();
+
+        assert!(node.triggered_update_tlvs(&key, now).is_some());
+        for i in 1..10 {
+            let t = now + Duration::from_millis(i * 10);
+            assert!(node.triggered_update_tlvs(&key, t).is_none());
+        }
+
+        // Once the window has elapsed the same Update may go out again.
+        let later = now + Duration::from_millis(600);
+        assert!(node.triggered_update_tlvs(&key, later).is_some());
+    }
+
+    #[test]
+    fn changed_triggered_update_is_not_suppressed() {
+        let mut node = test_node(BabelConfig::new().with_advertised_prefix(local_prefix()));
+        let key = local_key();
+        let now = Instant::now();
+
+        assert!(node.triggered_update_tlvs(&key, now).is_some());
+        let bumped = node.route_seqno().wrapping_add(1);
+        node.routes.install_or_update(Route {
+            key: key.clone(),
+            metric: 0,
+            advertised_metric: 0,
+            smoothed_metric: 0,
+            seqno: bumped,
+            router_id: node.router_id(),
+            next_hop: None,
+            iface_index: 1,
+            last_updated: now,
+            expires_at: now,
+        });
+
+        let tlvs = node
+            .triggered_update_tlvs(&key, now)
+            .expect("changed Update sent");
+        assert!(matches!(tlvs[1], Tlv::Update { seqno, .. } if seqno == bumped));
+    }
 }
@@ -2,17 +2,63 @@
 // Babel packet construction and I/O helpers with RFC-compliant builders,
 // multicast support, and integration tests
 
+use std::fmt;
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
 
-use crate::tlv::Tlv;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use crate::routing::RouteKey;
+use crate::tlv::{DefaultPrefixes, Tlv, TlvError, UPDATE_FLAG_DEFAULT_PREFIX};
 
 /// Babel default port and multicast group addresses
 pub const BABEL_PORT: u16 = 6696;
 pub const MULTICAST_V4_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 111);
 pub const MULTICAST_V6_ADDR: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x0006);
 
+/// TTL / hop limit of our multicast: Babel never leaves the link.
+pub const MULTICAST_TTL: u32 = 1;
+
+/// Minimum IPv6 MTU, used when the interface MTU can't be determined.
+pub const DEFAULT_MTU: usize = 1280;
+
+/// IPv4 (20) + UDP (8) header overhead.
+const V4_OVERHEAD: usize = 28;
+/// IPv6 (40) + UDP (8) header overhead.
+const V6_OVERHEAD: usize = 48;
+
+/// Largest UDP payload that fits in one datagram on a link with this MTU.
+pub fn udp_payload_size(mtu: usize, v6: bool) -> usize {
+    let overhead = if v6 { V6_OVERHEAD } else { V4_OVERHEAD };
+    mtu.saturating_sub(overhead)
+}
+
+/// Name of the network interface with the given index (Linux sysfs).
+pub fn interface_name(index: u32) -> io::Result<String> {
+    for entry in std::fs::read_dir("/sys/class/net")? {
+        let entry = entry?;
+        let ifindex = std::fs::read_to_string(entry.path().join("ifindex"))?;
+        if ifindex.trim().parse::<u32>().ok() == Some(index) {
+            return Ok(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no interface with index {index}"),
+    ))
+}
+
+/// MTU of the named network interface (Linux sysfs).
+pub fn interface_mtu(name: &str) -> io::Result<usize> {
+    let text = std::fs::read_to_string(format!("/sys/class/net/{name}/mtu"))?;
+    text.trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
 /// A Babel packet: a sequence of TLVs to be sent via UDP
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Packet {
     tlvs: Vec<Tlv>,
 }
@@ -20,22 +66,58 @@ pub struct Packet {
 impl Packet {
     pub const BABEL_MAGIC: u8 = 42;
     pub const BABEL_VERSION: u8 = 2;
+    /// Size of the packet header: magic, version and body length.
+    pub const HEADER_LEN: usize = 4;
 
     pub fn new() -> Self {
-        Packet { tlvs: Vec::new() }
+        Self::default()
     }
 
     pub fn with_tlvs(tlvs: Vec<Tlv>) -> Self {
         Packet { tlvs }
     }
 
+    /// Greedily split `tlvs` into packets whose bodies fit in `max_body`
+    /// bytes, keeping their order.
+    ///
+    /// A TLV that is larger than the budget on its own still goes out,
+    /// alone in its packet.
+    pub fn pack_within(tlvs: Vec<Tlv>, max_body: usize) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        let mut current = Packet::new();
+        let mut len = 0usize;
+
+        for tlv in tlvs {
+            let tlv_len = tlv.wire_len();
+            if !current.tlvs.is_empty() && len + tlv_len > max_body {
+                packets.push(std::mem::take(&mut current));
+                len = 0;
+            }
+            len += tlv_len;
+            current.tlvs.push(tlv);
+        }
+
+        if !current.tlvs.is_empty() {
+            packets.push(current);
+        }
+        packets
+    }
+
     pub fn add_tlv(&mut self, tlv: Tlv) {
         self.tlvs.push(tlv);
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let body: Vec<u8> = self.tlvs.iter().flat_map(|t| t.to_bytes()).collect();
-        let body_len = body.len() as u16;
+    /// Serialize header and TLVs.
+    ///
+    /// Fails if a TLV doesn't fit its length field (see [`Tlv::to_bytes`]),
+    /// or the body doesn't fit the header's.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TlvError> {
+        let mut body = Vec::new();
+        for tlv in &self.tlvs {
+            body.extend(tlv.to_bytes()?);
+        }
+        let body_len =
+            u16::try_from(body.len()).map_err(|_| TlvError::PacketTooLong { len: body.len() })?;
 
         let mut buf = Vec::with_capacity(4 + body.len());
         buf.push(Self::BABEL_MAGIC);
@@ -43,23 +125,52 @@ impl Packet {
         buf.extend_from_slice(&body_len.to_be_bytes());
         buf.extend_from_slice(&body);
 
-        buf
+        Ok(buf)
     }
 
+    /// Parse a datagram, ignoring any packet trailer.
+    ///
+    /// A header declaring an empty body followed by anything but a valid
+    /// trailer (Pad1, PadN and HMAC TLVs) is taken as a wrong length and
+    /// rejected; use [`from_bytes_with_remainder`](Self::from_bytes_with_remainder)
+    /// to look at such a datagram anyway.
     pub fn from_bytes(buf: &[u8]) -> Result<Self, String> {
-        let tlv_slice =
-            if buf.len() >= 4 && buf[0] == Self::BABEL_MAGIC && buf[1] == Self::BABEL_VERSION {
-                let body_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
-                if 4 + body_len > buf.len() {
-                    return Err("Babel body length exceeds buffer".into());
-                }
-                &buf[4..4 + body_len]
-            } else {
-                buf
-            };
+        let (pkt, trailer) = Self::from_bytes_with_remainder(buf)?;
+        if has_header(buf) && buf[2..4] == [0, 0] && !trailer.is_empty() && !is_trailer(trailer) {
+            return Err("Babel body length is 0 but TLVs follow the header".into());
+        }
+        Ok(pkt)
+    }
+
+    /// Parse a packet and also return how many bytes of `buf` it consumed.
+    ///
+    /// For a packet with a Babel header this is `4 + body_len`, so several
+    /// packets laid back to back in one buffer can be parsed in turn. A
+    /// headerless buffer is consumed entirely.
+    pub fn from_bytes_with_len(buf: &[u8]) -> Result<(Self, usize), String> {
+        let (tlv_slice, consumed) = if has_header(buf) {
+            let body_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+            if 4 + body_len > buf.len() {
+                return Err("Babel body length exceeds buffer".into());
+            }
+            (&buf[4..4 + body_len], 4 + body_len)
+        } else {
+            (buf, buf.len())
+        };
+
+        let tlvs = Tlv::parse_all(tlv_slice).map_err(|e| e.to_string())?;
+        Ok((Packet { tlvs }, consumed))
+    }
 
-        let tlvs = Tlv::parse_all(tlv_slice)?;
-        Ok(Packet { tlvs })
+    /// Parse a packet and return the bytes of `buf` that follow it.
+    ///
+    /// TLV parsing stops exactly at the header's `body_len`; whatever comes
+    /// after (the packet trailer of RFC 8966 §4.2, e.g. for HMAC
+    /// verification, or the next packet in a framed stream) is returned
+    /// untouched. A headerless buffer has nothing after it.
+    pub fn from_bytes_with_remainder(buf: &[u8]) -> Result<(Self, &[u8]), String> {
+        let (pkt, consumed) = Self::from_bytes_with_len(buf)?;
+        Ok((pkt, &buf[consumed..]))
     }
 
     pub fn magic() -> u8 {
@@ -70,12 +181,34 @@ impl Packet {
         Self::BABEL_VERSION
     }
 
-    pub fn body_len(&self) -> u16 {
-        self.tlvs.iter().map(|t| t.to_bytes().len()).sum::<usize>() as u16
+    /// The TLVs of the packet body, in order.
+    pub fn tlvs(&self) -> &[Tlv] {
+        &self.tlvs
+    }
+
+    /// Iterate over the TLVs of the packet body.
+    pub fn iter_tlvs(&self) -> impl Iterator<Item = &Tlv> {
+        self.tlvs.iter()
+    }
+
+    /// Take the TLVs out of the packet.
+    pub fn into_tlvs(self) -> Vec<Tlv> {
+        self.tlvs
     }
 
+    /// Length of the serialized body. Beyond 65535 bytes the packet can't
+    /// be serialized.
+    pub fn body_len(&self) -> usize {
+        self.tlvs.iter().map(Tlv::wire_len).sum()
+    }
+
+    /// Send this packet from a throwaway socket.
+    ///
+    /// Binds a fresh socket for every call, so the packet leaves from a
+    /// random ephemeral port rather than [`BABEL_PORT`]. Fine for one-off
+    /// tools; anything sending regularly should use [`send_via`](Self::send_via).
     pub fn send_to<A: ToSocketAddrs>(&self, addr: A) -> io::Result<usize> {
-        let buf = self.to_bytes();
+        let buf = self.to_bytes()?;
         let mut last_err = None;
         for target in addr.to_socket_addrs()? {
             let socket = if target.is_ipv4() {
@@ -90,7 +223,12 @@ impl Packet {
                 return Ok(buf.len());
             }
         }
-        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "send_to failed")))
+        Err(last_err.unwrap_or_else(|| io::Error::other("send_to failed")))
+    }
+
+    /// Send this packet to `dest` through an existing `socket`.
+    pub fn send_via(&self, socket: &UdpSocket, dest: SocketAddr) -> io::Result<usize> {
+        socket.send_to(&self.to_bytes()?, dest)
     }
 
     pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
@@ -107,6 +245,172 @@ impl Packet {
         Ok((pkt.tlvs, src))
     }
 
+    /// Like [`recv`](Self::recv), but rejects packets without a valid
+    /// HMAC for `key`.
+    ///
+    /// `dst` is the address the packet was sent to (our unicast address or
+    /// the multicast group), as it is covered by the HMAC.
+    pub fn recv_verified(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+        key: &[u8],
+        dst: SocketAddr,
+    ) -> io::Result<(Vec<Tlv>, SocketAddr)> {
+        let (amt, src) = socket.recv_from(buf)?;
+        if !Self::verify_hmac(&buf[..amt], key, src, dst) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HMAC verification failed",
+            ));
+        }
+        let pkt = Packet::from_bytes(&buf[..amt])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok((pkt.tlvs, src))
+    }
+
+    //=== RFC 8967 HMAC authentication ===
+
+    /// Serialize the packet and append an HMAC-SHA256 trailer for `key`.
+    ///
+    /// The HMAC covers the RFC 8967 pseudo-header (`src` and `dst`
+    /// addresses and ports) and the packet. Replay protection needs a
+    /// [`Tlv::Pc`] in the body, see [`append_pc`](Self::append_pc).
+    pub fn sign_hmac(
+        &self,
+        key: &[u8],
+        src: SocketAddr,
+        dst: SocketAddr,
+    ) -> Result<Vec<u8>, TlvError> {
+        let mut buf = self.to_bytes()?;
+        Self::append_hmac(&mut buf, key, src, dst);
+        Ok(buf)
+    }
+
+    /// Add a [`Tlv::Pc`] with counter `pc` and `index` to the body of the
+    /// serialized packet `buf`, which must not have a trailer yet, so that
+    /// an HMAC appended afterwards covers it.
+    pub fn append_pc(buf: &mut Vec<u8>, pc: u32, index: &[u8]) -> Result<(), TlvError> {
+        let tlv = Tlv::Pc {
+            pc,
+            index: index.to_vec(),
+        }
+        .to_bytes()?;
+        let len = buf.len() - 4 + tlv.len();
+        let body_len = u16::try_from(len).map_err(|_| TlvError::PacketTooLong { len })?;
+        buf[2..4].copy_from_slice(&body_len.to_be_bytes());
+        buf.extend(tlv);
+        Ok(())
+    }
+
+    /// Append an HMAC-SHA256 trailer for `key` to the serialized packet
+    /// `buf`, as [`sign_hmac`](Self::sign_hmac) does.
+    pub fn append_hmac(buf: &mut Vec<u8>, key: &[u8], src: SocketAddr, dst: SocketAddr) {
+        let hmac = compute_hmac(buf, key, src, dst);
+        buf.extend(
+            Tlv::Hmac {
+                hmac: hmac.to_vec(),
+            }
+            .to_bytes()
+            .expect("a 32-byte HMAC fits a TLV"),
+        );
+    }
+
+    /// Check that the trailer of `buf` carries a valid HMAC for `key`.
+    pub fn verify_hmac(buf: &[u8], key: &[u8], src: SocketAddr, dst: SocketAddr) -> bool {
+        if buf.len() < 4 || buf[0] != Self::BABEL_MAGIC || buf[1] != Self::BABEL_VERSION {
+            return false;
+        }
+        let end = 4 + u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        if end > buf.len() {
+            return false;
+        }
+
+        let trailer = match Tlv::parse_all_strict(&buf[end..]) {
+            Ok(trailer) => trailer,
+            Err(_) => return false,
+        };
+        let expected = compute_hmac(&buf[..end], key, src, dst);
+        trailer.iter().any(|tlv| match tlv {
+            Tlv::Hmac { hmac, .. } => constant_time_eq(hmac, &expected),
+            _ => false,
+        })
+    }
+
+    //=== Update prefix compression ===
+
+    /// Compress the prefixes of the Update TLVs in `tlvs`, in order.
+    ///
+    /// Updates must carry full prefixes (`omitted == 0`). Each compressible
+    /// Update sets the default prefix for its AE, so following Updates of the
+    /// same family omit the bytes they share with it.
+    pub fn compress_updates(tlvs: Vec<Tlv>) -> Vec<Tlv> {
+        let mut defaults = DefaultPrefixes::new();
+        tlvs.into_iter()
+            .map(|tlv| match tlv {
+                Tlv::Update {
+                    ae,
+                    flags,
+                    plen,
+                    interval,
+                    seqno,
+                    metric,
+                    prefix,
+                    sub_tlvs,
+                    ..
+                } => {
+                    let (extra_flags, omitted, prefix) = defaults.compress(ae, &prefix);
+                    Tlv::Update {
+                        ae,
+                        flags: flags | extra_flags,
+                        plen,
+                        omitted,
+                        interval,
+                        seqno,
+                        metric,
+                        prefix,
+                        sub_tlvs,
+                    }
+                }
+                other => other,
+            })
+            .collect()
+    }
+
+    /// Undo [`compress_updates`](Self::compress_updates): return Updates with
+    /// full prefixes, `omitted == 0` and the default-prefix flag cleared.
+    pub fn expand_updates(tlvs: Vec<Tlv>) -> Result<Vec<Tlv>, String> {
+        let mut defaults = DefaultPrefixes::new();
+        tlvs.into_iter()
+            .map(|tlv| match tlv {
+                Tlv::Update {
+                    ae,
+                    flags,
+                    plen,
+                    omitted,
+                    interval,
+                    seqno,
+                    metric,
+                    prefix,
+                    sub_tlvs,
+                } => {
+                    let prefix = defaults.expand(ae, flags, omitted, &prefix)?;
+                    Ok(Tlv::Update {
+                        ae,
+                        flags: flags & !UPDATE_FLAG_DEFAULT_PREFIX,
+                        plen,
+                        omitted: 0,
+                        interval,
+                        seqno,
+                        metric,
+                        prefix,
+                        sub_tlvs,
+                    })
+                }
+                other => Ok(other),
+            })
+            .collect()
+    }
+
     //=== RFC-compliant convenience builders ===
 
     pub fn build_pad1() -> Self {
@@ -166,6 +470,7 @@ impl Packet {
         }])
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn build_update(
         ae: u8,
         flags: u8,
@@ -189,6 +494,22 @@ impl Packet {
         }])
     }
 
+    /// Update for the prefix of length `plen` containing `prefix`.
+    ///
+    /// The AE comes from the address family and the prefix is encoded in
+    /// `ceil(plen / 8)` bytes with the host bits zeroed; `plen` is clamped
+    /// to the family's width. Nothing is omitted.
+    pub fn build_update_for(
+        prefix: IpAddr,
+        plen: u8,
+        metric: u16,
+        seqno: u16,
+        interval: u16,
+    ) -> Self {
+        let key = RouteKey::from_addr(prefix, plen);
+        Packet::build_update(key.ae, 0, key.plen, 0, interval, seqno, metric, key.prefix)
+    }
+
     pub fn build_route_request(ae: u8, plen: u8, prefix: Vec<u8>) -> Self {
         Packet::with_tlvs(vec![Tlv::RouteRequest {
             ae,
@@ -219,19 +540,148 @@ impl Packet {
 
     //=== Multicast support ===
 
+    /// Bind a UDP socket to `addr` that other sockets may bind too
+    /// (`SO_REUSEADDR`, and `SO_REUSEPORT` where available), so several
+    /// nodes can share [`BABEL_PORT`] on one host.
+    pub fn bind_reusable(addr: SocketAddr) -> io::Result<UdpSocket> {
+        Self::bind_reusable_with(addr, false)
+    }
+
+    /// Like [`bind_reusable`](Self::bind_reusable) for an IPv6 `addr`, but
+    /// with `IPV6_V6ONLY` set, so the socket can sit next to an IPv4 one on
+    /// the same port without receiving IPv4 traffic as mapped addresses.
+    pub fn bind_reusable_v6_only(addr: SocketAddr) -> io::Result<UdpSocket> {
+        Self::bind_reusable_with(addr, true)
+    }
+
+    fn bind_reusable_with(addr: SocketAddr, v6_only: bool) -> io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if v6_only {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_reuse_address(true)?;
+        #[cfg(all(
+            unix,
+            not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+        ))]
+        socket.set_reuse_port(true)?;
+        socket.bind(&addr.into())?;
+        Ok(socket.into())
+    }
+
     pub fn bind_multicast_v4(interface: Ipv4Addr) -> io::Result<UdpSocket> {
-        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, BABEL_PORT))?;
+        let socket = Self::bind_reusable((Ipv4Addr::UNSPECIFIED, BABEL_PORT).into())?;
+        Self::join_multicast_v4(&socket, interface)?;
+        Ok(socket)
+    }
+
+    /// Join the Babel IPv4 multicast group on `interface` with `socket`.
+    ///
+    /// Multicast is also sent out of `interface`, with a TTL of
+    /// [`MULTICAST_TTL`].
+    pub fn join_multicast_v4(socket: &UdpSocket, interface: Ipv4Addr) -> io::Result<()> {
         socket.join_multicast_v4(&MULTICAST_V4_ADDR, &interface)?;
+        SockRef::from(socket).set_multicast_if_v4(&interface)?;
+        Self::set_multicast_ttl(socket, MULTICAST_TTL)?;
         // Don't receive our own multicast packets.
-        socket.set_multicast_loop_v4(false)?;
-        Ok(socket)
+        socket.set_multicast_loop_v4(false)
     }
 
     pub fn bind_multicast_v6(interface_index: u32) -> io::Result<UdpSocket> {
-        let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, BABEL_PORT))?;
-        socket.join_multicast_v6(&MULTICAST_V6_ADDR, interface_index)?;
+        let socket = Self::bind_reusable((Ipv6Addr::UNSPECIFIED, BABEL_PORT).into())?;
+        Self::join_multicast_v6(&socket, interface_index)?;
         Ok(socket)
     }
+
+    /// Join the Babel IPv6 multicast group on `interface_index` with `socket`.
+    ///
+    /// Multicast is also sent out of that interface, with a hop limit of
+    /// [`MULTICAST_TTL`].
+    pub fn join_multicast_v6(socket: &UdpSocket, interface_index: u32) -> io::Result<()> {
+        socket.join_multicast_v6(&MULTICAST_V6_ADDR, interface_index)?;
+        SockRef::from(socket).set_multicast_if_v6(interface_index)?;
+        Self::set_multicast_ttl(socket, MULTICAST_TTL)?;
+        // Don't receive our own multicast packets.
+        socket.set_multicast_loop_v6(false)
+    }
+
+    /// Restrict `socket` to the interface called `name` (`SO_BINDTODEVICE`).
+    ///
+    /// Only supported on Linux; elsewhere a warning is logged and the
+    /// socket is left as is.
+    pub fn bind_to_device(socket: &UdpSocket, name: &str) -> io::Result<()> {
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        {
+            SockRef::from(socket).bind_device(Some(name.as_bytes()))
+        }
+        #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+        {
+            let _ = socket;
+            eprintln!("[Packet] can't bind to device {name} on this platform; ignoring");
+            Ok(())
+        }
+    }
+
+    /// Set the TTL (IPv4) or hop limit (IPv6) of multicast sent on `socket`.
+    pub fn set_multicast_ttl(socket: &UdpSocket, ttl: u32) -> io::Result<()> {
+        if socket.local_addr()?.is_ipv4() {
+            socket.set_multicast_ttl_v4(ttl)
+        } else {
+            SockRef::from(socket).set_multicast_hops_v6(ttl)
+        }
+    }
+}
+
+impl fmt::Display for Packet {
+    /// One line per TLV, decoded (see the `Display` impl of [`Tlv`]).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, tlv) in self.tlvs.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{tlv}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `buf` starts with a Babel header.
+fn has_header(buf: &[u8]) -> bool {
+    buf.len() >= 4 && buf[0] == Packet::BABEL_MAGIC && buf[1] == Packet::BABEL_VERSION
+}
+
+/// Whether `buf` is a well-formed packet trailer: padding and HMACs only.
+fn is_trailer(buf: &[u8]) -> bool {
+    Tlv::parse_all_strict(buf).is_ok_and(|tlvs| {
+        tlvs.iter()
+            .all(|t| matches!(t, Tlv::Pad1 | Tlv::PadN { .. } | Tlv::Hmac { .. }))
+    })
+}
+
+/// RFC 8967 pseudo-header: source address and port, destination address
+/// and port.
+fn hmac_pseudo_header(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let mut out = Vec::with_capacity(36);
+    for addr in [src, dst] {
+        match addr.ip() {
+            IpAddr::V4(v4) => out.extend(v4.octets()),
+            IpAddr::V6(v6) => out.extend(v6.octets()),
+        }
+        out.extend(addr.port().to_be_bytes());
+    }
+    out
+}
+
+/// HMAC-SHA256 of the pseudo-header followed by `packet` (header and body).
+fn compute_hmac(packet: &[u8], key: &[u8], src: SocketAddr, dst: SocketAddr) -> [u8; 32] {
+    let mut mac = hmac_sha256::HMAC::new(key);
+    mac.update(hmac_pseudo_header(src, dst));
+    mac.update(packet);
+    mac.finalize()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 /// Integration tests for packet construction, send/receive, and multicast
@@ -243,10 +693,313 @@ mod tests {
     #[test]
     fn test_build_and_serialize() {
         let pkt = Packet::build_hello(0x0001, 42, 1000);
-        let bytes = pkt.to_bytes();
+        let bytes = pkt.to_bytes().unwrap();
         assert!(bytes.len() > 4); // header + at least one TLV
     }
 
+    #[test]
+    fn test_oversized_body_is_rejected() {
+        let pkt = Packet::with_tlvs(vec![Tlv::PadN { n: 255 }; 256]);
+        assert_eq!(pkt.body_len(), 256 * 257);
+        assert!(matches!(
+            pkt.to_bytes(),
+            Err(TlvError::PacketTooLong { len: 65792 })
+        ));
+
+        let fits = Packet::with_tlvs(vec![Tlv::PadN { n: 255 }; 255]);
+        assert_eq!(fits.to_bytes().unwrap().len(), 4 + 255 * 257);
+    }
+
+    #[test]
+    fn test_build_update_for_encodes_prefix() {
+        let v4 = Packet::build_update_for("10.0.1.5".parse().unwrap(), 24, 96, 7, 400);
+        assert!(matches!(
+            v4.tlvs(),
+            [Tlv::Update { ae: 1, plen: 24, omitted: 0, metric: 96, seqno: 7, interval: 400, prefix, .. }]
+                if prefix == &[10, 0, 1]
+        ));
+
+        let v6 = Packet::build_update_for("2001:db8::".parse().unwrap(), 32, 96, 7, 400);
+        assert!(matches!(
+            v6.tlvs(),
+            [Tlv::Update { ae: 2, plen: 32, prefix, .. }] if prefix == &[0x20, 0x01, 0x0d, 0xb8]
+        ));
+
+        let odd = Packet::build_update_for("10.0.255.1".parse().unwrap(), 20, 0, 0, 0);
+        assert!(matches!(
+            odd.tlvs(),
+            [Tlv::Update { plen: 20, prefix, .. }] if prefix == &[10, 0, 0xf0]
+        ));
+        assert_eq!(
+            Packet::from_bytes(&odd.to_bytes().unwrap()).unwrap().tlvs(),
+            odd.tlvs()
+        );
+    }
+
+    #[test]
+    fn test_display_decodes_tlvs() {
+        let mut pkt = Packet::build_hello(0, 278, 400);
+        pkt.add_tlv(Tlv::RouterId {
+            router_id: [0x02, 0x11, 0x22, 0xff, 0xfe, 0x33, 0x44, 0x55],
+            sub_tlvs: Vec::new(),
+        });
+        pkt.add_tlv(
+            Packet::build_update_for("10.0.1.0".parse().unwrap(), 24, 256, 10, 1000)
+                .into_tlvs()
+                .remove(0),
+        );
+
+        let text = pkt.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Hello seqno=278 interval=400ms");
+        assert_eq!(lines[1], "RouterId 02:11:22:ff:fe:33:44:55");
+        assert!(lines[2].starts_with("Update 10.0.1.0/24 metric=256 seqno=10"));
+    }
+
+    #[test]
+    fn test_tlv_accessors() {
+        let tlvs = vec![
+            Tlv::Hello {
+                flags: 0,
+                seqno: 1,
+                interval: 400,
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Ack {
+                opaque: 7,
+                sub_tlvs: Vec::new(),
+            },
+        ];
+        let pkt = Packet::with_tlvs(tlvs.clone());
+
+        assert_eq!(pkt.tlvs(), &tlvs[..]);
+        let is_hello: Vec<bool> = pkt
+            .iter_tlvs()
+            .map(|t| matches!(t, Tlv::Hello { .. }))
+            .collect();
+        assert_eq!(is_hello, vec![true, false]);
+        assert_eq!(pkt.into_tlvs(), tlvs);
+    }
+
+    fn update(ae: u8, plen: u8, prefix: Vec<u8>) -> Tlv {
+        Tlv::Update {
+            ae,
+            flags: 0,
+            plen,
+            omitted: 0,
+            interval: 400,
+            seqno: 7,
+            metric: 256,
+            prefix,
+            sub_tlvs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compressed_updates_roundtrip() {
+        let originals = vec![
+            update(1, 24, vec![10, 0, 1]),
+            update(2, 48, vec![0x20, 0x01, 0x0d, 0xb8, 0x00, 0x01]),
+            update(1, 24, vec![10, 0, 2]),
+            update(1, 16, vec![10, 1]),
+            update(2, 64, vec![0x20, 0x01, 0x0d, 0xb8, 0x00, 0x01, 0xab, 0xcd]),
+            update(2, 32, vec![0x20, 0x01, 0x0d, 0xb9]),
+            update(1, 32, vec![192, 0, 2, 1]),
+        ];
+
+        let compressed = Packet::compress_updates(originals.clone());
+        let total_omitted: usize = compressed
+            .iter()
+            .map(|t| match t {
+                Tlv::Update { omitted, .. } => *omitted as usize,
+                _ => 0,
+            })
+            .sum();
+        assert!(total_omitted > 0);
+
+        let bytes = Packet::with_tlvs(compressed).to_bytes().unwrap();
+        let decoded = Tlv::parse_all(&bytes[4..]).unwrap();
+        let expanded = Packet::expand_updates(decoded).unwrap();
+        assert_eq!(expanded, originals);
+    }
+
+    #[test]
+    fn test_pack_within_budget() {
+        let updates: Vec<Tlv> = (0..100)
+            .map(|i| update(1, 32, vec![10, 0, 0, i as u8]))
+            .collect();
+        let per_update = updates[0].wire_len();
+
+        let packets = Packet::pack_within(updates.clone(), 1200);
+        assert!(packets.len() < 10);
+        for pkt in &packets {
+            assert!(pkt.body_len() <= 1200);
+        }
+        let flat: Vec<Tlv> = packets.iter().flat_map(|p| p.tlvs().to_vec()).collect();
+        assert_eq!(flat, updates);
+
+        // Too small for even one TLV: each still goes out alone.
+        let packets = Packet::pack_within(updates[..3].to_vec(), per_update - 1);
+        assert_eq!(packets.len(), 3);
+        assert!(Packet::pack_within(Vec::new(), 1200).is_empty());
+    }
+
+    #[test]
+    fn test_from_bytes_with_len_back_to_back() {
+        let first = Packet::build_hello(0, 1, 400);
+        let second = Packet::build_ack(99);
+
+        let mut stream = first.to_bytes().unwrap();
+        let first_len = stream.len();
+        stream.extend(second.to_bytes().unwrap());
+
+        let (pkt, consumed) = Packet::from_bytes_with_len(&stream).unwrap();
+        assert_eq!(consumed, first_len);
+        assert_eq!(pkt.tlvs, first.tlvs);
+
+        let (pkt, consumed) = Packet::from_bytes_with_len(&stream[consumed..]).unwrap();
+        assert_eq!(consumed, stream.len() - first_len);
+        assert_eq!(pkt.tlvs, second.tlvs);
+    }
+
+    #[test]
+    fn test_from_bytes_stops_at_body_len() {
+        let pkt = Packet::build_ack(7);
+        let mut buf = pkt.to_bytes().unwrap();
+        let trailing = Tlv::Pad1.to_bytes().unwrap();
+        buf.extend(&trailing);
+
+        let (parsed, rest) = Packet::from_bytes_with_remainder(&buf).unwrap();
+        assert_eq!(parsed.tlvs, pkt.tlvs);
+        assert_eq!(rest, &trailing[..]);
+    }
+
+    #[test]
+    fn test_from_bytes_with_hmac_trailer() {
+        let key = b"shared secret";
+        let src: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let dst: SocketAddr = "224.0.0.111:6696".parse().unwrap();
+        let hello = Packet::build_hello(0, 42, 400);
+        let signed = hello.sign_hmac(key, src, dst).unwrap();
+
+        let (pkt, trailer) = Packet::from_bytes_with_remainder(&signed).unwrap();
+        assert_eq!(pkt.tlvs, hello.tlvs);
+        let trailer = Tlv::parse_all_strict(trailer).unwrap();
+        assert!(matches!(trailer.as_slice(), [Tlv::Hmac { .. }]));
+        assert_eq!(Packet::from_bytes(&signed).unwrap().tlvs, hello.tlvs);
+
+        let headerless = hello.tlvs[0].to_bytes().unwrap();
+        let (_, trailer) = Packet::from_bytes_with_remainder(&headerless).unwrap();
+        assert!(trailer.is_empty());
+
+        // An empty body with only an HMAC trailer is fine too.
+        let empty = Packet::with_tlvs(Vec::new())
+            .sign_hmac(key, src, dst)
+            .unwrap();
+        assert!(Packet::from_bytes(&empty).unwrap().tlvs.is_empty());
+        assert!(Packet::verify_hmac(&empty, key, src, dst));
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_empty_body_with_data() {
+        let mut buf = Packet::with_tlvs(Vec::new()).to_bytes().unwrap();
+        assert_eq!(buf, [Packet::magic(), Packet::version(), 0, 0]);
+        assert!(Packet::from_bytes(&buf).unwrap().tlvs.is_empty());
+
+        let hello = Packet::build_hello(0, 1, 400).tlvs[0].to_bytes().unwrap();
+        buf.extend(&hello);
+        assert!(Packet::from_bytes(&buf).is_err());
+        let (pkt, trailer) = Packet::from_bytes_with_remainder(&buf).unwrap();
+        assert!(pkt.tlvs.is_empty());
+        assert_eq!(trailer, &hello[..]);
+
+        // A non-empty body whose TLVs were all skipped (here a truncated
+        // Hello) is no length mismatch.
+        let mut buf = vec![Packet::magic(), Packet::version(), 0, 3, 4, 1, 0];
+        buf.extend(Tlv::Hmac { hmac: vec![0; 32] }.to_bytes().unwrap());
+        assert!(Packet::from_bytes(&buf).unwrap().tlvs.is_empty());
+    }
+
+    #[test]
+    fn test_hmac_sign_and_verify() {
+        let key = b"shared secret";
+        let src: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let dst: SocketAddr = "224.0.0.111:6696".parse().unwrap();
+
+        let signed = Packet::build_hello(0, 42, 400)
+            .sign_hmac(key, src, dst)
+            .unwrap();
+        assert!(Packet::verify_hmac(&signed, key, src, dst));
+        assert!(!Packet::verify_hmac(&signed, b"other key", src, dst));
+        assert!(!Packet::verify_hmac(
+            &signed,
+            key,
+            "192.0.2.9:6696".parse().unwrap(),
+            dst
+        ));
+
+        // The trailer doesn't change how the packet itself parses.
+        let pkt = Packet::from_bytes(&signed).unwrap();
+        assert_eq!(pkt.tlvs, Packet::build_hello(0, 42, 400).tlvs);
+    }
+
+    #[test]
+    fn test_hmac_rejects_tampered_body() {
+        let key = b"shared secret";
+        let src: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let dst: SocketAddr = "224.0.0.111:6696".parse().unwrap();
+
+        let mut signed = Packet::build_hello(0, 42, 400)
+            .sign_hmac(key, src, dst)
+            .unwrap();
+        // Bump the Hello seqno.
+        signed[9] ^= 1;
+        assert!(!Packet::verify_hmac(&signed, key, src, dst));
+
+        let unsigned = Packet::build_hello(0, 42, 400).to_bytes().unwrap();
+        assert!(!Packet::verify_hmac(&unsigned, key, src, dst));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_roundtrip() {
+        let mut pkt = Packet::build_hello(0, 3, 400);
+        pkt.add_tlv(Tlv::RouterId {
+            router_id: [1, 2, 3, 4, 5, 6, 7, 8],
+            sub_tlvs: Vec::new(),
+        });
+        pkt.add_tlv(Tlv::NextHop {
+            ae: 2,
+            addr: Some("2001:db8::1".parse().unwrap()),
+            sub_tlvs: vec![crate::tlv::SubTlv::PadN { n: 2 }],
+        });
+        pkt.add_tlv(update(1, 24, vec![10, 0, 1]));
+
+        let json = serde_json::to_string(&pkt).unwrap();
+        assert!(json.contains("[1,2,3,4,5,6,7,8]"));
+        let back: Packet = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.tlvs(), pkt.tlvs());
+    }
+
+    #[test]
+    fn test_udp_payload_size() {
+        assert_eq!(udp_payload_size(1500, false), 1472);
+        assert_eq!(udp_payload_size(1500, true), 1452);
+        assert_eq!(udp_payload_size(10, true), 0);
+    }
+
+    #[test]
+    fn test_interface_mtu_payload() {
+        // Only where sysfs is available.
+        let Ok(name) = interface_name(1) else { return };
+        let Ok(mtu) = interface_mtu(&name) else {
+            return;
+        };
+        assert_eq!(udp_payload_size(mtu, false), mtu - 28);
+        assert_eq!(udp_payload_size(mtu, true), mtu - 48);
+    }
+
     #[test]
     fn test_send_recv_local() {
         let server = Packet::bind(("127.0.0.1", 0)).expect("bind failed");
@@ -261,12 +1014,37 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn test_send_via_uses_socket_port() {
+        let server = Packet::bind(("127.0.0.1", 0)).expect("bind failed");
+        let client = Packet::bind(("127.0.0.1", 0)).expect("bind failed");
+
+        Packet::build_pad1()
+            .send_via(&client, server.local_addr().unwrap())
+            .expect("send failed");
+
+        let mut buf = [0u8; 1500];
+        let (tlvs, src) = Packet::recv(&server, &mut buf).expect("recv failed");
+        assert_eq!(tlvs, vec![Tlv::Pad1]);
+        assert_eq!(src, client.local_addr().unwrap());
+    }
+
     #[test]
     fn test_multicast_v4_binding() {
         let iface = Ipv4Addr::new(127, 0, 0, 1);
         let socket = Packet::bind_multicast_v4(iface).expect("multicast bind failed");
         let local = socket.local_addr().unwrap();
         assert_eq!(local.port(), BABEL_PORT);
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), MULTICAST_TTL);
+    }
+
+    #[test]
+    fn test_multicast_sockets_share_port() {
+        let iface = Ipv4Addr::new(127, 0, 0, 1);
+        let first = Packet::bind_multicast_v4(iface).expect("first bind failed");
+        let second = Packet::bind_multicast_v4(iface).expect("second bind failed");
+        assert_eq!(first.local_addr().unwrap().port(), BABEL_PORT);
+        assert_eq!(second.local_addr().unwrap().port(), BABEL_PORT);
     }
 
     #[test]
@@ -274,5 +1052,7 @@ mod tests {
         let socket = Packet::bind_multicast_v6(0).expect("multicast v6 bind failed");
         let local = socket.local_addr().unwrap();
         assert_eq!(local.port(), BABEL_PORT);
+        let hops = SockRef::from(&socket).multicast_hops_v6().unwrap();
+        assert_eq!(hops, MULTICAST_TTL);
     }
 }
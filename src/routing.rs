@@ -1,7 +1,52 @@
 // src/routing.rs
 //! Simple routing table and route selection for Babel.
 
-use std::net::IpAddr;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+use crate::neighbor::Neighbor;
+use crate::tlv::{AddressFamily, ae_family};
+
+/// Metric value meaning "unreachable" (also used for retractions).
+pub const INFINITY: u16 = 0xFFFF;
+
+/// Weight of the previous value in [`Route::smoothed_metric`]: each new
+/// metric moves it `1/SMOOTHING_WEIGHT` of the way.
+const SMOOTHING_WEIGHT: u32 = 4;
+
+/// How long a source table entry is kept after it was last updated, per
+/// RFC 8966 §3.7.3 and Appendix B.
+pub const SOURCE_GC_TIME: Duration = Duration::from_secs(180);
+
+/// How long an unreachable route is kept before [`RoutingTable::gc`] drops it.
+pub const GC_GRACE: Duration = Duration::from_secs(60);
+
+/// Hold time of a learned route, per RFC 8966 §3.5.3: 3.5 times the
+/// interval advertised in its Update.
+pub fn route_hold_time(interval_ms: u16) -> Duration {
+    Duration::from_millis(interval_ms as u64 * 7 / 2)
+}
+
+/// Policy turning an advertised metric into the metric of a learned route.
+pub trait MetricComputer {
+    /// Metric of a route advertised with `advertised` by `neighbor`.
+    fn compute(&self, advertised: u16, neighbor: &Neighbor) -> u16;
+}
+
+/// Default metric policy: advertised metric plus the neighbor's link cost.
+///
+/// A neighbor without a known link cost adds nothing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AdditiveMetric;
+
+impl MetricComputer for AdditiveMetric {
+    fn compute(&self, advertised: u16, neighbor: &Neighbor) -> u16 {
+        advertised.saturating_add(neighbor.link_cost().unwrap_or(0))
+    }
+}
 
 /// Key identifying a prefix in Babel (AE + prefix length + bytes).
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -12,42 +57,373 @@ pub struct RouteKey {
     pub prefix: Vec<u8>,
 }
 
+impl RouteKey {
+    /// Build the key for the prefix of length `plen` containing `addr`.
+    ///
+    /// Picks AE 1 or 2 from the address family, keeps `ceil(plen / 8)` bytes
+    /// and zeroes the host bits. `plen` is clamped to the family's width.
+    pub fn from_addr(addr: IpAddr, plen: u8) -> RouteKey {
+        let (ae, octets, max) = match addr {
+            IpAddr::V4(v4) => (1u8, v4.octets().to_vec(), 32u8),
+            IpAddr::V6(v6) => (2u8, v6.octets().to_vec(), 128u8),
+        };
+        let plen = plen.min(max);
+
+        let mut prefix = octets[..(plen as usize).div_ceil(8)].to_vec();
+        let rem = plen % 8;
+        if rem != 0
+            && let Some(last) = prefix.last_mut()
+        {
+            *last &= 0xffu8 << (8 - rem);
+        }
+
+        RouteKey { ae, plen, prefix }
+    }
+
+    /// Whether `other` lies within this prefix (same family, at least as
+    /// long, and equal on our first `plen` bits).
+    pub fn contains(&self, other: &RouteKey) -> bool {
+        if self.address_family() != other.address_family() || self.plen > other.plen {
+            return false;
+        }
+
+        let byte = |p: &[u8], i: usize| p.get(i).copied().unwrap_or(0);
+        let full = (self.plen / 8) as usize;
+        if (0..full).any(|i| byte(&self.prefix, i) != byte(&other.prefix, i)) {
+            return false;
+        }
+
+        let rem = self.plen % 8;
+        if rem == 0 {
+            return true;
+        }
+        let mask = 0xffu8 << (8 - rem);
+        byte(&self.prefix, full) & mask == byte(&other.prefix, full) & mask
+    }
+
+    /// Address family of this prefix, if its AE designates one.
+    pub fn address_family(&self) -> Option<AddressFamily> {
+        ae_family(self.ae)
+    }
+
+    /// The prefix as a network address and prefix length.
+    ///
+    /// Missing trailing bytes are taken as zeros. Returns `None` for an AE
+    /// without an address family.
+    pub fn to_ip_and_prefixlen(&self) -> Option<(IpAddr, u8)> {
+        let addr: IpAddr = match self.address_family()? {
+            AddressFamily::V4 => {
+                let mut octets = [0u8; 4];
+                copy_prefix(&mut octets, &self.prefix);
+                Ipv4Addr::from(octets).into()
+            }
+            AddressFamily::V6 => {
+                let mut octets = [0u8; 16];
+                copy_prefix(&mut octets, &self.prefix);
+                Ipv6Addr::from(octets).into()
+            }
+        };
+        Some((addr, self.plen))
+    }
+
+    /// The prefix in CIDR notation, e.g. `10.0.1.0/24` or `2001:db8::/32`.
+    ///
+    /// Returns `None` for an AE without an address family.
+    pub fn to_cidr_string(&self) -> Option<String> {
+        let (addr, plen) = self.to_ip_and_prefixlen()?;
+        Some(format!("{addr}/{plen}"))
+    }
+
+    /// The prefix as an [`ipnet::IpNet`], host bits cleared.
+    ///
+    /// Returns `None` for an AE without an address family, or a `plen`
+    /// longer than the family's width.
+    #[cfg(feature = "ipnet")]
+    pub fn to_ipnet(&self) -> Option<ipnet::IpNet> {
+        let (addr, plen) = self.to_ip_and_prefixlen()?;
+        ipnet::IpNet::new(addr, plen).ok().map(|net| net.trunc())
+    }
+
+    /// The key of `net`, as [`from_addr`](Self::from_addr) builds it.
+    #[cfg(feature = "ipnet")]
+    pub fn from_ipnet(net: &ipnet::IpNet) -> RouteKey {
+        RouteKey::from_addr(net.addr(), net.prefix_len())
+    }
+}
+
+/// Copy as much of `prefix` as fits into `octets`.
+fn copy_prefix(octets: &mut [u8], prefix: &[u8]) {
+    let n = prefix.len().min(octets.len());
+    octets[..n].copy_from_slice(&prefix[..n]);
+}
+
+impl fmt::Display for RouteKey {
+    /// CIDR notation, or the raw fields if the AE has no address family.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_cidr_string() {
+            Some(cidr) => f.write_str(&cidr),
+            None => write!(
+                f,
+                "ae={} plen={} prefix={:02x?}",
+                self.ae, self.plen, self.prefix
+            ),
+        }
+    }
+}
+
 /// One route entry learned via Babel Update.
 #[derive(Debug, Clone)]
 pub struct Route {
     pub key: RouteKey,
+    /// Metric of the route through its next hop (advertised metric plus link cost).
     pub metric: u16,
+    /// Metric as advertised by the neighbor in its Update.
+    pub advertised_metric: u16,
+    /// Moving average of `metric`, used for hysteresis in
+    /// [`RoutingTable::best_route`]. Maintained by the table; a new path
+    /// starts at its metric.
+    pub smoothed_metric: u16,
     pub seqno: u16,
     pub router_id: [u8; 8],
     pub next_hop: Option<IpAddr>,
     pub iface_index: u32,
+    /// When this route was last installed or refreshed by an Update.
+    pub last_updated: Instant,
+    /// When this route expires unless refreshed (see [`route_hold_time`]).
+    /// Ignored for local routes.
+    pub expires_at: Instant,
 }
 
 impl Route {
+    /// Whether this route is unreachable (infinite metric).
+    pub fn is_retracted(&self) -> bool {
+        self.metric == INFINITY
+    }
+
+    /// Change the metric, moving `smoothed_metric` towards it.
+    ///
+    /// An unreachable route has no history worth keeping, so leaving or
+    /// entering INFINITY resets the average.
+    pub fn set_metric(&mut self, metric: u16) {
+        self.smoothed_metric = if metric == INFINITY || self.metric == INFINITY {
+            metric
+        } else {
+            let old = self.smoothed_metric as u32;
+            ((old * (SMOOTHING_WEIGHT - 1) + metric as u32) / SMOOTHING_WEIGHT) as u16
+        };
+        self.metric = metric;
+    }
+
     /// Return a short human-ish description for debugging/logging.
     pub fn summary(&self) -> String {
         format!(
-            "ae={} plen={} metric={} seqno={} router_id={:02x?} nexthop={:?} iface={}",
-            self.key.ae,
-            self.key.plen,
-            self.metric,
-            self.seqno,
-            self.router_id,
-            self.next_hop,
-            self.iface_index
+            "{} metric={} seqno={} router_id={:02x?} nexthop={:?} iface={}",
+            self.key, self.metric, self.seqno, self.router_id, self.next_hop, self.iface_index
         )
     }
 }
 
+/// Whether `new` differs from `old` in a way that matters for forwarding.
+pub(crate) fn best_changed(old: Option<&Route>, new: &Route) -> bool {
+    match old {
+        None => true,
+        Some(old) => {
+            old.metric != new.metric
+                || old.seqno != new.seqno
+                || old.router_id != new.router_id
+                || old.next_hop != new.next_hop
+        }
+    }
+}
+
+/// What [`RoutingTable::install_or_update`] did with a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteChange {
+    /// A path we had no route for was added.
+    Inserted,
+    /// An existing path got a newer seqno or a better metric.
+    Improved,
+    /// Nothing changed: the route was worse, unfeasible, or a refresh.
+    Unchanged,
+    /// The path was added or updated with an infinite metric.
+    Retracted,
+}
+
+/// Result of [`RoutingTable::install_or_update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstallOutcome {
+    pub change: RouteChange,
+    /// Whether the best route for the prefix is now a different one (see
+    /// [`RoutingTable::best_route`]).
+    pub best_changed: bool,
+}
+
+impl InstallOutcome {
+    /// Whether the table changed at all.
+    pub fn changed(&self) -> bool {
+        self.change != RouteChange::Unchanged
+    }
+}
+
+/// Whether seqno `a` is newer than `b` in modulo-2^16 arithmetic, per
+/// RFC 8966 §3.2.1: `0 < (a - b) mod 2^16 < 2^15`.
+///
+/// Seqnos exactly 2^15 apart are incomparable: neither is newer.
+pub(crate) fn seqno_gt(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+/// Order two seqnos by [`seqno_gt`]; incomparable ones count as equal.
+fn seqno_cmp(a: u16, b: u16) -> Ordering {
+    if seqno_gt(a, b) {
+        Ordering::Greater
+    } else if seqno_gt(b, a) {
+        Ordering::Less
+    } else {
+        Ordering::Equal
+    }
+}
+
+/// Feasibility distances, per RFC 8966 §3.5.1: for each prefix and
+/// originating router-id, the best `(seqno, metric)` we have advertised.
+///
+/// Entries outlive the routes they came from, so a retraction doesn't make
+/// a worse route acceptable again; they are forgotten [`SOURCE_GC_TIME`]
+/// after their last update (see [`prune`](Self::prune)).
+#[derive(Debug, Default)]
+pub struct SourceTable {
+    entries: HashMap<(RouteKey, [u8; 8]), SourceEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SourceEntry {
+    seqno: u16,
+    metric: u16,
+    updated: Instant,
+}
+
+impl SourceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feasibility distance `(seqno, metric)` for `key` from `router_id`.
+    pub fn get(&self, key: &RouteKey, router_id: [u8; 8]) -> Option<(u16, u16)> {
+        self.entries
+            .get(&(key.clone(), router_id))
+            .map(|e| (e.seqno, e.metric))
+    }
+
+    /// Record that we advertised `key` from `router_id` with this seqno and
+    /// metric. The stored distance only ever improves: a newer seqno, or a
+    /// lower metric at the same seqno. Retractions are not recorded and
+    /// leave the entry as it is.
+    ///
+    /// Any finite advertisement restarts the entry's [`SOURCE_GC_TIME`]
+    /// at `now`, per RFC 8966 §3.7.3.
+    pub fn update(
+        &mut self,
+        key: &RouteKey,
+        router_id: [u8; 8],
+        seqno: u16,
+        metric: u16,
+        now: Instant,
+    ) {
+        if metric == INFINITY {
+            return;
+        }
+        let fd = self
+            .entries
+            .entry((key.clone(), router_id))
+            .or_insert(SourceEntry {
+                seqno,
+                metric,
+                updated: now,
+            });
+        if seqno_gt(seqno, fd.seqno) || (seqno == fd.seqno && metric < fd.metric) {
+            fd.seqno = seqno;
+            fd.metric = metric;
+        }
+        fd.updated = now;
+    }
+
+    /// Forget entries not updated for [`SOURCE_GC_TIME`] by `now`; returns
+    /// how many.
+    pub fn prune(&mut self, now: Instant) -> usize {
+        let before = self.entries.len();
+        self.entries
+            .retain(|_, e| now.saturating_duration_since(e.updated) <= SOURCE_GC_TIME);
+        before - self.entries.len()
+    }
+}
+
+/// Identifies one path to a prefix: router-id, next hop and interface.
+type PathId = ([u8; 8], Option<IpAddr>, u32);
+
+fn path_id(route: &Route) -> PathId {
+    (route.router_id, route.next_hop, route.iface_index)
+}
+
 /// In-memory routing table with naive best-route selection.
 #[derive(Debug, Default)]
 pub struct RoutingTable {
     routes: Vec<Route>,
+    sources: SourceTable,
+    /// How much better a challenger's smoothed metric must be to replace
+    /// the selected route; 0 disables hysteresis.
+    hysteresis_margin: u16,
+    /// The path last selected as best for each prefix.
+    selected: HashMap<RouteKey, PathId>,
 }
 
 impl RoutingTable {
     pub fn new() -> Self {
-        RoutingTable { routes: Vec::new() }
+        RoutingTable {
+            routes: Vec::new(),
+            sources: SourceTable::new(),
+            hysteresis_margin: 0,
+            selected: HashMap::new(),
+        }
+    }
+
+    /// A table that only switches away from a prefix's best route when
+    /// another route's smoothed metric is lower by more than `margin`.
+    pub fn with_hysteresis_margin(margin: u16) -> Self {
+        RoutingTable {
+            hysteresis_margin: margin,
+            ..Self::new()
+        }
+    }
+
+    /// Feasibility distances consulted by [`install_or_update`](Self::install_or_update).
+    pub fn sources(&self) -> &SourceTable {
+        &self.sources
+    }
+
+    /// Record that we advertised `route` at `now`, tightening its
+    /// feasibility distance.
+    pub fn note_advertised(&mut self, route: &Route, now: Instant) {
+        self.sources
+            .update(&route.key, route.router_id, route.seqno, route.metric, now);
+    }
+
+    /// Whether `route` satisfies the feasibility condition against `sources`.
+    ///
+    /// A route is feasible if we never advertised its source, if it's a
+    /// retraction, if its seqno is newer than the feasibility distance, or
+    /// if the seqno is equal and the metric the neighbor advertised (not
+    /// including our link cost, RFC 8966 §3.5.1) strictly lower.
+    pub fn is_feasible(route: &Route, sources: &SourceTable) -> bool {
+        if route.metric == INFINITY {
+            return true;
+        }
+        match sources.get(&route.key, route.router_id) {
+            None => true,
+            Some((seqno, metric)) => {
+                seqno_gt(route.seqno, seqno)
+                    || (route.seqno == seqno && route.advertised_metric < metric)
+            }
+        }
     }
 
     /// Return a slice of all routes.
@@ -61,52 +437,794 @@ impl RoutingTable {
     }
 
     /// Best route for a given key, if any (lower metric, then higher seqno).
+    ///
+    /// Remaining ties go to the lowest router-id, then next hop, then
+    /// interface, so the choice doesn't depend on insertion order. Routes
+    /// with an infinite metric are never selected.
+    ///
+    /// With a hysteresis margin, the previously selected route is kept
+    /// while still reachable unless the winner's smoothed metric beats its
+    /// smoothed metric by more than the margin.
     pub fn best_route(&self, key: &RouteKey) -> Option<&Route> {
-        self.routes_for(key).max_by(|a, b| {
-            use std::cmp::Ordering;
-            match a.metric.cmp(&b.metric).reverse() {
-                Ordering::Equal => a.seqno.cmp(&b.seqno),
-                other => other,
-            }
-        })
+        let best = self.naive_best_route(key)?;
+        if self.hysteresis_margin == 0 {
+            return Some(best);
+        }
+        let Some(selected) = self.selected.get(key).and_then(|id| {
+            self.routes_for(key)
+                .find(|r| r.metric < INFINITY && &path_id(r) == id)
+        }) else {
+            return Some(best);
+        };
+        let threshold = selected
+            .smoothed_metric
+            .saturating_sub(self.hysteresis_margin);
+        if best.smoothed_metric < threshold {
+            Some(best)
+        } else {
+            Some(selected)
+        }
+    }
+
+    /// Best route for `key` by metric alone, ignoring hysteresis.
+    fn naive_best_route(&self, key: &RouteKey) -> Option<&Route> {
+        self.routes_for(key)
+            .filter(|r| r.metric < INFINITY)
+            .min_by(|a, b| {
+                a.metric
+                    .cmp(&b.metric)
+                    .then_with(|| seqno_cmp(b.seqno, a.seqno))
+                    .then_with(|| a.router_id.cmp(&b.router_id))
+                    .then_with(|| a.next_hop.cmp(&b.next_hop))
+                    .then_with(|| a.iface_index.cmp(&b.iface_index))
+            })
+    }
+
+    /// Route to use for forwarding to `addr`: the best route of the longest
+    /// reachable prefix covering it.
+    pub fn lookup(&self, addr: IpAddr) -> Option<&Route> {
+        let host = RouteKey::from_addr(addr, u8::MAX);
+        let key = self
+            .routes
+            .iter()
+            .filter(|r| r.metric < INFINITY && r.key.contains(&host))
+            .map(|r| &r.key)
+            .max_by_key(|k| k.plen)?;
+        self.best_route(key)
     }
 
     /// Install or update a route.
     ///
-    /// Returns true if the table changed, false if the new route was worse.
-    pub fn install_or_update(&mut self, new_route: Route) -> bool {
+    /// The outcome says how the path changed and whether the best route for
+    /// its prefix did. Nothing changes if the new route was worse (an older
+    /// seqno, or a worse metric at the same seqno) or, for a learned route,
+    /// not feasible (see [`is_feasible`](Self::is_feasible)). An Update
+    /// identical to the installed route (same seqno and advertised metric)
+    /// doesn't count as a change but still refreshes `last_updated`.
+    pub fn install_or_update(&mut self, new_route: Route) -> InstallOutcome {
+        let key = new_route.key.clone();
+        let old_best = self.best_route(&key).cloned();
+
+        let change = self.install_path(new_route);
+        self.reselect(&key);
+        let best_changed = change != RouteChange::Unchanged
+            && match self.best_route(&key) {
+                Some(best) => best_changed(old_best.as_ref(), best),
+                None => old_best.is_some(),
+            };
+        InstallOutcome {
+            change,
+            best_changed,
+        }
+    }
+
+    /// Remember the current best route for `key` as the selected one, so
+    /// hysteresis applies relative to it.
+    pub(crate) fn reselect(&mut self, key: &RouteKey) {
+        match self.best_route(key).map(path_id) {
+            Some(id) => {
+                self.selected.insert(key.clone(), id);
+            }
+            None => {
+                self.selected.remove(key);
+            }
+        }
+    }
+
+    fn reselect_all(&mut self) {
+        let keys: Vec<RouteKey> = self.selected.keys().cloned().collect();
+        for key in keys {
+            self.reselect(&key);
+        }
+    }
+
+    /// The table update behind [`install_or_update`](Self::install_or_update).
+    fn install_path(&mut self, mut new_route: Route) -> RouteChange {
+        let retracted = new_route.metric == INFINITY;
+        let feasible = new_route.next_hop.is_none() || Self::is_feasible(&new_route, &self.sources);
+
         if let Some(existing) = self.routes.iter_mut().find(|r| {
             r.key == new_route.key
                 && r.router_id == new_route.router_id
                 && r.next_hop == new_route.next_hop
                 && r.iface_index == new_route.iface_index
         }) {
-            if Self::is_better(&new_route, existing) {
+            if feasible && Self::is_better(&new_route, existing) {
+                let metric = new_route.metric;
+                new_route.metric = existing.metric;
+                new_route.smoothed_metric = existing.smoothed_metric;
+                new_route.set_metric(metric);
                 *existing = new_route;
-                true
+                if retracted {
+                    RouteChange::Retracted
+                } else {
+                    RouteChange::Improved
+                }
             } else {
-                false
+                if new_route.seqno == existing.seqno
+                    && new_route.advertised_metric == existing.advertised_metric
+                {
+                    existing.last_updated = existing.last_updated.max(new_route.last_updated);
+                    existing.expires_at = existing.expires_at.max(new_route.expires_at);
+                }
+                RouteChange::Unchanged
             }
-        } else {
+        } else if feasible {
+            new_route.smoothed_metric = new_route.metric;
             self.routes.push(new_route);
-            true
+            if retracted {
+                RouteChange::Retracted
+            } else {
+                RouteChange::Inserted
+            }
+        } else {
+            RouteChange::Unchanged
+        }
+    }
+
+    /// Drop learned routes that have been unreachable for longer than
+    /// [`GC_GRACE`] and release unused storage; returns how many.
+    ///
+    /// Expired source table entries are dropped too (see
+    /// [`SourceTable::prune`]).
+    pub fn gc(&mut self, now: Instant) -> usize {
+        self.sources.prune(now);
+        let before = self.routes.len();
+        self.routes.retain(|r| {
+            r.next_hop.is_none()
+                || r.metric < INFINITY
+                || now.saturating_duration_since(r.last_updated) <= GC_GRACE
+        });
+        self.routes.shrink_to_fit();
+        self.reselect_all();
+        before - self.routes.len()
+    }
+
+    /// Remove learned routes whose hold time ran out by `now`.
+    ///
+    /// Returns the keys that lost at least one route.
+    pub fn prune_expired(&mut self, now: Instant) -> Vec<RouteKey> {
+        let mut keys: Vec<RouteKey> = Vec::new();
+        self.routes.retain(|r| {
+            if r.next_hop.is_some() && r.expires_at <= now {
+                if !keys.contains(&r.key) {
+                    keys.push(r.key.clone());
+                }
+                false
+            } else {
+                true
+            }
+        });
+        for key in &keys {
+            self.reselect(key);
         }
+        keys
+    }
+
+    /// Remove all learned (non-local) routes for `key`; returns how many.
+    pub fn remove_learned(&mut self, key: &RouteKey) -> usize {
+        let before = self.routes.len();
+        self.routes
+            .retain(|r| !(&r.key == key && r.next_hop.is_some()));
+        self.reselect(key);
+        before - self.routes.len()
+    }
+
+    /// Remove the route for `key` from `router_id` through `next_hop` on
+    /// `iface_index`; returns whether there was one.
+    pub fn remove(
+        &mut self,
+        key: &RouteKey,
+        router_id: [u8; 8],
+        next_hop: Option<IpAddr>,
+        iface_index: u32,
+    ) -> bool {
+        let before = self.routes.len();
+        self.routes.retain(|r| {
+            !(&r.key == key
+                && r.router_id == router_id
+                && r.next_hop == next_hop
+                && r.iface_index == iface_index)
+        });
+        self.reselect(key);
+        self.routes.len() != before
     }
 
     /// Remove all routes that came from a given router-id.
     pub fn remove_by_router(&mut self, router_id: [u8; 8]) -> usize {
         let before = self.routes.len();
         self.routes.retain(|r| r.router_id != router_id);
+        self.reselect_all();
         before - self.routes.len()
     }
 
+    /// Remove all routes through `next_hop` on `iface_index`, e.g. when
+    /// that neighbor is gone. The same (link-local) address on another
+    /// interface is another neighbor, whose routes stay.
+    ///
+    /// Our own routes have no next hop and are never removed. Returns the
+    /// keys that lost at least one route.
+    pub fn remove_by_next_hop(&mut self, next_hop: IpAddr, iface_index: u32) -> Vec<RouteKey> {
+        let mut keys: Vec<RouteKey> = Vec::new();
+        self.routes.retain(|r| {
+            if r.next_hop == Some(next_hop) && r.iface_index == iface_index {
+                if !keys.contains(&r.key) {
+                    keys.push(r.key.clone());
+                }
+                false
+            } else {
+                true
+            }
+        });
+        for key in &keys {
+            self.reselect(key);
+        }
+        keys
+    }
+
+    /// Mutable access to all learned routes whose next hop is `next_hop`.
+    ///
+    /// Use [`Route::set_metric`] to change their metric, and reselect the
+    /// affected prefixes afterwards.
+    pub fn routes_via_mut(&mut self, next_hop: IpAddr) -> impl Iterator<Item = &mut Route> {
+        self.routes
+            .iter_mut()
+            .filter(move |r| r.next_hop == Some(next_hop))
+    }
+
+    /// Remove all learned routes on the given interface.
+    ///
+    /// Local routes (no next hop) are kept. Returns the keys that lost at
+    /// least one route.
+    pub fn retract_all_from_iface(&mut self, iface_index: u32) -> Vec<RouteKey> {
+        let mut keys: Vec<RouteKey> = Vec::new();
+        self.routes.retain(|r| {
+            if r.iface_index == iface_index && r.next_hop.is_some() {
+                if !keys.contains(&r.key) {
+                    keys.push(r.key.clone());
+                }
+                false
+            } else {
+                true
+            }
+        });
+        for key in &keys {
+            self.reselect(key);
+        }
+        keys
+    }
+
+    /// Whether `new` should replace `old` for the same path.
+    ///
+    /// A newer seqno always wins. At the same seqno only a better metric is
+    /// accepted, so a peer re-sending one seqno can't make the metric bounce.
     fn is_better(new: &Route, old: &Route) -> bool {
-        if new.metric < old.metric {
-            true
-        } else if new.metric > old.metric {
-            false
+        if new.seqno != old.seqno {
+            seqno_gt(new.seqno, old.seqno)
         } else {
-            new.seqno > old.seqno
+            new.metric < old.metric
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn key() -> RouteKey {
+        RouteKey {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 9, 9],
         }
     }
+
+    fn route(router: u8, last_octet: u8, iface_index: u32) -> Route {
+        Route {
+            key: key(),
+            metric: 100,
+            advertised_metric: 100,
+            smoothed_metric: 100,
+            seqno: 1,
+            router_id: [router; 8],
+            next_hop: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, last_octet))),
+            iface_index,
+            last_updated: Instant::now(),
+            expires_at: Instant::now() + Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn best_route_ties_are_stable() {
+        let candidates = [
+            route(2, 1, 1),
+            route(1, 2, 1),
+            route(1, 1, 2),
+            route(1, 1, 1),
+        ];
+
+        for rotation in 0..candidates.len() {
+            let mut table = RoutingTable::new();
+            for i in 0..candidates.len() {
+                table.install_or_update(candidates[(i + rotation) % candidates.len()].clone());
+            }
+
+            let best = table.best_route(&key()).unwrap();
+            assert_eq!(best.router_id, [1; 8]);
+            assert_eq!(best.next_hop, Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+            assert_eq!(best.iface_index, 1);
+        }
+    }
+
+    #[test]
+    fn reinstalling_an_equal_route_keeps_the_best() {
+        for (first, second) in [
+            (route(1, 1, 1), route(2, 2, 1)),
+            (route(2, 2, 1), route(1, 1, 1)),
+        ] {
+            let mut table = RoutingTable::new();
+            table.install_or_update(first.clone());
+            table.install_or_update(second.clone());
+            assert_eq!(table.best_route(&key()).unwrap().router_id, [1; 8]);
+
+            for again in [first, second] {
+                assert!(!table.install_or_update(again).best_changed);
+                assert_eq!(table.best_route(&key()).unwrap().router_id, [1; 8]);
+            }
+        }
+    }
+
+    #[test]
+    fn route_keys_render_as_cidr() {
+        let v4 = RouteKey {
+            ae: 1,
+            plen: 24,
+            prefix: vec![10, 0, 1],
+        };
+        assert_eq!(v4.to_cidr_string().as_deref(), Some("10.0.1.0/24"));
+        assert_eq!(v4.to_string(), "10.0.1.0/24");
+
+        let v6 = RouteKey {
+            ae: 2,
+            plen: 48,
+            prefix: vec![0x20, 0x01, 0x0d, 0xb8, 0x00, 0x01],
+        };
+        assert_eq!(v6.to_cidr_string().as_deref(), Some("2001:db8:1::/48"));
+
+        let link_local = RouteKey {
+            ae: 3,
+            plen: 128,
+            prefix: vec![0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        };
+        assert_eq!(link_local.to_string(), "fe80::1/128");
+
+        let wildcard = RouteKey {
+            ae: 0,
+            plen: 0,
+            prefix: Vec::new(),
+        };
+        assert_eq!(wildcard.to_cidr_string(), None);
+        assert_eq!(wildcard.to_string(), "ae=0 plen=0 prefix=[]");
+
+        let mut r = route(1, 1, 1);
+        assert!(r.summary().starts_with("10.9.9.0/24 metric=100"));
+        assert!(!r.is_retracted());
+        r.metric = INFINITY;
+        assert!(r.is_retracted());
+    }
+
+    #[cfg(feature = "ipnet")]
+    #[test]
+    fn route_keys_round_trip_through_ipnet() {
+        for (cidr, prefix) in [
+            ("10.0.1.0/24", vec![10, 0, 1]),
+            ("10.0.16.0/20", vec![10, 0, 16]),
+            ("2001:db8:1::/48", vec![0x20, 0x01, 0x0d, 0xb8, 0x00, 0x01]),
+        ] {
+            let net: ipnet::IpNet = cidr.parse().unwrap();
+            let key = RouteKey::from_ipnet(&net);
+            assert_eq!(key.prefix, prefix);
+            assert_eq!(key.to_ipnet(), Some(net));
+        }
+
+        // Host bits in the partial byte are cleared both ways.
+        let net: ipnet::IpNet = "10.0.31.7/20".parse().unwrap();
+        assert_eq!(RouteKey::from_ipnet(&net).prefix, vec![10, 0, 16]);
+        let key = RouteKey {
+            ae: 1,
+            plen: 20,
+            prefix: vec![10, 0, 31],
+        };
+        assert_eq!(key.to_ipnet(), Some("10.0.16.0/20".parse().unwrap()));
+        assert!(
+            key.to_ipnet()
+                .unwrap()
+                .contains(&"10.0.17.1".parse::<IpAddr>().unwrap())
+        );
+
+        let wildcard = RouteKey {
+            ae: 0,
+            plen: 0,
+            prefix: Vec::new(),
+        };
+        assert_eq!(wildcard.to_ipnet(), None);
+    }
+
+    #[test]
+    fn lookup_prefers_longest_prefix() {
+        let mut table = RoutingTable::new();
+        let with_key = |router: u8, addr: IpAddr, plen: u8| Route {
+            key: RouteKey::from_addr(addr, plen),
+            ..route(router, router, 1)
+        };
+        let v4 = |a, b, c, d| IpAddr::V4(Ipv4Addr::new(a, b, c, d));
+        table.install_or_update(with_key(1, v4(10, 9, 0, 0), 16));
+        table.install_or_update(with_key(2, v4(10, 9, 9, 0), 24));
+        table.install_or_update(with_key(3, v4(10, 9, 16, 0), 20));
+
+        assert_eq!(table.lookup(v4(10, 9, 9, 7)).unwrap().router_id, [2; 8]);
+        assert_eq!(table.lookup(v4(10, 9, 8, 1)).unwrap().router_id, [1; 8]);
+        // /20 covers 10.9.16.0 - 10.9.31.255 only.
+        assert_eq!(table.lookup(v4(10, 9, 31, 1)).unwrap().router_id, [3; 8]);
+        assert_eq!(table.lookup(v4(10, 9, 32, 1)).unwrap().router_id, [1; 8]);
+        assert!(table.lookup(v4(10, 8, 0, 1)).is_none());
+
+        let v6 = |s: &str| IpAddr::V6(s.parse().unwrap());
+        table.install_or_update(with_key(4, v6("2001:db8::"), 32));
+        table.install_or_update(with_key(5, v6("2001:db8:1::"), 48));
+        assert_eq!(table.lookup(v6("2001:db8:1::5")).unwrap().router_id, [5; 8]);
+        assert_eq!(table.lookup(v6("2001:db8:2::5")).unwrap().router_id, [4; 8]);
+        assert!(table.lookup(v6("::ffff:10.9.9.7")).is_none());
+
+        // An unreachable /24 doesn't shadow the /16.
+        let mut retracted = with_key(2, v4(10, 9, 9, 0), 24);
+        retracted.metric = INFINITY;
+        retracted.seqno = 2;
+        table.install_or_update(retracted);
+        assert_eq!(table.lookup(v4(10, 9, 9, 7)).unwrap().router_id, [1; 8]);
+    }
+
+    #[test]
+    fn seqnos_compare_modulo_2_16() {
+        assert!(seqno_gt(2, 1));
+        assert!(!seqno_gt(1, 2));
+        assert!(!seqno_gt(7, 7));
+
+        // Wraparound: 0 follows 65535.
+        assert!(seqno_gt(0, 65535));
+        assert!(!seqno_gt(65535, 0));
+        assert!(seqno_gt(0x7fff, 0));
+        assert!(seqno_gt(0, 0x8001));
+
+        // Exactly half the space apart: neither is newer.
+        assert!(!seqno_gt(0x8000, 0));
+        assert!(!seqno_gt(0, 0x8000));
+        assert_eq!(seqno_cmp(0, 0x8000), Ordering::Equal);
+    }
+
+    #[test]
+    fn wrapped_seqno_replaces_route() {
+        let mut table = RoutingTable::new();
+        let mut old = route(1, 1, 1);
+        old.seqno = 65535;
+        table.install_or_update(old);
+
+        let mut wrapped = route(1, 1, 1);
+        wrapped.seqno = 0;
+        wrapped.metric = 300;
+        assert!(table.install_or_update(wrapped).changed());
+        assert_eq!(table.best_route(&key()).unwrap().seqno, 0);
+
+        // Between equal metrics, the post-wrap seqno is preferred.
+        let mut other = route(2, 2, 1);
+        other.seqno = 65535;
+        other.metric = 300;
+        table.install_or_update(other);
+        assert_eq!(table.best_route(&key()).unwrap().router_id, [1; 8]);
+    }
+
+    #[test]
+    fn install_reports_change_kind() {
+        let mut table = RoutingTable::new();
+        let outcome = |change, best_changed| InstallOutcome {
+            change,
+            best_changed,
+        };
+
+        assert_eq!(
+            table.install_or_update(route(1, 1, 1)),
+            outcome(RouteChange::Inserted, true)
+        );
+        // Same seqno and metric: a refresh.
+        assert_eq!(
+            table.install_or_update(route(1, 1, 1)),
+            outcome(RouteChange::Unchanged, false)
+        );
+        // Worse metric at the same seqno is ignored.
+        let worse = Route {
+            metric: 200,
+            ..route(1, 1, 1)
+        };
+        assert_eq!(
+            table.install_or_update(worse),
+            outcome(RouteChange::Unchanged, false)
+        );
+
+        let better = Route {
+            metric: 50,
+            ..route(1, 1, 1)
+        };
+        assert_eq!(
+            table.install_or_update(better),
+            outcome(RouteChange::Improved, true)
+        );
+        // A second, worse path doesn't change the best route.
+        assert_eq!(
+            table.install_or_update(route(2, 2, 1)),
+            outcome(RouteChange::Inserted, false)
+        );
+
+        let retraction = Route {
+            metric: INFINITY,
+            seqno: 2,
+            ..route(1, 1, 1)
+        };
+        assert_eq!(
+            table.install_or_update(retraction),
+            outcome(RouteChange::Retracted, true)
+        );
+        assert_eq!(table.best_route(&key()).unwrap().router_id, [2; 8]);
+    }
+
+    #[test]
+    fn hysteresis_keeps_best_route_within_margin() {
+        let mut table = RoutingTable::with_hysteresis_margin(16);
+        let update = |router: u8, seqno: u16, metric: u16| Route {
+            metric,
+            smoothed_metric: metric,
+            seqno,
+            ..route(router, router, 1)
+        };
+
+        table.install_or_update(update(1, 1, 100));
+        table.install_or_update(update(2, 1, 104));
+        assert_eq!(table.best_route(&key()).unwrap().router_id, [1; 8]);
+
+        // The routes take turns being slightly better.
+        for seqno in 2..20 {
+            let (a, b) = if seqno % 2 == 0 { (110, 96) } else { (96, 110) };
+            table.install_or_update(update(1, seqno, a));
+            assert_eq!(table.best_route(&key()).unwrap().router_id, [1; 8]);
+            table.install_or_update(update(2, seqno, b));
+            assert_eq!(table.best_route(&key()).unwrap().router_id, [1; 8]);
+        }
+
+        // A clearly better route wins once its average has caught up.
+        let mut seqno = 20;
+        while table.best_route(&key()).unwrap().router_id == [1; 8] {
+            assert!(seqno < 30, "never switched");
+            table.install_or_update(update(2, seqno, 40));
+            seqno += 1;
+        }
+        assert_eq!(table.best_route(&key()).unwrap().metric, 40);
+    }
+
+    #[test]
+    fn smoothed_metric_follows_metric() {
+        let mut r = route(1, 1, 1);
+        r.set_metric(200);
+        assert_eq!(r.smoothed_metric, 125);
+        r.set_metric(INFINITY);
+        assert_eq!(r.smoothed_metric, INFINITY);
+        r.set_metric(80);
+        assert_eq!(r.smoothed_metric, 80);
+    }
+
+    #[test]
+    fn remove_by_next_hop_keeps_other_routes() {
+        let mut table = RoutingTable::new();
+        let local = Route {
+            next_hop: None,
+            ..route(9, 0, 1)
+        };
+        let other_key = RouteKey {
+            prefix: vec![10, 9, 8],
+            ..key()
+        };
+        table.install_or_update(local);
+        table.install_or_update(route(1, 1, 1));
+        table.install_or_update(route(2, 2, 1));
+        table.install_or_update(Route {
+            key: other_key.clone(),
+            ..route(1, 1, 2)
+        });
+
+        let gone = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(table.remove_by_next_hop(gone, 1), vec![key()]);
+        assert!(
+            table
+                .all()
+                .iter()
+                .all(|r| r.next_hop != Some(gone) || r.iface_index != 1)
+        );
+        assert_eq!(table.all().len(), 3);
+        assert!(table.remove_by_next_hop(gone, 1).is_empty());
+
+        // The same address on another interface is another neighbor.
+        assert_eq!(table.remove_by_next_hop(gone, 2), vec![other_key]);
+        assert_eq!(table.all().len(), 2);
+    }
+
+    #[test]
+    fn feasibility_condition() {
+        let mut sources = SourceTable::new();
+        let advertised = route(1, 1, 1);
+        sources.update(
+            &advertised.key,
+            advertised.router_id,
+            10,
+            100,
+            Instant::now(),
+        );
+
+        // Learned over a link of cost 96.
+        let with = |seqno: u16, advertised_metric: u16| Route {
+            seqno,
+            metric: advertised_metric.saturating_add(96),
+            advertised_metric,
+            ..route(1, 2, 1)
+        };
+
+        // Newer seqno: feasible whatever the metric.
+        assert!(RoutingTable::is_feasible(&with(11, 500), &sources));
+        // Same seqno: only a strictly lower advertised metric is feasible,
+        // whatever our link cost adds to it.
+        assert!(RoutingTable::is_feasible(&with(10, 99), &sources));
+        assert!(!RoutingTable::is_feasible(&with(10, 100), &sources));
+        // Older seqno: never feasible, but a retraction always is.
+        assert!(!RoutingTable::is_feasible(&with(9, 1), &sources));
+        assert!(RoutingTable::is_feasible(&with(9, INFINITY), &sources));
+        // Seqnos wrap around.
+        sources.update(&advertised.key, [3; 8], u16::MAX, 100, Instant::now());
+        let wrapped = Route {
+            router_id: [3; 8],
+            ..with(0, 500)
+        };
+        assert!(RoutingTable::is_feasible(&wrapped, &sources));
+    }
+
+    #[test]
+    fn feasibility_distance_survives_retraction_until_gc() {
+        let mut table = RoutingTable::new();
+        let mut advertised = route(1, 1, 1);
+        advertised.seqno = 10;
+        advertised.metric = 100;
+        let t0 = Instant::now();
+        table.install_or_update(advertised.clone());
+        table.note_advertised(&advertised, t0);
+
+        // The route is retracted and garbage-collected...
+        table.install_or_update(Route {
+            seqno: 11,
+            metric: INFINITY,
+            ..advertised.clone()
+        });
+        let later = t0 + GC_GRACE + Duration::from_secs(1);
+        table.gc(later);
+        assert!(table.all().is_empty());
+        // ...but its feasibility distance stays.
+        assert_eq!(table.sources().get(&key(), [1; 8]), Some((10, 100)));
+        let worse = Route {
+            seqno: 10,
+            metric: 200,
+            ..route(1, 2, 1)
+        };
+        assert!(!table.install_or_update(worse.clone()).changed());
+
+        // Until the source entry times out.
+        assert_eq!(table.sources.prune(later), 0);
+        table.gc(t0 + SOURCE_GC_TIME + Duration::from_secs(1));
+        assert_eq!(table.sources().get(&key(), [1; 8]), None);
+        assert!(table.install_or_update(worse).changed());
+    }
+
+    #[test]
+    fn source_entries_age_on_the_given_clock() {
+        let mut sources = SourceTable::new();
+        let t0 = Instant::now() + Duration::from_secs(3600);
+        sources.update(&key(), [1; 8], 10, 100, t0);
+
+        assert_eq!(sources.prune(t0 + SOURCE_GC_TIME), 0);
+        assert_eq!(
+            sources.prune(t0 + SOURCE_GC_TIME + Duration::from_secs(1)),
+            1
+        );
+    }
+
+    #[test]
+    fn unfeasible_routes_are_not_installed() {
+        let mut table = RoutingTable::new();
+        let mut advertised = route(1, 1, 1);
+        advertised.seqno = 10;
+        table.install_or_update(advertised.clone());
+        table.note_advertised(&advertised, Instant::now());
+
+        // Same source via another neighbor, same seqno, no better metric.
+        let mut looped = route(1, 2, 1);
+        looped.seqno = 10;
+        assert!(!table.install_or_update(looped.clone()).changed());
+        assert_eq!(table.routes_for(&key()).count(), 1);
+
+        looped.seqno = 11;
+        assert!(table.install_or_update(looped).changed());
+        assert_eq!(table.routes_for(&key()).count(), 2);
+    }
+
+    #[test]
+    fn routes_expire_after_hold_time() {
+        let mut table = RoutingTable::new();
+        let now = Instant::now();
+
+        let mut short = route(1, 1, 1);
+        short.expires_at = now + route_hold_time(1000);
+        let mut local = route(2, 2, 1);
+        local.next_hop = None;
+        local.expires_at = now;
+        table.install_or_update(short);
+        table.install_or_update(local);
+
+        assert!(
+            table
+                .prune_expired(now + Duration::from_millis(3000))
+                .is_empty()
+        );
+        assert_eq!(
+            table.prune_expired(now + Duration::from_millis(3500)),
+            vec![key()]
+        );
+        // Only the local route is left.
+        assert_eq!(table.all().len(), 1);
+        assert!(table.all()[0].next_hop.is_none());
+    }
+
+    #[test]
+    fn gc_reclaims_unreachable_routes_after_grace() {
+        let mut table = RoutingTable::new();
+        let now = Instant::now();
+
+        let live = route(1, 1, 1);
+        let mut retracted = route(2, 2, 1);
+        retracted.metric = INFINITY;
+        retracted.last_updated = now;
+        table.install_or_update(live);
+        table.install_or_update(retracted);
+
+        assert_eq!(table.gc(now + GC_GRACE / 2), 0);
+        assert_eq!(table.all().len(), 2);
+
+        assert_eq!(table.gc(now + GC_GRACE * 2), 1);
+        assert_eq!(table.all().len(), 1);
+        assert_eq!(table.all()[0].router_id, [1; 8]);
+    }
 }
@@ -1,17 +1,23 @@
 //! Babel TLV parsing and serialization based on RFC 8966
 //!
 //! This module provides types and functions to work with Babel TLVs and sub-TLVs:
-//! - `Tlv`: enum of all Babel TLV types (0‒10 plus Unknown)
-//! - `SubTlv`: enum for sub-TLV types (Pad1, PadN, Unknown)
+//! - `Tlv`: enum of all Babel TLV types (0‒10 plus RFC 8967 PC and HMAC, and Unknown)
+//! - `SubTlv`: enum for sub-TLV types (Pad1, PadN, SourcePrefix, Unknown)
 //! - `parse_all` / `parse`: routines to decode TLVs from a byte buffer
 //! - `to_bytes`: routines to encode TLVs back to wire format
+//! - `DefaultPrefixes`: per-AE state for Update prefix compression
+//! - `ae_family` / `AddressFamily`: mapping from an AE to its IP family
+//! - `TlvError`: errors from parsing and encoding
+//! - `TlvRef` / `parse_all_borrowed`: allocation-free view of received TLVs
+//! - `Display` for `Tlv`: one-line, human-readable rendering for logs
 //!
 //! References:
 //! - <https://tools.ietf.org/html/rfc8966#section-4.3> (TLV types)
 //! - <https://tools.ietf.org/html/rfc8966#section-4.7> (sub-TLVs)
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{Cursor, Read};
+use std::fmt;
+use std::io::{self, Cursor, Read};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::vec;
 
@@ -20,6 +26,7 @@ use std::vec;
 /// Each variant holds the TLV-specific fields. Unrecognized TLV types
 /// are captured in the `Unknown` variant for forward compatibility.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Tlv {
     /// Pad1 (Type = 0): single-byte padding.
     Pad1,
@@ -88,59 +95,571 @@ pub enum Tlv {
         prefix: Vec<u8>,
         sub_tlvs: Vec<SubTlv>,
     },
+    /// PC (Type = 16, RFC 8967): [PC(4), Index...]
+    Pc { pc: u32, index: Vec<u8> },
+    /// HMAC (Type = 17, RFC 8967): [HMAC...], found in the packet trailer.
+    Hmac { hmac: Vec<u8> },
     /// Any other, unrecognized TLV: raw type byte + data.
     Unknown { tlv_type: u8, data: Vec<u8> },
 }
 
+/// Update flag: this Update establishes a new default prefix for its AE.
+pub const UPDATE_FLAG_DEFAULT_PREFIX: u8 = 0x80;
+/// Update flag: the router-id should be derived from this Update's prefix.
+pub const UPDATE_FLAG_DEFAULT_ROUTER_ID: u8 = 0x40;
+
+/// IP address family designated by an address encoding (AE).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    /// Width of an address of this family, in bits.
+    pub fn max_plen(self) -> u8 {
+        match self {
+            AddressFamily::V4 => 32,
+            AddressFamily::V6 => 128,
+        }
+    }
+}
+
+/// Address family of `ae`: 1 is IPv4, 2 and 3 (link-local) are IPv6.
+///
+/// AE 0 (wildcard) and unknown AEs have no family.
+pub fn ae_family(ae: u8) -> Option<AddressFamily> {
+    match ae {
+        1 => Some(AddressFamily::V4),
+        2 | 3 => Some(AddressFamily::V6),
+        _ => None,
+    }
+}
+
+/// The `fe80::/64` prefix implied by AE 3.
+const LINK_LOCAL_PREFIX: [u8; 8] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0];
+
+/// Read the address of an IHU or NextHop TLV encoded with `ae`.
+///
+/// AE 3 carries only the interface identifier of a link-local address;
+/// the `fe80::/64` prefix is implied.
+fn read_address<R: Read>(p: &mut R, ae: u8) -> io::Result<Option<IpAddr>> {
+    let addr = match ae {
+        1 => {
+            let mut o = [0; 4];
+            p.read_exact(&mut o)?;
+            Some(IpAddr::V4(Ipv4Addr::from(o)))
+        }
+        2 => {
+            let mut o = [0; 16];
+            p.read_exact(&mut o)?;
+            Some(IpAddr::V6(Ipv6Addr::from(o)))
+        }
+        3 => {
+            let mut o = [0; 16];
+            o[..8].copy_from_slice(&LINK_LOCAL_PREFIX);
+            p.read_exact(&mut o[8..])?;
+            Some(IpAddr::V6(Ipv6Addr::from(o)))
+        }
+        _ => None,
+    };
+    Ok(addr)
+}
+
+/// Wire form of an IHU or NextHop address encoded with `ae`.
+fn address_bytes(ae: u8, addr: &Option<IpAddr>) -> Vec<u8> {
+    match addr {
+        Some(IpAddr::V4(v4)) => v4.octets().to_vec(),
+        Some(IpAddr::V6(v6)) if ae == 3 => v6.octets()[8..].to_vec(),
+        Some(IpAddr::V6(v6)) => v6.octets().to_vec(),
+        None => Vec::new(),
+    }
+}
+
+/// Bytes of an Update prefix that go on the wire.
+///
+/// For AE 3 the `fe80::/64` part is implied and left off.
+fn update_prefix_wire(ae: u8, prefix: &[u8]) -> &[u8] {
+    if ae == 3 {
+        &prefix[prefix.len().min(LINK_LOCAL_PREFIX.len())..]
+    } else {
+        prefix
+    }
+}
+
+/// Length of the address that follows the fixed fields of an IHU or
+/// NextHop with `ae`.
+fn ae_address_len(ae: u8) -> usize {
+    match ae {
+        1 => 4,
+        2 => 16,
+        3 => 8,
+        _ => 0,
+    }
+}
+
+/// Number of prefix bytes on the wire in an Update with these fields.
+fn update_prefix_wire_len(ae: u8, plen: u8, omitted: u8) -> usize {
+    let full_len = (plen as usize).div_ceil(8);
+    let len = full_len.saturating_sub(omitted as usize);
+    if ae == 3 {
+        len.saturating_sub(full_len.min(LINK_LOCAL_PREFIX.len()))
+    } else {
+        len
+    }
+}
+
+/// Length of the address [`address_bytes`] writes for `ae`.
+fn address_len(ae: u8, addr: &Option<IpAddr>) -> usize {
+    match addr {
+        Some(IpAddr::V4(_)) => 4,
+        Some(IpAddr::V6(_)) if ae == 3 => 8,
+        Some(IpAddr::V6(_)) => 16,
+        None => 0,
+    }
+}
+
+/// Per-AE default prefix state used to compress Update prefixes within a
+/// single packet, per RFC 8966 §4.6.9.
+///
+/// The same state machine serves both directions: a sender calls
+/// [`compress`](Self::compress) for each Update it writes, a receiver calls
+/// [`expand`](Self::expand) for each Update it reads, in packet order.
+#[derive(Debug, Default, Clone)]
+pub struct DefaultPrefixes {
+    v4: Option<Vec<u8>>,
+    v6: Option<Vec<u8>>,
+}
+
+impl DefaultPrefixes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn slot(&mut self, ae: u8) -> Option<&mut Option<Vec<u8>>> {
+        match ae {
+            1 => Some(&mut self.v4),
+            2 => Some(&mut self.v6),
+            _ => None,
+        }
+    }
+
+    /// Compress a full prefix for the wire.
+    ///
+    /// Returns `(flags, omitted, bytes)` where `bytes` is the prefix with the
+    /// leading `omitted` bytes shared with the current default removed. The
+    /// full prefix then becomes the new default for `ae`.
+    pub fn compress(&mut self, ae: u8, prefix: &[u8]) -> (u8, u8, Vec<u8>) {
+        let slot = match self.slot(ae) {
+            Some(slot) => slot,
+            None => return (0, 0, prefix.to_vec()),
+        };
+
+        let omitted = match slot {
+            Some(default) => default
+                .iter()
+                .zip(prefix)
+                .take_while(|(a, b)| a == b)
+                .count(),
+            None => 0,
+        };
+
+        *slot = Some(prefix.to_vec());
+        (
+            UPDATE_FLAG_DEFAULT_PREFIX,
+            omitted as u8,
+            prefix[omitted..].to_vec(),
+        )
+    }
+
+    /// Reconstruct a full prefix from its wire form.
+    ///
+    /// Prepends `omitted` bytes of the current default prefix for `ae`, and
+    /// records the result as the new default if `flags` asks for it.
+    pub fn expand(
+        &mut self,
+        ae: u8,
+        flags: u8,
+        omitted: u8,
+        prefix: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let omitted = omitted as usize;
+        let slot = match self.slot(ae) {
+            Some(slot) => slot,
+            None if omitted == 0 => return Ok(prefix.to_vec()),
+            None => return Err(format!("omitted bytes not allowed for AE {ae}")),
+        };
+
+        let mut full = Vec::with_capacity(omitted + prefix.len());
+        if omitted > 0 {
+            match slot {
+                Some(default) if default.len() >= omitted => {
+                    full.extend_from_slice(&default[..omitted]);
+                }
+                _ => return Err("omitted bytes exceed default prefix".into()),
+            }
+        }
+        full.extend_from_slice(prefix);
+
+        if flags & UPDATE_FLAG_DEFAULT_PREFIX != 0 {
+            *slot = Some(full.clone());
+        }
+        Ok(full)
+    }
+}
+
+/// Errors from parsing or encoding TLVs.
+#[derive(Debug)]
+pub enum TlvError {
+    /// Clean end of buffer: no TLV starts at the cursor.
+    Eof,
+    /// A TLV's length field points past the end of the buffer.
+    LengthExceedsBuffer,
+    /// A TLV's body is too short for the fields its type requires.
+    Truncated { tlv_type: u8 },
+    /// Any other I/O error while reading.
+    Io(io::Error),
+    /// The serialized body of a TLV or sub-TLV (`tlv_type` is then its
+    /// SType) would not fit the one-byte length field.
+    BodyTooLong { tlv_type: u8, len: usize },
+    /// The serialized body of a packet would not fit the two-byte length
+    /// field of its header.
+    PacketTooLong { len: usize },
+    /// A prefix length beyond the width of the AE's addresses, or more
+    /// omitted bytes than the prefix has.
+    InvalidPrefix { tlv_type: u8 },
+}
+
+impl fmt::Display for TlvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlvError::Eof => write!(f, "EOF"),
+            TlvError::LengthExceedsBuffer => write!(f, "Length exceeds buffer"),
+            TlvError::Truncated { tlv_type } => write!(f, "TLV type {tlv_type} is truncated"),
+            TlvError::Io(e) => write!(f, "I/O error: {e}"),
+            TlvError::BodyTooLong { tlv_type, len } => {
+                write!(f, "TLV type {tlv_type} body is {len} bytes, max is 255")
+            }
+            TlvError::PacketTooLong { len } => {
+                write!(f, "packet body is {len} bytes, max is 65535")
+            }
+            TlvError::InvalidPrefix { tlv_type } => {
+                write!(f, "TLV type {tlv_type} has an invalid prefix length")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TlvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TlvError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TlvError {
+    fn from(e: io::Error) -> Self {
+        TlvError::Io(e)
+    }
+}
+
+impl From<TlvError> for io::Error {
+    /// Encoding errors are invalid input, parse errors invalid data.
+    fn from(e: TlvError) -> Self {
+        match e {
+            TlvError::Io(e) => e,
+            TlvError::BodyTooLong { .. } | TlvError::PacketTooLong { .. } => {
+                io::Error::new(io::ErrorKind::InvalidInput, e)
+            }
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Error mapper for reads past the end of a TLV body of type `tlv_type`.
+fn truncated(tlv_type: u8) -> impl Fn(io::Error) -> TlvError {
+    move |_| TlvError::Truncated { tlv_type }
+}
+
+/// Parse the sub-TLVs at the end of a TLV body of type `tlv_type`; a
+/// malformed sub-TLV makes the enclosing TLV truncated.
+fn parse_sub_tlvs(tlv_type: u8, buf: &[u8]) -> Result<Vec<SubTlv>, TlvError> {
+    SubTlv::parse_list(buf).map_err(|_| TlvError::Truncated { tlv_type })
+}
+
+/// Reject a `plen` wider than the addresses of `ae`. AE 0 (wildcard)
+/// carries no prefix; unknown AEs are left for the caller to ignore.
+fn check_plen(tlv_type: u8, ae: u8, plen: u8) -> Result<(), TlvError> {
+    let max = match ae {
+        0 => 0,
+        _ => match ae_family(ae) {
+            Some(family) => family.max_plen(),
+            None => return Ok(()),
+        },
+    };
+    if plen > max {
+        return Err(TlvError::InvalidPrefix { tlv_type });
+    }
+    Ok(())
+}
+
+/// Size of the fixed fields of a TLV of type `tlv_type`, i.e. the shortest
+/// body it can have (addresses and prefixes come on top).
+fn min_body_len(tlv_type: u8) -> usize {
+    match tlv_type {
+        2 => 6,   // AckRequest: Reserved, Opaque, Interval
+        3 => 2,   // Ack: Opaque
+        4 => 6,   // Hello: Flags, Seqno, Interval
+        5 => 6,   // IHU: AE, Reserved, Rxcost, Interval
+        6 => 10,  // RouterId: Reserved, Router-Id
+        7 => 2,   // NextHop: AE, Reserved
+        8 => 10,  // Update: AE, Flags, PLen, Omitted, Interval, Seqno, Metric
+        9 => 2,   // RouteRequest: AE, PLen
+        10 => 14, // SeqnoRequest: AE, PLen, Seqno, Hop Count, Reserved, Router-Id
+        16 => 4,  // PC: Packet Counter
+        _ => 0,
+    }
+}
+
+/// SType of the RFC 9616 Timestamp sub-TLV.
+pub const SUBTLV_TIMESTAMP: u8 = 3;
+
+/// SType of the RFC 9079 Source Prefix sub-TLV.
+pub const SUBTLV_SOURCE_PREFIX: u8 = 128;
+
 /// A sub-TLV inside certain TLVs, per RFC 8966 §4.7.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SubTlv {
     /// Pad1 (SType = 0)
     Pad1,
     /// PadN (SType = 1)
     PadN { n: u8 },
+    /// Timestamp (SType = 3, RFC 9616), in microseconds. In a Hello,
+    /// `origin` is the transmit time and `receive` is `None`; in an IHU,
+    /// `origin` echoes the neighbor's Hello timestamp and `receive` is when
+    /// that Hello arrived.
+    Timestamp { origin: u32, receive: Option<u32> },
+    /// Source Prefix (SType = 128), RFC 9079: the source of a
+    /// source-specific route, `ceil(plen / 8)` prefix bytes.
+    SourcePrefix { plen: u8, source_prefix: Vec<u8> },
     /// Any other, unrecognized sub-TLV: SType + data.
     Unknown { stype: u8, data: Vec<u8> },
 }
 
+impl fmt::Display for Tlv {
+    /// One line with the decoded fields, e.g. `Hello seqno=278
+    /// interval=400ms` or `Update 10.0.1.0/24 metric=256 seqno=10 ...`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tlv::Pad1 => write!(f, "Pad1")?,
+            Tlv::PadN { n } => write!(f, "PadN n={n}")?,
+            Tlv::AckRequest {
+                opaque, interval, ..
+            } => write!(f, "AckRequest opaque={opaque} interval={interval}ms")?,
+            Tlv::Ack { opaque, .. } => write!(f, "Ack opaque={opaque}")?,
+            Tlv::Hello {
+                flags,
+                seqno,
+                interval,
+                ..
+            } => {
+                write!(f, "Hello seqno={seqno} interval={interval}ms")?;
+                if *flags != 0 {
+                    write!(f, " flags={flags:#06x}")?;
+                }
+            }
+            Tlv::Ihu {
+                rxcost,
+                interval,
+                addr,
+                ..
+            } => {
+                write!(f, "IHU rxcost={rxcost} interval={interval}ms")?;
+                if let Some(addr) = addr {
+                    write!(f, " addr={addr}")?;
+                }
+            }
+            Tlv::RouterId { router_id, .. } => write!(f, "RouterId {}", RouterIdHex(router_id))?,
+            Tlv::NextHop { addr, .. } => match addr {
+                Some(addr) => write!(f, "NextHop {addr}")?,
+                None => write!(f, "NextHop (sender)")?,
+            },
+            Tlv::Update {
+                ae,
+                flags,
+                plen,
+                omitted,
+                interval,
+                seqno,
+                metric,
+                prefix,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Update {} metric={metric} seqno={seqno} interval={interval}ms",
+                    PrefixDisplay(*ae, *plen, prefix)
+                )?;
+                if *omitted != 0 {
+                    write!(f, " omitted={omitted}")?;
+                }
+                if *flags != 0 {
+                    write!(f, " flags={flags:#04x}")?;
+                }
+            }
+            Tlv::RouteRequest {
+                ae, plen, prefix, ..
+            } => write!(f, "RouteRequest {}", PrefixDisplay(*ae, *plen, prefix))?,
+            Tlv::SeqnoRequest {
+                ae,
+                plen,
+                seqno,
+                hop_count,
+                router_id,
+                prefix,
+                ..
+            } => write!(
+                f,
+                "SeqnoRequest {} seqno={seqno} hop_count={hop_count} router_id={}",
+                PrefixDisplay(*ae, *plen, prefix),
+                RouterIdHex(router_id)
+            )?,
+            Tlv::Pc { pc, index } => write!(f, "PC pc={pc} index={index:02x?}")?,
+            Tlv::Hmac { hmac } => write!(f, "HMAC len={}", hmac.len())?,
+            Tlv::Unknown { tlv_type, data } => {
+                write!(f, "Unknown type={tlv_type} len={}", data.len())?
+            }
+        }
+
+        let sub_tlvs = self.sub_tlvs();
+        if !sub_tlvs.is_empty() {
+            write!(f, " sub_tlvs={}", sub_tlvs.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// A router-id as colon-separated hex bytes, as babeld prints them.
+struct RouterIdHex<'a>(&'a [u8; 8]);
+
+impl fmt::Display for RouterIdHex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The prefix of an Update or request in CIDR notation: `any` for the
+/// wildcard AE 0, raw fields for AEs without an address family.
+struct PrefixDisplay<'a>(u8, u8, &'a [u8]);
+
+impl fmt::Display for PrefixDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let PrefixDisplay(ae, plen, prefix) = *self;
+        let addr: IpAddr = match (ae, ae_family(ae)) {
+            (0, _) => return f.write_str("any"),
+            (_, Some(AddressFamily::V4)) => {
+                let mut octets = [0u8; 4];
+                let n = prefix.len().min(4);
+                octets[..n].copy_from_slice(&prefix[..n]);
+                Ipv4Addr::from(octets).into()
+            }
+            (_, Some(AddressFamily::V6)) => {
+                let mut octets = [0u8; 16];
+                let n = prefix.len().min(16);
+                octets[..n].copy_from_slice(&prefix[..n]);
+                Ipv6Addr::from(octets).into()
+            }
+            (_, None) => return write!(f, "ae={ae} plen={plen} prefix={prefix:02x?}"),
+        };
+        write!(f, "{addr}/{plen}")
+    }
+}
+
 impl Tlv {
     /// Parse all TLVs found in `buf`, stopping at EOF or error.
     ///
-    /// Returns `Ok(Vec<Tlv>)` if parsing succeeds (possibly empty),
-    /// or `Err(String)` on malformed data.
-    pub fn parse_all(buf: &[u8]) -> Result<Vec<Tlv>, String> {
+    /// Lenient: a TLV whose body is malformed (e.g. a sub-TLV overrunning
+    /// it) is skipped and parsing goes on with the next one. If a TLV's
+    /// length overruns the buffer, the TLVs before it are returned and the
+    /// rest of the buffer is ignored. This never fails; see
+    /// [`parse_all_strict`](Self::parse_all_strict) for a parser that does.
+    pub fn parse_all(buf: &[u8]) -> Result<Vec<Tlv>, TlvError> {
+        let mut out = Vec::new();
+        let mut rest = buf;
+        loop {
+            let (tlvs, error) = Self::parse_until_error(rest);
+            out.extend(tlvs);
+            match error {
+                Some((TlvError::Truncated { .. } | TlvError::InvalidPrefix { .. }, end)) => {
+                    rest = &rest[end..];
+                }
+                _ => return Ok(out),
+            }
+        }
+    }
+
+    /// Parse all TLVs in `buf`, failing unless it ends on a TLV boundary.
+    pub fn parse_all_strict(buf: &[u8]) -> Result<Vec<Tlv>, TlvError> {
+        match Self::parse_until_error(buf) {
+            (out, None) => Ok(out),
+            (_, Some((e, _))) => Err(e),
+        }
+    }
+
+    /// Parse TLVs until clean EOF or the first error, which is returned
+    /// alongside everything parsed before it, with the offset just past
+    /// the TLV that caused it.
+    fn parse_until_error(buf: &[u8]) -> (Vec<Tlv>, Option<(TlvError, usize)>) {
         let mut out = Vec::new();
         let mut cur = Cursor::new(buf);
-        while let Ok(t) = Tlv::parse(&mut cur) {
-            out.push(t);
+        loop {
+            match Tlv::parse(&mut cur) {
+                Ok(t) => out.push(t),
+                Err(TlvError::Eof) => return (out, None),
+                Err(e) => return (out, Some((e, cur.position() as usize))),
+            }
         }
-        Ok(out)
     }
 
     /// Parse a single TLV at the cursor position, advancing the cursor.
     ///
-    /// Returns `Err("EOF")` on end-of-buffer, or other error strings on failure.
-    pub fn parse(cur: &mut Cursor<&[u8]>) -> Result<Tlv, String> {
+    /// Returns [`TlvError::Eof`] on end-of-buffer, or another [`TlvError`]
+    /// on malformed data.
+    pub fn parse(cur: &mut Cursor<&[u8]>) -> Result<Tlv, TlvError> {
         let start = cur.position() as usize;
         let total = cur.get_ref().len();
         if start >= total {
-            return Err("EOF".into());
+            return Err(TlvError::Eof);
         }
         // Read type byte
-        let t = cur.read_u8().map_err(|e| e.to_string())?;
+        let t = cur.read_u8()?;
         if t == 0 {
             // Pad1 is a single byte, no length field
             return Ok(Tlv::Pad1);
         }
         // Read length
-        let length = cur.read_u8().map_err(|e| e.to_string())? as usize;
+        let length = cur.read_u8().map_err(truncated(t))? as usize;
         let pos = cur.position() as usize;
         if pos + length > total {
-            return Err("Length exceeds buffer".into());
+            return Err(TlvError::LengthExceedsBuffer);
         }
         // Extract payload slice
         let payload = cur.get_ref()[pos..pos + length].to_vec();
         cur.set_position((pos + length) as u64);
+        // Reject short bodies before any field is read, so a sub-TLV parse
+        // never starts inside what should have been a fixed field.
+        if length < min_body_len(t) {
+            return Err(TlvError::Truncated { tlv_type: t });
+        }
 
         // Dispatch by TLV type
         // Values 0 and >10 are treated as Pad1 (0) or Unknown respectively
@@ -154,10 +673,10 @@ impl Tlv {
             }
             2 => {
                 let mut p = Cursor::new(&payload);
-                p.read_u16::<BigEndian>().map_err(|e| e.to_string())?; // reserved
-                let opaque = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let interval = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                p.read_u16::<BigEndian>().map_err(truncated(t))?; // reserved
+                let opaque = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let interval = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::AckRequest {
                     opaque,
                     interval,
@@ -166,8 +685,8 @@ impl Tlv {
             }
             3 => {
                 let mut p = Cursor::new(&payload);
-                let opaque = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                let opaque = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::Ack {
                     opaque,
                     sub_tlvs: subs,
@@ -175,10 +694,10 @@ impl Tlv {
             }
             4 => {
                 let mut p = Cursor::new(&payload);
-                let flags = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let seqno = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let interval = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                let flags = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let seqno = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let interval = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::Hello {
                     flags,
                     seqno,
@@ -188,24 +707,12 @@ impl Tlv {
             }
             5 => {
                 let mut p = Cursor::new(&payload);
-                let ae = p.read_u8().map_err(|e| e.to_string())?;
-                p.read_u8().map_err(|e| e.to_string())?;
-                let rxcost = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let interval = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let addr = match ae {
-                    1 => {
-                        let mut o = [0; 4];
-                        p.read_exact(&mut o).map_err(|e| e.to_string())?;
-                        Some(IpAddr::V4(Ipv4Addr::from(o)))
-                    }
-                    2 | 3 => {
-                        let mut o = [0; 16];
-                        p.read_exact(&mut o).map_err(|e| e.to_string())?;
-                        Some(IpAddr::V6(Ipv6Addr::from(o)))
-                    }
-                    _ => None,
-                };
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                let ae = p.read_u8().map_err(truncated(t))?;
+                p.read_u8().map_err(truncated(t))?;
+                let rxcost = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let interval = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let addr = read_address(&mut p, ae).map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::Ihu {
                     ae,
                     rxcost,
@@ -216,10 +723,10 @@ impl Tlv {
             }
             6 => {
                 let mut p = Cursor::new(&payload);
-                p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
+                p.read_u16::<BigEndian>().map_err(truncated(t))?;
                 let mut router_id = [0; 8];
-                p.read_exact(&mut router_id).map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                p.read_exact(&mut router_id).map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::RouterId {
                     router_id,
                     sub_tlvs: subs,
@@ -227,22 +734,10 @@ impl Tlv {
             }
             7 => {
                 let mut p = Cursor::new(&payload);
-                let ae = p.read_u8().map_err(|e| e.to_string())?;
-                p.read_u8().map_err(|e| e.to_string())?;
-                let addr = match ae {
-                    1 => {
-                        let mut o = [0; 4];
-                        p.read_exact(&mut o).map_err(|e| e.to_string())?;
-                        Some(IpAddr::V4(Ipv4Addr::from(o)))
-                    }
-                    2 | 3 => {
-                        let mut o = [0; 16];
-                        p.read_exact(&mut o).map_err(|e| e.to_string())?;
-                        Some(IpAddr::V6(Ipv6Addr::from(o)))
-                    }
-                    _ => None,
-                };
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                let ae = p.read_u8().map_err(truncated(t))?;
+                p.read_u8().map_err(truncated(t))?;
+                let addr = read_address(&mut p, ae).map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::NextHop {
                     ae,
                     addr,
@@ -252,18 +747,30 @@ impl Tlv {
             8 => {
                 // Update TLV: AE, Flags, PLen, Omitted, Interval, Seqno, Metric, Prefix, Sub-TLVs
                 let mut p = Cursor::new(&payload);
-                let ae = p.read_u8().map_err(|e| e.to_string())?;
-                let flags = p.read_u8().map_err(|e| e.to_string())?;
-                let plen = p.read_u8().map_err(|e| e.to_string())?;
-                let omitted = p.read_u8().map_err(|e| e.to_string())?;
-                let interval = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let seqno = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let metric = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
+                let ae = p.read_u8().map_err(truncated(t))?;
+                let flags = p.read_u8().map_err(truncated(t))?;
+                let plen = p.read_u8().map_err(truncated(t))?;
+                let omitted = p.read_u8().map_err(truncated(t))?;
+                let interval = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let seqno = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let metric = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                check_plen(t, ae, plen)?;
                 // Calculate prefix length in bytes
-                let prefix_len = ((plen as usize + 7) / 8).saturating_sub(omitted as usize);
-                let mut prefix = vec![0u8; prefix_len];
-                p.read_exact(&mut prefix).map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                let full_len = (plen as usize).div_ceil(8);
+                if omitted as usize > full_len {
+                    return Err(TlvError::InvalidPrefix { tlv_type: t });
+                }
+                let mut prefix = if ae == 3 {
+                    // Link-local: the fe80::/64 half is implied, only the
+                    // remaining bytes are on the wire.
+                    LINK_LOCAL_PREFIX[..full_len.min(8)].to_vec()
+                } else {
+                    Vec::with_capacity(full_len)
+                };
+                let start = prefix.len();
+                prefix.resize(start + update_prefix_wire_len(ae, plen, omitted), 0);
+                p.read_exact(&mut prefix[start..]).map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::Update {
                     ae,
                     flags,
@@ -279,12 +786,13 @@ impl Tlv {
             9 => {
                 // RouteRequest TLV: AE, PLen, Prefix, Sub-TLVs
                 let mut p = Cursor::new(&payload);
-                let ae = p.read_u8().map_err(|e| e.to_string())?;
-                let plen = p.read_u8().map_err(|e| e.to_string())?;
-                let prefix_len = (plen as usize + 7) / 8;
+                let ae = p.read_u8().map_err(truncated(t))?;
+                let plen = p.read_u8().map_err(truncated(t))?;
+                check_plen(t, ae, plen)?;
+                let prefix_len = (plen as usize).div_ceil(8);
                 let mut prefix = vec![0u8; prefix_len];
-                p.read_exact(&mut prefix).map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                p.read_exact(&mut prefix).map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::RouteRequest {
                     ae,
                     plen,
@@ -295,17 +803,18 @@ impl Tlv {
             10 => {
                 // SeqnoRequest TLV: AE, PLen, Seqno, HopCount, Reserved, RouterID, Prefix, Sub-TLVs
                 let mut p = Cursor::new(&payload);
-                let ae = p.read_u8().map_err(|e| e.to_string())?;
-                let plen = p.read_u8().map_err(|e| e.to_string())?;
-                let seqno = p.read_u16::<BigEndian>().map_err(|e| e.to_string())?;
-                let hop_count = p.read_u8().map_err(|e| e.to_string())?;
-                p.read_u8().map_err(|e| e.to_string())?; // reserved
+                let ae = p.read_u8().map_err(truncated(t))?;
+                let plen = p.read_u8().map_err(truncated(t))?;
+                let seqno = p.read_u16::<BigEndian>().map_err(truncated(t))?;
+                let hop_count = p.read_u8().map_err(truncated(t))?;
+                p.read_u8().map_err(truncated(t))?; // reserved
                 let mut router_id = [0u8; 8];
-                p.read_exact(&mut router_id).map_err(|e| e.to_string())?;
-                let prefix_len = (plen as usize + 7) / 8;
+                p.read_exact(&mut router_id).map_err(truncated(t))?;
+                check_plen(t, ae, plen)?;
+                let prefix_len = (plen as usize).div_ceil(8);
                 let mut prefix = vec![0u8; prefix_len];
-                p.read_exact(&mut prefix).map_err(|e| e.to_string())?;
-                let subs = SubTlv::parse_list(&payload[p.position() as usize..])?;
+                p.read_exact(&mut prefix).map_err(truncated(t))?;
+                let subs = parse_sub_tlvs(t, &payload[p.position() as usize..])?;
                 Tlv::SeqnoRequest {
                     ae,
                     plen,
@@ -316,6 +825,17 @@ impl Tlv {
                     sub_tlvs: subs,
                 }
             }
+            16 => {
+                let mut p = Cursor::new(&payload);
+                let pc = p.read_u32::<BigEndian>().map_err(truncated(t))?;
+                Tlv::Pc {
+                    pc,
+                    index: payload[4..].to_vec(),
+                }
+            }
+            17 => Tlv::Hmac {
+                hmac: payload.clone(),
+            },
             other => Tlv::Unknown {
                 tlv_type: other,
                 data: payload.clone(),
@@ -324,14 +844,108 @@ impl Tlv {
         Ok(result)
     }
 
+    /// Sub-TLVs carried by this TLV (empty for TLVs that have none).
+    pub fn sub_tlvs(&self) -> &[SubTlv] {
+        match self {
+            Tlv::AckRequest { sub_tlvs, .. }
+            | Tlv::Ack { sub_tlvs, .. }
+            | Tlv::Hello { sub_tlvs, .. }
+            | Tlv::Ihu { sub_tlvs, .. }
+            | Tlv::RouterId { sub_tlvs, .. }
+            | Tlv::NextHop { sub_tlvs, .. }
+            | Tlv::Update { sub_tlvs, .. }
+            | Tlv::RouteRequest { sub_tlvs, .. }
+            | Tlv::SeqnoRequest { sub_tlvs, .. } => sub_tlvs,
+            Tlv::Pad1
+            | Tlv::PadN { .. }
+            | Tlv::Pc { .. }
+            | Tlv::Hmac { .. }
+            | Tlv::Unknown { .. } => &[],
+        }
+    }
+
+    /// Whether this TLV carries a mandatory sub-TLV (type 128 and up) we
+    /// don't understand, in which case the whole TLV must be ignored
+    /// (RFC 8966 §4.4).
+    pub fn has_unknown_mandatory_subtlv(&self) -> bool {
+        self.sub_tlvs()
+            .iter()
+            .any(|st| matches!(st, SubTlv::Unknown { stype, .. } if *stype >= 128))
+    }
+
+    /// The Timestamp sub-TLV `(origin, receive)` of a Hello or IHU.
+    pub fn timestamp(&self) -> Option<(u32, Option<u32>)> {
+        match self {
+            Tlv::Hello { sub_tlvs, .. } | Tlv::Ihu { sub_tlvs, .. } => {
+                sub_tlvs.iter().find_map(|st| match st {
+                    SubTlv::Timestamp { origin, receive } => Some((*origin, *receive)),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The source prefix `(plen, bytes)` of a source-specific Update.
+    pub fn source_prefix(&self) -> Option<(u8, &[u8])> {
+        match self {
+            Tlv::Update { sub_tlvs, .. } => sub_tlvs.iter().find_map(|st| match st {
+                SubTlv::SourcePrefix {
+                    plen,
+                    source_prefix,
+                } => Some((*plen, source_prefix.as_slice())),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Exact length of [`to_bytes`](Self::to_bytes)' output (type, length,
+    /// payload and sub-TLVs), computed without encoding.
+    pub fn wire_len(&self) -> usize {
+        let body = match self {
+            Tlv::Pad1 => return 1,
+            Tlv::PadN { n } => *n as usize,
+            Tlv::AckRequest { .. } => 6,
+            Tlv::Ack { .. } => 2,
+            Tlv::Hello { .. } => 6,
+            Tlv::Ihu { ae, addr, .. } => 6 + address_len(*ae, addr),
+            Tlv::RouterId { .. } => 10,
+            Tlv::NextHop { ae, addr, .. } => 2 + address_len(*ae, addr),
+            Tlv::Update { ae, prefix, .. } => 10 + update_prefix_wire(*ae, prefix).len(),
+            Tlv::RouteRequest { prefix, .. } => 2 + prefix.len(),
+            Tlv::SeqnoRequest { prefix, .. } => 14 + prefix.len(),
+            Tlv::Pc { index, .. } => 4 + index.len(),
+            Tlv::Hmac { hmac } => hmac.len(),
+            Tlv::Unknown { data, .. } => data.len(),
+        };
+        2 + body + self.sub_tlvs().iter().map(SubTlv::len).sum::<usize>()
+    }
+
     /// Encode this Tlv into wire-format bytes: type, length, payload, sub-TLVs.
-    pub fn to_bytes(&self) -> Vec<u8> {
+    ///
+    /// Fails with [`TlvError::BodyTooLong`] if the body, or that of one of
+    /// its sub-TLVs, doesn't fit the one-byte length field.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TlvError> {
+        let buf = self.encode()?;
+        if buf.len() > 2 + u8::MAX as usize {
+            return Err(TlvError::BodyTooLong {
+                tlv_type: buf[0],
+                len: buf.len() - 2,
+            });
+        }
+        Ok(buf)
+    }
+
+    /// Encode without checking the TLV's own length; the length byte is
+    /// truncated for bodies over 255 bytes.
+    fn encode(&self) -> Result<Vec<u8>, TlvError> {
         let mut buf = Vec::new();
         match self {
             Tlv::Pad1 => buf.push(0),
             Tlv::PadN { n } => {
                 buf.push(1);
-                buf.push(*n as u8);
+                buf.push(*n);
                 let mbz = vec![0; usize::from(*n)];
                 buf.extend(mbz);
             }
@@ -347,7 +961,7 @@ impl Tlv {
                 buf.write_u16::<BigEndian>(*opaque).unwrap();
                 buf.write_u16::<BigEndian>(*interval).unwrap();
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::Ack { opaque, sub_tlvs } => {
@@ -356,7 +970,7 @@ impl Tlv {
                 buf.push(body_len as u8);
                 buf.write_u16::<BigEndian>(*opaque).unwrap();
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::Hello {
@@ -372,7 +986,7 @@ impl Tlv {
                 buf.write_u16::<BigEndian>(*seqno).unwrap();
                 buf.write_u16::<BigEndian>(*interval).unwrap();
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::Ihu {
@@ -383,27 +997,21 @@ impl Tlv {
                 sub_tlvs,
             } => {
                 buf.push(5);
-                let addr_len = match addr {
-                    Some(IpAddr::V4(_)) => 4,
-                    Some(IpAddr::V6(_)) => 16,
-                    _ => 0,
-                };
-                let body_len =
-                    1 + 1 + 2 + 2 + addr_len + sub_tlvs.iter().map(|st| st.len()).sum::<usize>();
+                let addr_bytes = address_bytes(*ae, addr);
+                let body_len = 1
+                    + 1
+                    + 2
+                    + 2
+                    + addr_bytes.len()
+                    + sub_tlvs.iter().map(|st| st.len()).sum::<usize>();
                 buf.push(body_len as u8);
                 buf.push(*ae);
                 buf.push(0);
                 buf.write_u16::<BigEndian>(*rxcost).unwrap();
                 buf.write_u16::<BigEndian>(*interval).unwrap();
-                if let Some(a) = addr {
-                    match a {
-                        IpAddr::V4(v4) => buf.extend(&v4.octets()),
-                        IpAddr::V6(v6) => buf.extend(&v6.octets()),
-                        _ => {}
-                    }
-                }
+                buf.extend(addr_bytes);
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::RouterId {
@@ -416,29 +1024,20 @@ impl Tlv {
                 buf.extend(&[0, 0]);
                 buf.extend(router_id);
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::NextHop { ae, addr, sub_tlvs } => {
                 buf.push(7);
-                let addr_len = match addr {
-                    Some(IpAddr::V4(_)) => 4,
-                    Some(IpAddr::V6(_)) => 16,
-                    _ => 0,
-                };
-                let body_len = 1 + 1 + addr_len + sub_tlvs.iter().map(|st| st.len()).sum::<usize>();
+                let addr_bytes = address_bytes(*ae, addr);
+                let body_len =
+                    1 + 1 + addr_bytes.len() + sub_tlvs.iter().map(|st| st.len()).sum::<usize>();
                 buf.push(body_len as u8);
                 buf.push(*ae);
                 buf.push(0);
-                if let Some(a) = addr {
-                    match a {
-                        IpAddr::V4(v4) => buf.extend(&v4.octets()),
-                        IpAddr::V6(v6) => buf.extend(&v6.octets()),
-                        _ => {}
-                    }
-                }
+                buf.extend(addr_bytes);
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::Update {
@@ -453,6 +1052,7 @@ impl Tlv {
                 sub_tlvs,
             } => {
                 buf.push(8);
+                let prefix = update_prefix_wire(*ae, prefix);
                 let body_len = 1
                     + 1
                     + 1
@@ -472,7 +1072,7 @@ impl Tlv {
                 buf.write_u16::<BigEndian>(*metric).unwrap();
                 buf.extend(prefix);
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::RouteRequest {
@@ -489,7 +1089,7 @@ impl Tlv {
                 buf.push(*plen);
                 buf.extend(prefix);
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
             Tlv::SeqnoRequest {
@@ -519,28 +1119,43 @@ impl Tlv {
                 buf.extend(router_id);
                 buf.extend(prefix);
                 for st in sub_tlvs {
-                    buf.extend(st.to_bytes());
+                    buf.extend(st.to_bytes()?);
                 }
             }
+            Tlv::Pc { pc, index } => {
+                buf.push(16);
+                buf.push((4 + index.len()) as u8);
+                buf.write_u32::<BigEndian>(*pc).unwrap();
+                buf.extend(index);
+            }
+            Tlv::Hmac { hmac } => {
+                buf.push(17);
+                buf.push(hmac.len() as u8);
+                buf.extend(hmac);
+            }
             Tlv::Unknown { tlv_type, data } => {
                 buf.push(*tlv_type);
                 buf.push(data.len() as u8);
                 buf.extend(data);
             }
         }
-        buf
+        Ok(buf)
     }
 }
 
 impl SubTlv {
     /// Parse a sequence of sub-TLVs from a slice.
     /// Stops at end-of-buffer; errors on malformed fields.
-    pub fn parse_list(buf: &[u8]) -> Result<Vec<SubTlv>, String> {
+    ///
+    /// A sub-TLV whose length runs past the end of `buf` gives
+    /// [`TlvError::Truncated`] with the sub-TLV's type; [`Tlv::parse`]
+    /// reports it as the enclosing TLV's.
+    pub fn parse_list(buf: &[u8]) -> Result<Vec<SubTlv>, TlvError> {
         let mut out = Vec::new();
         let mut cur = Cursor::new(buf);
 
         while (cur.position() as usize) < buf.len() {
-            let stype = cur.read_u8().map_err(|e| e.to_string())?;
+            let stype = cur.read_u8()?;
 
             if stype == 0 {
                 // Pad1: single byte, no length
@@ -548,15 +1163,37 @@ impl SubTlv {
                 continue;
             }
 
-            let slen = cur.read_u8().map_err(|e| e.to_string())? as usize;
-            let mut data = vec![0u8; slen];
-            cur.read_exact(&mut data).map_err(|e| e.to_string())?;
+            let overrun = || TlvError::Truncated { tlv_type: stype };
+            let slen = cur.read_u8().map_err(|_| overrun())? as usize;
+            let pos = cur.position() as usize;
+            if slen > buf.len() - pos {
+                return Err(overrun());
+            }
+            let data = buf[pos..pos + slen].to_vec();
+            cur.set_position((pos + slen) as u64);
 
             let s = match stype {
                 1 => {
                     // PadN sub-TLV: content is MBZ, we only keep the count
                     SubTlv::PadN { n: slen as u8 }
                 }
+                SUBTLV_TIMESTAMP if data.len() == 4 || data.len() == 8 => {
+                    let word = |i: usize| {
+                        u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+                    };
+                    SubTlv::Timestamp {
+                        origin: word(0),
+                        receive: (data.len() == 8).then(|| word(4)),
+                    }
+                }
+                SUBTLV_SOURCE_PREFIX
+                    if !data.is_empty() && data.len() == 1 + (data[0] as usize).div_ceil(8) =>
+                {
+                    SubTlv::SourcePrefix {
+                        plen: data[0],
+                        source_prefix: data[1..].to_vec(),
+                    }
+                }
                 other => SubTlv::Unknown { stype: other, data },
             };
 
@@ -567,58 +1204,338 @@ impl SubTlv {
     }
 
     /// Compute the full wire length of this sub-TLV (including header).
-    fn len(&self) -> usize {
+    // A sub-TLV is never zero bytes long, so there's no `is_empty`.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
         match self {
             SubTlv::Pad1 => 1,
             SubTlv::PadN { n } => 2 + (*n as usize),
+            SubTlv::Timestamp { receive, .. } => 2 + if receive.is_some() { 8 } else { 4 },
+            SubTlv::SourcePrefix { source_prefix, .. } => 3 + source_prefix.len(),
             SubTlv::Unknown { data, .. } => 2 + data.len(),
         }
     }
 
     /// Serialize this sub-TLV into wire-format bytes.
-    pub fn to_bytes(&self) -> Vec<u8> {
+    ///
+    /// Fails with [`TlvError::BodyTooLong`] if the body doesn't fit the
+    /// one-byte length field.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TlvError> {
+        let body_len = |stype: u8, len: usize| {
+            u8::try_from(len).map_err(|_| TlvError::BodyTooLong {
+                tlv_type: stype,
+                len,
+            })
+        };
+
         let mut buf = Vec::new();
         match self {
             SubTlv::Pad1 => buf.push(0),
             SubTlv::PadN { n } => {
                 buf.push(1);
-                buf.push(*n as u8);
+                buf.push(*n);
                 let mbz = vec![0; usize::from(*n)];
                 buf.extend(mbz);
             }
+            SubTlv::Timestamp { origin, receive } => {
+                buf.push(SUBTLV_TIMESTAMP);
+                buf.push(if receive.is_some() { 8 } else { 4 });
+                buf.extend(origin.to_be_bytes());
+                if let Some(receive) = receive {
+                    buf.extend(receive.to_be_bytes());
+                }
+            }
+            SubTlv::SourcePrefix {
+                plen,
+                source_prefix,
+            } => {
+                buf.push(SUBTLV_SOURCE_PREFIX);
+                buf.push(body_len(SUBTLV_SOURCE_PREFIX, 1 + source_prefix.len())?);
+                buf.push(*plen);
+                buf.extend(source_prefix);
+            }
             SubTlv::Unknown { stype, data } => {
                 buf.push(*stype);
-                buf.push(data.len() as u8);
+                buf.push(body_len(*stype, data.len())?);
                 buf.extend(data);
             }
         }
-        buf
+        Ok(buf)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
+/// A TLV borrowed from a received buffer, parsed just far enough to be
+/// framed and checked.
+///
+/// The common fields (AE, seqno, metric, prefix) can be read without
+/// allocating; [`to_owned`](Self::to_owned) decodes the full [`Tlv`] when
+/// it's worth keeping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlvRef<'a> {
+    tlv_type: u8,
+    body: &'a [u8],
+}
 
-    // --- Basic TLVs ---
+/// Frame all TLVs in `buf` without copying them.
+///
+/// Unlike [`Tlv::parse_all`] this is strict: any TLV that is cut off or
+/// shorter than its fixed fields (address and prefix included) fails the
+/// whole buffer. Sub-TLVs are only checked by [`TlvRef::to_owned`].
+pub fn parse_all_borrowed(buf: &[u8]) -> Result<Vec<TlvRef<'_>>, TlvError> {
+    let mut out = Vec::new();
+    let mut pos = 0;
 
-    #[test]
-    fn pad1_to_bytes() {
-        let pad1 = Tlv::Pad1;
-        assert_eq!(pad1.to_bytes(), vec![0]);
+    while pos < buf.len() {
+        let tlv_type = buf[pos];
+        if tlv_type == 0 {
+            out.push(TlvRef {
+                tlv_type,
+                body: &[],
+            });
+            pos += 1;
+            continue;
+        }
+
+        let length = *buf.get(pos + 1).ok_or(TlvError::Truncated { tlv_type })? as usize;
+        let body = buf
+            .get(pos + 2..pos + 2 + length)
+            .ok_or(TlvError::LengthExceedsBuffer)?;
+        let tlv = TlvRef { tlv_type, body };
+        if length < min_body_len(tlv_type) {
+            return Err(TlvError::Truncated { tlv_type });
+        }
+        tlv.check_prefix()?;
+        if length < tlv.fixed_len() {
+            return Err(TlvError::Truncated { tlv_type });
+        }
+
+        out.push(tlv);
+        pos += 2 + length;
+    }
+
+    Ok(out)
+}
+
+impl<'a> TlvRef<'a> {
+    /// The TLV type byte.
+    pub fn tlv_type(&self) -> u8 {
+        self.tlv_type
+    }
+
+    /// The raw TLV body, without type and length.
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// AE of an IHU, NextHop, Update, RouteRequest or SeqnoRequest.
+    pub fn ae(&self) -> Option<u8> {
+        match self.tlv_type {
+            5 | 7..=10 => Some(self.body[0]),
+            _ => None,
+        }
+    }
+
+    /// Prefix length of an Update, RouteRequest or SeqnoRequest.
+    pub fn plen(&self) -> Option<u8> {
+        match self.tlv_type {
+            8 => Some(self.body[2]),
+            9 | 10 => Some(self.body[1]),
+            _ => None,
+        }
+    }
+
+    /// Seqno of a Hello, Update or SeqnoRequest.
+    pub fn seqno(&self) -> Option<u16> {
+        match self.tlv_type {
+            4 | 10 => Some(self.u16_at(2)),
+            8 => Some(self.u16_at(6)),
+            _ => None,
+        }
+    }
+
+    /// Metric of an Update.
+    pub fn metric(&self) -> Option<u16> {
+        (self.tlv_type == 8).then(|| self.u16_at(8))
+    }
+
+    /// Prefix bytes of an Update, RouteRequest or SeqnoRequest as they
+    /// appear on the wire (still compressed, for an Update).
+    pub fn prefix(&self) -> Option<&'a [u8]> {
+        let start = match self.tlv_type {
+            8 => 10,
+            9 => 2,
+            10 => 14,
+            _ => return None,
+        };
+        Some(&self.body[start..self.fixed_len()])
+    }
+
+    /// The undecoded sub-TLV area that follows the fixed fields.
+    pub fn sub_tlv_bytes(&self) -> &'a [u8] {
+        match self.tlv_type {
+            2..=10 => &self.body[self.fixed_len()..],
+            _ => &[],
+        }
+    }
+
+    /// Decode into an owned [`Tlv`].
+    ///
+    /// The fixed fields were checked when the TLV was framed, so they are
+    /// copied out directly; only the sub-TLVs are parsed here.
+    pub fn to_owned(&self) -> Result<Tlv, TlvError> {
+        let t = self.tlv_type;
+        let b = self.body;
+        let sub_tlvs = || parse_sub_tlvs(t, self.sub_tlv_bytes());
+        let address = |at: usize| read_address(&mut &b[at..], b[0]).map_err(truncated(t));
+        let router_id = |at: usize| {
+            let mut id = [0; 8];
+            id.copy_from_slice(&b[at..at + 8]);
+            id
+        };
+
+        let tlv = match t {
+            0 => Tlv::Pad1,
+            1 => Tlv::PadN { n: b.len() as u8 },
+            2 => Tlv::AckRequest {
+                opaque: self.u16_at(2),
+                interval: self.u16_at(4),
+                sub_tlvs: sub_tlvs()?,
+            },
+            3 => Tlv::Ack {
+                opaque: self.u16_at(0),
+                sub_tlvs: sub_tlvs()?,
+            },
+            4 => Tlv::Hello {
+                flags: self.u16_at(0),
+                seqno: self.u16_at(2),
+                interval: self.u16_at(4),
+                sub_tlvs: sub_tlvs()?,
+            },
+            5 => Tlv::Ihu {
+                ae: b[0],
+                rxcost: self.u16_at(2),
+                interval: self.u16_at(4),
+                addr: address(6)?,
+                sub_tlvs: sub_tlvs()?,
+            },
+            6 => Tlv::RouterId {
+                router_id: router_id(2),
+                sub_tlvs: sub_tlvs()?,
+            },
+            7 => Tlv::NextHop {
+                ae: b[0],
+                addr: address(2)?,
+                sub_tlvs: sub_tlvs()?,
+            },
+            8 => {
+                let (ae, plen) = (b[0], b[2]);
+                let wire = &b[10..self.fixed_len()];
+                let mut prefix = Vec::with_capacity((plen as usize).div_ceil(8));
+                if ae == 3 {
+                    // The implied fe80::/64 half, as in `Tlv::parse`.
+                    prefix.extend_from_slice(
+                        &LINK_LOCAL_PREFIX[..(plen as usize).div_ceil(8).min(8)],
+                    );
+                }
+                prefix.extend_from_slice(wire);
+                Tlv::Update {
+                    ae,
+                    flags: b[1],
+                    plen,
+                    omitted: b[3],
+                    interval: self.u16_at(4),
+                    seqno: self.u16_at(6),
+                    metric: self.u16_at(8),
+                    prefix,
+                    sub_tlvs: sub_tlvs()?,
+                }
+            }
+            9 => Tlv::RouteRequest {
+                ae: b[0],
+                plen: b[1],
+                prefix: b[2..self.fixed_len()].to_vec(),
+                sub_tlvs: sub_tlvs()?,
+            },
+            10 => Tlv::SeqnoRequest {
+                ae: b[0],
+                plen: b[1],
+                seqno: self.u16_at(2),
+                hop_count: b[4],
+                router_id: router_id(6),
+                prefix: b[14..self.fixed_len()].to_vec(),
+                sub_tlvs: sub_tlvs()?,
+            },
+            16 => Tlv::Pc {
+                pc: u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+                index: b[4..].to_vec(),
+            },
+            17 => Tlv::Hmac { hmac: b.to_vec() },
+            other => Tlv::Unknown {
+                tlv_type: other,
+                data: b.to_vec(),
+            },
+        };
+        Ok(tlv)
+    }
+
+    /// Validate `plen` and, for an Update, `omitted`, as [`Tlv::parse`] does.
+    fn check_prefix(&self) -> Result<(), TlvError> {
+        let (Some(ae), Some(plen)) = (self.ae(), self.plen()) else {
+            return Ok(());
+        };
+        check_plen(self.tlv_type, ae, plen)?;
+        if self.tlv_type == 8 && self.body[3] as usize > (plen as usize).div_ceil(8) {
+            return Err(TlvError::InvalidPrefix {
+                tlv_type: self.tlv_type,
+            });
+        }
+        Ok(())
+    }
+
+    /// Offset of the end of the fixed fields, address and prefix included.
+    ///
+    /// Only meaningful once the body is known to hold the fields the
+    /// lengths are read from, i.e. `min_body_len`.
+    fn fixed_len(&self) -> usize {
+        let b = self.body;
+        match self.tlv_type {
+            5 => 6 + ae_address_len(b[0]),
+            7 => 2 + ae_address_len(b[0]),
+            8 => 10 + update_prefix_wire_len(b[0], b[2], b[3]),
+            9 => 2 + (b[1] as usize).div_ceil(8),
+            10 => 14 + (b[1] as usize).div_ceil(8),
+            t => min_body_len(t),
+        }
+    }
+
+    fn u16_at(&self, i: usize) -> u16 {
+        u16::from_be_bytes([self.body[i], self.body[i + 1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // --- Basic TLVs ---
+
+    #[test]
+    fn pad1_to_bytes() {
+        let pad1 = Tlv::Pad1;
+        assert_eq!(pad1.to_bytes().unwrap(), vec![0]);
     }
 
     #[test]
     fn padn_to_bytes() {
         let pad4 = Tlv::PadN { n: 4 };
-        assert_eq!(pad4.to_bytes(), vec![1, 4, 0, 0, 0, 0]);
+        assert_eq!(pad4.to_bytes().unwrap(), vec![1, 4, 0, 0, 0, 0]);
     }
 
     #[test]
     fn padn_roundtrip() {
         let original = Tlv::PadN { n: 3 };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -631,7 +1548,7 @@ mod tests {
             interval: 400,
             sub_tlvs: Vec::new(),
         };
-        assert_eq!(ackreq.to_bytes(), vec![2, 6, 0, 0, 1, 22, 1, 144]);
+        assert_eq!(ackreq.to_bytes().unwrap(), vec![2, 6, 0, 0, 1, 22, 1, 144]);
     }
 
     #[test]
@@ -640,7 +1557,7 @@ mod tests {
             opaque: 278,
             sub_tlvs: Vec::new(),
         };
-        assert_eq!(ack.to_bytes(), vec![3, 2, 1, 22]);
+        assert_eq!(ack.to_bytes().unwrap(), vec![3, 2, 1, 22]);
     }
 
     #[test]
@@ -651,7 +1568,7 @@ mod tests {
             interval: 400,
             sub_tlvs: Vec::new(),
         };
-        assert_eq!(hello.to_bytes(), vec![4, 6, 0, 0, 1, 22, 1, 144]);
+        assert_eq!(hello.to_bytes().unwrap(), vec![4, 6, 0, 0, 1, 22, 1, 144]);
     }
 
     #[test]
@@ -662,7 +1579,7 @@ mod tests {
             interval: 1000,
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -679,7 +1596,7 @@ mod tests {
             addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -694,7 +1611,7 @@ mod tests {
             addr: Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -707,19 +1624,227 @@ mod tests {
             addr: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
     }
 
+    #[test]
+    fn ihu_link_local_uses_eight_bytes() {
+        let original = Tlv::Ihu {
+            ae: 3,
+            rxcost: 96,
+            interval: 400,
+            addr: Some(IpAddr::V6("fe80::1:2:3:4".parse().unwrap())),
+            sub_tlvs: vec![SubTlv::PadN { n: 2 }],
+        };
+        let bytes = original.to_bytes().unwrap();
+        // type, length, AE, reserved, rxcost, interval, 8 address bytes, PadN(2)
+        assert_eq!(bytes.len(), 2 + 6 + 8 + 4);
+        assert_eq!(bytes[1] as usize, bytes.len() - 2);
+
+        let mut cur = Cursor::new(bytes.as_slice());
+        let parsed = Tlv::parse(&mut cur).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn nexthop_link_local_roundtrip() {
+        let original = Tlv::NextHop {
+            ae: 3,
+            addr: Some(IpAddr::V6("fe80::aa:bb".parse().unwrap())),
+            sub_tlvs: Vec::new(),
+        };
+        let bytes = original.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 2 + 2 + 8);
+
+        let mut cur = Cursor::new(bytes.as_slice());
+        let parsed = Tlv::parse(&mut cur).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn update_link_local_roundtrip() {
+        let original = Tlv::Update {
+            ae: 3,
+            flags: 0,
+            plen: 128,
+            omitted: 0,
+            interval: 400,
+            seqno: 7,
+            metric: 0,
+            prefix: vec![0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xaa, 0xbb],
+            sub_tlvs: Vec::new(),
+        };
+        let bytes = original.to_bytes().unwrap();
+        // type, length, 10 fixed bytes, 8 interface-identifier bytes
+        assert_eq!(bytes.len(), 2 + 10 + 8);
+        assert_eq!(&bytes[12..], &[0, 0, 0, 0, 0, 0, 0xaa, 0xbb]);
+
+        let mut cur = Cursor::new(bytes.as_slice());
+        let parsed = Tlv::parse(&mut cur).unwrap();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn oversized_update_is_rejected() {
+        let tlv = Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen: 24,
+            omitted: 0,
+            interval: 400,
+            seqno: 1,
+            metric: 96,
+            prefix: vec![10, 0, 1],
+            sub_tlvs: vec![
+                SubTlv::Unknown {
+                    stype: 200,
+                    data: vec![0; 150],
+                };
+                2
+            ],
+        };
+        assert!(matches!(
+            tlv.to_bytes(),
+            Err(TlvError::BodyTooLong { tlv_type: 8, .. })
+        ));
+        assert!(matches!(
+            crate::packet::Packet::with_tlvs(vec![tlv]).to_bytes(),
+            Err(TlvError::BodyTooLong { tlv_type: 8, .. })
+        ));
+
+        // A sub-TLV too long for its own length byte fails on its own.
+        let sub = SubTlv::Unknown {
+            stype: 200,
+            data: vec![0; 300],
+        };
+        assert!(matches!(
+            sub.to_bytes(),
+            Err(TlvError::BodyTooLong { tlv_type: 200, .. })
+        ));
+        let hello = Tlv::Hello {
+            flags: 0,
+            seqno: 1,
+            interval: 400,
+            sub_tlvs: vec![sub],
+        };
+        assert!(matches!(
+            hello.to_bytes(),
+            Err(TlvError::BodyTooLong { tlv_type: 200, .. })
+        ));
+
+        // The plen byte counts too: a 255-byte source prefix is one over.
+        let source = |len: usize| SubTlv::SourcePrefix {
+            plen: 0,
+            source_prefix: vec![0; len],
+        };
+        assert_eq!(source(254).to_bytes().unwrap().len(), 257);
+        assert!(matches!(
+            source(255).to_bytes(),
+            Err(TlvError::BodyTooLong {
+                tlv_type: SUBTLV_SOURCE_PREFIX,
+                len: 256
+            })
+        ));
+
+        let small = Tlv::PadN { n: 4 };
+        assert_eq!(small.to_bytes().unwrap(), vec![1, 4, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn update_with_source_prefix_roundtrip() {
+        let source = vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 1];
+        let original = Tlv::Update {
+            ae: 2,
+            flags: 0,
+            plen: 48,
+            omitted: 0,
+            interval: 400,
+            seqno: 3,
+            metric: 96,
+            prefix: vec![0x20, 0x01, 0x0d, 0xb8, 0, 2],
+            sub_tlvs: vec![SubTlv::SourcePrefix {
+                plen: 64,
+                source_prefix: source.clone(),
+            }],
+        };
+        let bytes = original.to_bytes().unwrap();
+        assert_eq!(&bytes[bytes.len() - 11..bytes.len() - 8], &[128, 9, 64]);
+        assert_eq!(&bytes[bytes.len() - 8..], &source[..]);
+
+        let mut cur = Cursor::new(bytes.as_slice());
+        let parsed = Tlv::parse(&mut cur).unwrap();
+        assert_eq!(parsed, original);
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+        assert_eq!(parsed.source_prefix(), Some((64, &source[..])));
+    }
+
+    #[test]
+    fn pc_and_hmac_roundtrip() {
+        for (original, tlv_type, len) in [
+            (
+                Tlv::Pc {
+                    pc: 0x0102_0304,
+                    index: vec![7, 7, 7, 7],
+                },
+                16,
+                8,
+            ),
+            (
+                Tlv::Hmac {
+                    hmac: vec![0xab; 32],
+                },
+                17,
+                32,
+            ),
+        ] {
+            let bytes = original.to_bytes().unwrap();
+            assert_eq!(&bytes[..2], &[tlv_type, len]);
+            let mut cur = Cursor::new(bytes.as_slice());
+            assert_eq!(Tlv::parse(&mut cur).unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn timestamp_subtlv_roundtrip() {
+        let hello = Tlv::Hello {
+            flags: 0,
+            seqno: 1,
+            interval: 400,
+            sub_tlvs: vec![SubTlv::Timestamp {
+                origin: 1_000_000,
+                receive: None,
+            }],
+        };
+        let ihu = Tlv::Ihu {
+            ae: 1,
+            rxcost: 96,
+            interval: 400,
+            addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            sub_tlvs: vec![SubTlv::Timestamp {
+                origin: 1_000_000,
+                receive: Some(2_000_500),
+            }],
+        };
+
+        for original in [hello, ihu] {
+            let bytes = original.to_bytes().unwrap();
+            let mut cur = Cursor::new(bytes.as_slice());
+            let parsed = Tlv::parse(&mut cur).unwrap();
+            assert_eq!(parsed, original);
+            assert_eq!(parsed.timestamp(), original.timestamp());
+        }
+    }
+
     #[test]
     fn router_id_roundtrip() {
         let original = Tlv::RouterId {
             router_id: [1, 2, 3, 4, 5, 6, 7, 8],
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -741,7 +1866,7 @@ mod tests {
             prefix: vec![192, 0, 2],
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -755,7 +1880,7 @@ mod tests {
             prefix: vec![10, 0],
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -772,7 +1897,7 @@ mod tests {
             prefix: vec![192, 0, 2],
             sub_tlvs: Vec::new(),
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -786,7 +1911,7 @@ mod tests {
             tlv_type: 250,
             data: vec![1, 2, 3, 4],
         };
-        let bytes = original.to_bytes();
+        let bytes = original.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, original);
@@ -797,7 +1922,7 @@ mod tests {
     #[test]
     fn subtlv_pad1_to_bytes_and_parse() {
         let st = SubTlv::Pad1;
-        let bytes = st.to_bytes();
+        let bytes = st.to_bytes().unwrap();
         assert_eq!(bytes, vec![0]);
 
         let parsed = SubTlv::parse_list(&bytes).unwrap();
@@ -807,7 +1932,7 @@ mod tests {
     #[test]
     fn subtlv_padn_to_bytes_and_parse() {
         let st = SubTlv::PadN { n: 3 };
-        let bytes = st.to_bytes();
+        let bytes = st.to_bytes().unwrap();
         // type=1, len=3, then 3 MBZ bytes
         assert_eq!(bytes, vec![1, 3, 0, 0, 0]);
 
@@ -821,7 +1946,7 @@ mod tests {
             stype: 99,
             data: vec![0xaa, 0xbb],
         };
-        let bytes = st.to_bytes();
+        let bytes = st.to_bytes().unwrap();
         let parsed = SubTlv::parse_list(&bytes).unwrap();
         assert_eq!(parsed, vec![st]);
     }
@@ -842,14 +1967,216 @@ mod tests {
             ],
         };
 
-        let bytes = hello.to_bytes();
+        let bytes = hello.to_bytes().unwrap();
         let mut cur = Cursor::new(bytes.as_slice());
         let parsed = Tlv::parse(&mut cur).unwrap();
         assert_eq!(parsed, hello);
     }
 
+    // --- Prefix compression ---
+
+    #[test]
+    fn default_prefixes_share_leading_bytes() {
+        let mut dp = DefaultPrefixes::new();
+        assert_eq!(
+            dp.compress(1, &[10, 0, 1]),
+            (UPDATE_FLAG_DEFAULT_PREFIX, 0, vec![10, 0, 1])
+        );
+        assert_eq!(
+            dp.compress(1, &[10, 0, 2]),
+            (UPDATE_FLAG_DEFAULT_PREFIX, 2, vec![2])
+        );
+        // Other AEs have independent state.
+        assert_eq!(
+            dp.compress(2, &[0x20, 0x01]),
+            (UPDATE_FLAG_DEFAULT_PREFIX, 0, vec![0x20, 0x01])
+        );
+    }
+
+    #[test]
+    fn default_prefixes_reject_omitted_without_default() {
+        let mut dp = DefaultPrefixes::new();
+        assert!(dp.expand(1, 0, 2, &[1]).is_err());
+        assert!(dp.expand(0, 0, 1, &[]).is_err());
+    }
+
+    #[test]
+    fn parse_reports_error_kinds() {
+        let parse = |bytes: &[u8]| Tlv::parse(&mut Cursor::new(bytes));
+
+        assert!(matches!(parse(&[]), Err(TlvError::Eof)));
+        assert!(matches!(
+            parse(&[8, 5, 1, 0]),
+            Err(TlvError::LengthExceedsBuffer)
+        ));
+        // An Update body needs at least 10 bytes.
+        assert!(matches!(
+            parse(&[8, 3, 1, 0, 24]),
+            Err(TlvError::Truncated { tlv_type: 8 })
+        ));
+    }
+
+    #[test]
+    fn short_bodies_are_truncated() {
+        let parse = |bytes: &[u8]| Tlv::parse(&mut Cursor::new(bytes));
+
+        // A 2-byte Update body, followed by bytes that could pass for sub-TLVs.
+        let mut cur = Cursor::new(&[8u8, 2, 1, 0, 1, 0][..]);
+        assert!(matches!(
+            Tlv::parse(&mut cur),
+            Err(TlvError::Truncated { tlv_type: 8 })
+        ));
+        assert_eq!(cur.position(), 4);
+
+        // A 1-byte IHU body.
+        assert!(matches!(
+            parse(&[5, 1, 1]),
+            Err(TlvError::Truncated { tlv_type: 5 })
+        ));
+    }
+
+    #[test]
+    fn update_plen_must_fit_ae() {
+        let update = |ae: u8, plen: u8, omitted: u8| {
+            let mut buf = vec![8, 42, ae, 0, plen, omitted, 0, 0, 0, 1, 0, 0];
+            buf.resize(44, 0);
+            buf
+        };
+        let parse = |buf: &[u8]| Tlv::parse(&mut Cursor::new(buf));
+
+        // A v4 /255 would need 32 prefix bytes, which are all there.
+        let buf = update(1, 255, 0);
+        assert!(matches!(
+            parse(&buf),
+            Err(TlvError::InvalidPrefix { tlv_type: 8 })
+        ));
+        assert!(matches!(
+            parse_all_borrowed(&buf),
+            Err(TlvError::InvalidPrefix { tlv_type: 8 })
+        ));
+        assert!(matches!(
+            parse(&update(2, 129, 0)),
+            Err(TlvError::InvalidPrefix { tlv_type: 8 })
+        ));
+        assert!(matches!(
+            parse(&update(0, 8, 0)),
+            Err(TlvError::InvalidPrefix { tlv_type: 8 })
+        ));
+
+        // Widest valid prefixes are fine.
+        assert!(parse(&update(1, 32, 0)).is_ok());
+        assert!(parse(&update(2, 128, 0)).is_ok());
+    }
+
+    #[test]
+    fn update_omitted_must_fit_prefix() {
+        // A /24 has 3 prefix bytes; 10 can't have been omitted.
+        let buf = [8, 10, 1, 0, 24, 10, 0, 0, 0, 1, 0, 0];
+        assert!(matches!(
+            Tlv::parse(&mut Cursor::new(&buf[..])),
+            Err(TlvError::InvalidPrefix { tlv_type: 8 })
+        ));
+        assert!(matches!(
+            parse_all_borrowed(&buf),
+            Err(TlvError::InvalidPrefix { tlv_type: 8 })
+        ));
+
+        // Omitting the whole prefix is allowed.
+        let buf = [8, 10, 1, 0, 24, 3, 0, 0, 0, 1, 0, 0];
+        assert!(Tlv::parse(&mut Cursor::new(&buf[..])).is_ok());
+    }
+
+    #[test]
+    fn request_plen_must_fit_ae() {
+        let route_request = [9, 35, 1, 255];
+        let mut buf = route_request.to_vec();
+        buf.resize(37, 0);
+        assert!(matches!(
+            Tlv::parse(&mut Cursor::new(&buf[..])),
+            Err(TlvError::InvalidPrefix { tlv_type: 9 })
+        ));
+    }
+
     // --- parse_all ---
 
+    #[test]
+    fn overrunning_sub_tlv_skips_only_its_tlv() {
+        let update = Tlv::Update {
+            ae: 1,
+            flags: 0,
+            plen: 24,
+            omitted: 0,
+            interval: 400,
+            seqno: 1,
+            metric: 96,
+            prefix: vec![10, 0, 1],
+            sub_tlvs: Vec::new(),
+        };
+        let hello = Tlv::Hello {
+            flags: 0,
+            seqno: 1,
+            interval: 400,
+            sub_tlvs: Vec::new(),
+        };
+        // The Update ends in a sub-TLV of type 2 claiming 9 bytes of which
+        // there are 2.
+        let mut bad = update.to_bytes().unwrap();
+        bad.extend([2, 9, 0, 0]);
+        bad[1] += 4;
+
+        assert!(matches!(
+            SubTlv::parse_list(&[2, 9, 0, 0]),
+            Err(TlvError::Truncated { tlv_type: 2 })
+        ));
+        assert!(matches!(
+            Tlv::parse(&mut Cursor::new(&bad[..])),
+            Err(TlvError::Truncated { tlv_type: 8 })
+        ));
+
+        let mut buf = hello.to_bytes().unwrap();
+        buf.extend(&bad);
+        buf.extend(update.to_bytes().unwrap());
+        assert_eq!(
+            Tlv::parse_all(&buf).unwrap(),
+            vec![hello.clone(), update.clone()]
+        );
+        assert!(Tlv::parse_all_strict(&buf).is_err());
+
+        // Bad TLVs in a row are all skipped; an overrunning length ends
+        // the parse.
+        let mut wide = update.to_bytes().unwrap();
+        wide[4] = 33;
+        let mut buf = bad.clone();
+        buf.extend(&wide);
+        buf.extend(&bad);
+        buf.extend(hello.to_bytes().unwrap());
+        buf.extend([4, 200, 0]);
+        assert_eq!(Tlv::parse_all(&buf).unwrap(), vec![hello]);
+    }
+
+    #[test]
+    fn parse_all_strict_rejects_truncated_tail() {
+        let hello = Tlv::Hello {
+            flags: 0,
+            seqno: 1,
+            interval: 400,
+            sub_tlvs: Vec::new(),
+        };
+        let mut buf = hello.to_bytes().unwrap();
+        // An Update cut off after its AE and flags.
+        buf.extend([8, 2, 1, 0]);
+
+        assert_eq!(Tlv::parse_all(&buf).unwrap(), vec![hello.clone()]);
+        assert!(matches!(
+            Tlv::parse_all_strict(&buf),
+            Err(TlvError::Truncated { tlv_type: 8 })
+        ));
+        assert_eq!(
+            Tlv::parse_all_strict(&hello.to_bytes().unwrap()).unwrap(),
+            vec![hello]
+        );
+    }
+
     #[test]
     fn parse_all_multiple_tlvs() {
         let t1 = Tlv::Pad1;
@@ -860,11 +2187,310 @@ mod tests {
         };
 
         let mut buf = Vec::new();
-        buf.extend(t1.to_bytes());
-        buf.extend(t2.to_bytes());
-        buf.extend(t3.to_bytes());
+        buf.extend(t1.to_bytes().unwrap());
+        buf.extend(t2.to_bytes().unwrap());
+        buf.extend(t3.to_bytes().unwrap());
 
         let parsed = Tlv::parse_all(&buf).unwrap();
         assert_eq!(parsed, vec![t1, t2, t3]);
     }
+
+    #[test]
+    fn wire_len_matches_encoding() {
+        let subs = vec![
+            SubTlv::Pad1,
+            SubTlv::PadN { n: 3 },
+            SubTlv::Timestamp {
+                origin: 1,
+                receive: Some(2),
+            },
+        ];
+        let tlvs = vec![
+            Tlv::Pad1,
+            Tlv::PadN { n: 4 },
+            Tlv::AckRequest {
+                opaque: 1,
+                interval: 2,
+                sub_tlvs: subs.clone(),
+            },
+            Tlv::Ack {
+                opaque: 1,
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Hello {
+                flags: 0,
+                seqno: 1,
+                interval: 400,
+                sub_tlvs: subs.clone(),
+            },
+            Tlv::Ihu {
+                ae: 1,
+                rxcost: 96,
+                interval: 400,
+                addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Ihu {
+                ae: 3,
+                rxcost: 96,
+                interval: 400,
+                addr: Some(IpAddr::V6("fe80::1".parse().unwrap())),
+                sub_tlvs: subs.clone(),
+            },
+            Tlv::RouterId {
+                router_id: [1; 8],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::NextHop {
+                ae: 2,
+                addr: Some(IpAddr::V6("2001:db8::1".parse().unwrap())),
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: 1,
+                flags: 0,
+                plen: 24,
+                omitted: 1,
+                interval: 400,
+                seqno: 1,
+                metric: 96,
+                prefix: vec![0, 1],
+                sub_tlvs: vec![SubTlv::SourcePrefix {
+                    plen: 16,
+                    source_prefix: vec![10, 1],
+                }],
+            },
+            Tlv::Update {
+                ae: 3,
+                flags: 0,
+                plen: 128,
+                omitted: 0,
+                interval: 400,
+                seqno: 1,
+                metric: 96,
+                prefix: vec![0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::RouteRequest {
+                ae: 1,
+                plen: 16,
+                prefix: vec![10, 0],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::SeqnoRequest {
+                ae: 1,
+                plen: 24,
+                seqno: 3,
+                hop_count: 64,
+                router_id: [2; 8],
+                prefix: vec![10, 0, 1],
+                sub_tlvs: subs.clone(),
+            },
+            Tlv::Pc {
+                pc: 7,
+                index: vec![1, 2, 3],
+            },
+            Tlv::Hmac { hmac: vec![0; 32] },
+            Tlv::Unknown {
+                tlv_type: 200,
+                data: vec![1, 2, 3],
+            },
+        ];
+
+        for tlv in &tlvs {
+            assert_eq!(tlv.wire_len(), tlv.to_bytes().unwrap().len(), "{tlv:?}");
+        }
+        for st in &subs {
+            assert_eq!(st.len(), st.to_bytes().unwrap().len(), "{st:?}");
+        }
+    }
+
+    #[test]
+    fn borrowed_parse_matches_owned() {
+        let tlvs = vec![
+            Tlv::Pad1,
+            Tlv::Hello {
+                flags: 0,
+                seqno: 9,
+                interval: 400,
+                sub_tlvs: vec![SubTlv::Timestamp {
+                    origin: 1,
+                    receive: None,
+                }],
+            },
+            Tlv::Ihu {
+                ae: 3,
+                rxcost: 96,
+                interval: 400,
+                addr: Some(IpAddr::V6("fe80::1".parse().unwrap())),
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: 1,
+                flags: 0,
+                plen: 24,
+                omitted: 1,
+                interval: 400,
+                seqno: 5,
+                metric: 256,
+                prefix: vec![0, 1],
+                sub_tlvs: vec![SubTlv::PadN { n: 2 }],
+            },
+            Tlv::Pc {
+                pc: 3,
+                index: vec![1],
+            },
+            Tlv::PadN { n: 3 },
+            Tlv::AckRequest {
+                opaque: 7,
+                interval: 100,
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Ack {
+                opaque: 7,
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::RouterId {
+                router_id: [1, 2, 3, 4, 5, 6, 7, 8],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::NextHop {
+                ae: 1,
+                addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::Update {
+                ae: 3,
+                flags: 0,
+                plen: 128,
+                omitted: 0,
+                interval: 400,
+                seqno: 5,
+                metric: 96,
+                prefix: "fe80::1".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::RouteRequest {
+                ae: 2,
+                plen: 32,
+                prefix: vec![0x20, 0x01, 0x0d, 0xb8],
+                sub_tlvs: Vec::new(),
+            },
+            Tlv::SeqnoRequest {
+                ae: 1,
+                plen: 24,
+                seqno: 6,
+                hop_count: 64,
+                router_id: [2; 8],
+                prefix: vec![10, 0, 1],
+                sub_tlvs: vec![SubTlv::PadN { n: 1 }],
+            },
+            Tlv::Hmac { hmac: vec![9; 32] },
+            Tlv::Unknown {
+                tlv_type: 200,
+                data: vec![1, 2, 3],
+            },
+        ];
+        let buf: Vec<u8> = tlvs.iter().flat_map(|t| t.to_bytes().unwrap()).collect();
+
+        let borrowed = parse_all_borrowed(&buf).unwrap();
+        assert_eq!(borrowed.len(), tlvs.len());
+
+        let update = &borrowed[3];
+        assert_eq!(update.tlv_type(), 8);
+        assert_eq!(update.ae(), Some(1));
+        assert_eq!(update.plen(), Some(24));
+        assert_eq!(update.seqno(), Some(5));
+        assert_eq!(update.metric(), Some(256));
+        assert_eq!(update.prefix(), Some(&[0u8, 1][..]));
+        assert_eq!(update.sub_tlv_bytes(), &[1, 2, 0, 0]);
+        assert_eq!(borrowed[1].seqno(), Some(9));
+        assert_eq!(borrowed[2].ae(), Some(3));
+        assert_eq!(borrowed[4].ae(), None);
+
+        let owned: Vec<Tlv> = borrowed.iter().map(|t| t.to_owned().unwrap()).collect();
+        assert_eq!(owned, tlvs);
+        let parsed = Tlv::parse_all_strict(&buf).unwrap();
+        assert_eq!(owned, parsed);
+
+        // A bad sub-TLV area fails as in the owned parser.
+        let bad_sub = [4, 8, 0, 0, 0, 9, 1, 144, 5, 9];
+        let borrowed = parse_all_borrowed(&bad_sub).unwrap();
+        assert!(matches!(
+            borrowed[0].to_owned(),
+            Err(TlvError::Truncated { tlv_type: 4 })
+        ));
+        assert!(Tlv::parse(&mut Cursor::new(&bad_sub[..])).is_err());
+    }
+
+    #[test]
+    fn borrowed_parse_rejects_short_prefix() {
+        // An Update for a /24 whose body stops after two prefix bytes.
+        let buf = [8, 12, 1, 0, 24, 0, 0, 0, 0, 0, 0, 0, 10, 0];
+        assert!(matches!(
+            parse_all_borrowed(&buf),
+            Err(TlvError::Truncated { tlv_type: 8 })
+        ));
+        assert!(matches!(
+            parse_all_borrowed(&[4, 6, 0]),
+            Err(TlvError::LengthExceedsBuffer)
+        ));
+    }
+
+    #[test]
+    fn display_renders_prefixes_by_ae() {
+        let update = |ae, plen, omitted, prefix: Vec<u8>| Tlv::Update {
+            ae,
+            flags: 0,
+            plen,
+            omitted,
+            interval: 400,
+            seqno: 1,
+            metric: 0xffff,
+            prefix,
+            sub_tlvs: Vec::new(),
+        };
+        assert_eq!(
+            update(2, 48, 0, vec![0x20, 0x01, 0x0d, 0xb8, 0, 1]).to_string(),
+            "Update 2001:db8:1::/48 metric=65535 seqno=1 interval=400ms"
+        );
+        assert_eq!(
+            update(0, 0, 0, Vec::new()).to_string(),
+            "Update any metric=65535 seqno=1 interval=400ms"
+        );
+        assert!(
+            update(1, 24, 2, vec![7])
+                .to_string()
+                .ends_with(" omitted=2")
+        );
+        assert!(
+            update(9, 8, 0, vec![7])
+                .to_string()
+                .starts_with("Update ae=9 plen=8")
+        );
+
+        let ihu = Tlv::Ihu {
+            ae: 1,
+            rxcost: 256,
+            interval: 4000,
+            addr: Some("192.0.2.1".parse().unwrap()),
+            sub_tlvs: vec![SubTlv::Timestamp {
+                origin: 1,
+                receive: Some(2),
+            }],
+        };
+        assert_eq!(
+            ihu.to_string(),
+            "IHU rxcost=256 interval=4000ms addr=192.0.2.1 sub_tlvs=1"
+        );
+    }
+
+    #[test]
+    fn ae_family_mapping() {
+        assert_eq!(ae_family(0), None);
+        assert_eq!(ae_family(1), Some(AddressFamily::V4));
+        assert_eq!(ae_family(2), Some(AddressFamily::V6));
+        assert_eq!(ae_family(3), Some(AddressFamily::V6));
+        assert_eq!(ae_family(4), None);
+    }
 }
@@ -0,0 +1,176 @@
+// src/trace.rs
+//! Minimal pcap writer for tracing the datagrams a node sends and receives.
+//!
+//! Each datagram is wrapped in made-up IP and UDP headers and written with
+//! the BSD loopback ("null") link type, so Wireshark's Babel dissector
+//! picks it up by port.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// `LINKTYPE_NULL`: a 4-byte address family in front of the IP header.
+pub const LINKTYPE_NULL: u32 = 0;
+
+/// Magic number of a pcap file with microsecond timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Largest record we write; also the snaplen in the global header.
+const SNAPLEN: u32 = 65535;
+
+/// Address family values of the null header. Wireshark accepts any of
+/// the BSDs' AF_INET6 values; this is NetBSD's and OpenBSD's.
+const AF_INET: u32 = 2;
+const AF_INET6: u32 = 24;
+
+const UDP_PROTO: u8 = 17;
+
+/// Appends datagrams to a pcap file.
+///
+/// Writes go straight to the file (no buffering), so a trace is complete
+/// up to the last packet even if the process dies.
+#[derive(Debug)]
+pub struct PcapWriter {
+    file: File,
+}
+
+impl PcapWriter {
+    /// Open `path` for appending, writing the pcap global header if the
+    /// file is new or empty.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            let mut header = Vec::with_capacity(24);
+            header.write_u32::<LittleEndian>(PCAP_MAGIC)?;
+            header.write_u16::<LittleEndian>(2)?; // version 2.4
+            header.write_u16::<LittleEndian>(4)?;
+            header.write_i32::<LittleEndian>(0)?; // timezone offset
+            header.write_u32::<LittleEndian>(0)?; // timestamp accuracy
+            header.write_u32::<LittleEndian>(SNAPLEN)?;
+            header.write_u32::<LittleEndian>(LINKTYPE_NULL)?;
+            (&file).write_all(&header)?;
+        }
+        Ok(PcapWriter { file })
+    }
+
+    /// Record one UDP datagram `payload` from `src` to `dst`, stamped with
+    /// the current time.
+    pub fn write_datagram(
+        &self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let frame = udp_frame(src, dst, payload);
+        let len = frame.len().min(SNAPLEN as usize);
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut record = Vec::with_capacity(16 + len);
+        record.write_u32::<LittleEndian>(ts.as_secs() as u32)?;
+        record.write_u32::<LittleEndian>(ts.subsec_micros())?;
+        record.write_u32::<LittleEndian>(len as u32)?;
+        record.write_u32::<LittleEndian>(frame.len() as u32)?;
+        record.extend_from_slice(&frame[..len]);
+        // One write per record, so records from several writers on the
+        // same file don't interleave.
+        (&self.file).write_all(&record)
+    }
+}
+
+/// Null header, IP header and UDP header in front of `payload`.
+///
+/// Mixed families (e.g. an IPv4 peer on a dual-stack socket) are written
+/// as IPv6 with the IPv4 address mapped.
+fn udp_frame(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + payload.len();
+    let mut frame = Vec::with_capacity(4 + 40 + udp_len);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            frame.extend(AF_INET.to_le_bytes());
+            let mut ip = [0u8; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            ip[8] = 1; // TTL, as Babel sends with
+            ip[9] = UDP_PROTO;
+            ip[12..16].copy_from_slice(&s.octets());
+            ip[16..20].copy_from_slice(&d.octets());
+            let checksum = ipv4_checksum(&ip);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            frame.extend(ip);
+        }
+        (s, d) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+                IpAddr::V6(v6) => v6,
+            };
+            frame.extend(AF_INET6.to_le_bytes());
+            let mut ip = [0u8; 40];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+            ip[6] = UDP_PROTO;
+            ip[7] = 1; // hop limit
+            ip[8..24].copy_from_slice(&to_v6(s).octets());
+            ip[24..40].copy_from_slice(&to_v6(d).octets());
+            frame.extend(ip);
+        }
+    }
+
+    frame.extend(src.port().to_be_bytes());
+    frame.extend(dst.port().to_be_bytes());
+    frame.extend((udp_len as u16).to_be_bytes());
+    // No UDP checksum: optional over IPv4, and Wireshark doesn't check it
+    // by default over IPv6.
+    frame.extend([0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Internet checksum of an IPv4 header whose checksum field is zero.
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], w[1]]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_frames_are_checksummed() {
+        let src: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let dst: SocketAddr = "224.0.0.111:6696".parse().unwrap();
+        let frame = udp_frame(src, dst, &[42, 2, 0, 0]);
+
+        assert_eq!(&frame[..4], &AF_INET.to_le_bytes());
+        assert_eq!(frame.len(), 4 + 20 + 8 + 4);
+        assert_eq!(ipv4_checksum(&frame[4..24]), 0);
+        assert_eq!(&frame[24..28], &[0x1a, 0x28, 0x1a, 0x28]);
+    }
+
+    #[test]
+    fn mixed_families_are_written_as_ipv6() {
+        let src: SocketAddr = "192.0.2.1:6696".parse().unwrap();
+        let dst: SocketAddr = "[ff02::1:6]:6696".parse().unwrap();
+        let frame = udp_frame(src, dst, &[]);
+
+        assert_eq!(&frame[..4], &AF_INET6.to_le_bytes());
+        assert_eq!(frame[4] >> 4, 6);
+        assert_eq!(
+            &frame[4 + 8..4 + 24],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 0, 2, 1]
+        );
+    }
+}